@@ -3,6 +3,8 @@ use clap::{App, Arg};
 pub struct CommandLineConfig {
     pub rom_path: String,
     pub sram_path: String,
+    pub rewind_buffer_size: usize,
+    pub strict: bool,
 }
 
 pub fn parse_args() -> CommandLineConfig {
@@ -18,12 +20,21 @@ pub fn parse_args() -> CommandLineConfig {
               .help("Path to an SRAM")
               .short("s")
               .long("sram")
+        ).arg(Arg::with_name("REWIND_BUFFER_SIZE")
+              .help("Memory budget for the rewind buffer in megabytes (0 disables rewinding)")
+              .long("rewind-buffer-size")
+              .takes_value(true)
+              .default_value("64")
+        ).arg(Arg::with_name("STRICT")
+              .help("Also report accesses to mirrored addresses as unmapped, stopping in the debugger (accesses to truly unmapped addresses are always reported)")
+              .long("strict")
         );
 
     let matches = app.get_matches();
     //
     // unwrap is safe here becuase clap guarantees that required arguments are never None
     let rom_path = matches.value_of("ROM").unwrap();
+    let rewind_buffer_size = value_t!(matches, "REWIND_BUFFER_SIZE", usize).unwrap_or_else(|e| e.exit());
 
     CommandLineConfig {
         rom_path: rom_path.into(),
@@ -31,5 +42,7 @@ pub fn parse_args() -> CommandLineConfig {
             Some(v) => v.into(),
             None => rom_path.replace(".vb", ".srm")
         },
+        rewind_buffer_size: rewind_buffer_size * 1024 * 1024,
+        strict: matches.is_present("STRICT"),
     }
 }
@@ -1,4 +1,4 @@
-use combine::{choice, eof, many1, optional, Parser, parser, try, value};
+use combine::{any, choice, eof, many1, optional, Parser, parser, try, value};
 use combine::char::{alpha_num, digit, hex_digit, space, spaces, string};
 use combine::primitives::{ParseResult, Stream};
 
@@ -14,6 +14,7 @@ pub enum Command {
     Goto(u32),
     ShowMem(Option<u32>),
     Disassemble(u32),
+    Assemble(String),
     Label,
     AddLabel(String, u32),
     RemoveLabel(String),
@@ -77,6 +78,13 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|(_, count)| Command::Disassemble(count.unwrap_or(4)))
         .boxed();
 
+    let assemble =
+        (choice([try(string("assemble")), try(string("a"))]),
+            space(),
+            many1::<String, _>(any()))
+        .map(|(_, _, source)| Command::Assemble(source))
+        .boxed();
+
     let label =
         choice([try(string("label")), try(string("l"))])
         .map(|_| Command::Label)
@@ -152,6 +160,7 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
             goto,
             show_mem,
             disassemble,
+            assemble,
             label,
             add_label,
             remove_label,
@@ -6,20 +6,28 @@ use rustual_boy_core::sinks::{AudioFrame, Sink, SinkRef, VideoFrame};
 use rustual_boy_core::time_source::TimeSource;
 use rustual_boy_core::rom::Rom;
 use rustual_boy_core::sram::Sram;
+use rustual_boy_core::assembler::Assembler;
+use rustual_boy_core::bus::Bus;
 use rustual_boy_core::instruction::*;
 use rustual_boy_core::game_pad::Button;
-use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::hooks::MemoryRegion;
+use rustual_boy_core::virtual_boy::{EmulationError, StepOutcome, StopReason, VirtualBoy};
 
-use rustual_boy_middleware::{Anaglyphizer, GammaAdjustSink, MostRecentSink};
+use rustual_boy_middleware::{Anaglyphizer, GammaAdjustSink, MostRecentSink, RewindBuffer};
 
 use std::time;
 use std::thread::{self, JoinHandle};
-use std::io::{stdin, stdout, Write};
-use std::collections::{HashSet, HashMap, VecDeque};
+use std::io::{self, stdin, stdout, Read, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{channel, Receiver};
 
 const CPU_CYCLE_TIME_NS: u64 = 50;
 
+// Slot n is loaded with F<n> and saved with Shift+F<n>
+const SAVE_STATE_SLOT_KEYS: [Key; 9] = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9];
+
 struct SimpleAudioFrameSink {
     inner: VecDeque<AudioFrame>,
 }
@@ -42,8 +50,6 @@ pub struct Emulator {
     pub virtual_boy: VirtualBoy,
     mode: Mode,
 
-    breakpoints: HashSet<u32>,
-
     labels: HashMap<String, u32>,
     cursor: u32,
     last_command: Option<Command>,
@@ -57,10 +63,15 @@ pub struct Emulator {
     time_source_start_time_ns: u64,
 
     emulated_cycles: u64,
+
+    save_state_base_path: PathBuf,
+
+    rewind_buffer: RewindBuffer,
+    is_rewinding: bool,
 }
 
 impl Emulator {
-    pub fn new(rom: Rom, sram: Sram, audio_buffer_sink: Box<SinkRef<[AudioFrame]>>, time_source: Box<TimeSource>) -> Emulator {
+    pub fn new(rom: Rom, sram: Sram, save_state_base_path: PathBuf, rewind_buffer_size: usize, audio_buffer_sink: Box<SinkRef<[AudioFrame]>>, time_source: Box<TimeSource>) -> Emulator {
         let (stdin_sender, stdin_receiver) = channel();
         let stdin_thread = thread::spawn(move || {
             loop {
@@ -79,8 +90,6 @@ impl Emulator {
             virtual_boy: VirtualBoy::new(rom, sram),
             mode: Mode::Running,
 
-            breakpoints: HashSet::new(),
-
             labels: HashMap::new(),
             cursor: 0,
             last_command: None,
@@ -94,6 +103,11 @@ impl Emulator {
             time_source_start_time_ns: 0,
 
             emulated_cycles: 0,
+
+            save_state_base_path: save_state_base_path,
+
+            rewind_buffer: RewindBuffer::new(rewind_buffer_size),
+            is_rewinding: false,
         }
     }
 
@@ -118,18 +132,27 @@ impl Emulator {
 
             match self.mode {
                 Mode::Running => {
-                    let mut start_debugger = false;
-
-                    while self.emulated_cycles < target_emulated_cycles && !start_debugger {
-                        let (_, trigger_watchpoint) = self.step(&mut video_frame_sink, &mut audio_frame_sink);
-                        if trigger_watchpoint || (self.breakpoints.len() != 0 && self.breakpoints.contains(&self.virtual_boy.cpu.reg_pc())) {
-                            start_debugger = true;
+                    if self.emulated_cycles < target_emulated_cycles {
+                        match self.virtual_boy.run_cycles(target_emulated_cycles - self.emulated_cycles) {
+                            Ok(result) => {
+                                self.emulated_cycles += result.cycles;
+
+                                if let Some(frame) = result.video_frame {
+                                    video_frame_sink.append(frame);
+                                }
+                                audio_frame_sink.inner.extend(result.audio_frames);
+
+                                match result.stop_reason {
+                                    StopReason::Breakpoint | StopReason::Watchpoint => self.start_debugger(),
+                                    _ => {}
+                                }
+                            }
+                            Err(e) => {
+                                println!("{}", e);
+                                self.start_debugger();
+                            }
                         }
                     }
-
-                    if start_debugger {
-                        self.start_debugger();
-                    }
                 }
                 Mode::Debugging => {
                     if self.run_debugger_commands(&mut video_frame_sink, &mut audio_frame_sink) {
@@ -148,24 +171,29 @@ impl Emulator {
                     // We only want to update the key state when a frame is actually pushed
                     // Otherwise some games break.
                     self.read_input_keys();
+                    self.read_save_state_keys();
+                    self.update_rewind();
                     if self.window.is_key_pressed(Key::F12, KeyRepeat::No) {
                         self.start_debugger();
                     }
                 }
             }
 
-            self.audio_buffer_sink.append(audio_frame_sink.inner.as_slices().0);
+            // Audio produced while rewinding is just noise, so drop it
+            if !self.is_rewinding {
+                self.audio_buffer_sink.append(audio_frame_sink.inner.as_slices().0);
+            }
 
             thread::sleep(time::Duration::from_millis(3));
         }
     }
 
-    fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
-        let ret = self.virtual_boy.step(video_frame_sink, audio_frame_sink);
+    fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<StepOutcome, EmulationError> {
+        let ret = self.virtual_boy.step(video_frame_sink, audio_frame_sink)?;
 
-        self.emulated_cycles += ret.0 as u64;
+        self.emulated_cycles += ret.cycles as u64;
 
-        ret
+        Ok(ret)
     }
 
     fn read_input_keys(&mut self) {
@@ -185,6 +213,69 @@ impl Emulator {
         self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::RightDPadRight, self.window.is_key_down(Key::L));
     }
 
+    fn read_save_state_keys(&mut self) {
+        let shift_down = self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+
+        for (index, &key) in SAVE_STATE_SLOT_KEYS.iter().enumerate() {
+            if self.window.is_key_pressed(key, KeyRepeat::No) {
+                let slot = index + 1;
+                if shift_down {
+                    self.save_state_slot(slot);
+                } else {
+                    self.load_state_slot(slot);
+                }
+            }
+        }
+    }
+
+    // Snapshots are taken every displayed frame; holding backspace steps back one snapshot per frame
+    fn update_rewind(&mut self) {
+        self.is_rewinding = self.window.is_key_down(Key::Backspace);
+
+        if self.is_rewinding {
+            if let Some(state) = self.rewind_buffer.rewind() {
+                if let Err(err) = self.virtual_boy.load_state(state) {
+                    println!("Couldn't rewind: {}", err);
+                }
+            }
+        } else {
+            self.rewind_buffer.push(self.virtual_boy.save_state());
+        }
+    }
+
+    fn save_state_slot(&mut self, slot: usize) {
+        let path = self.save_state_slot_path(slot);
+        let state = self.virtual_boy.save_state();
+
+        match File::create(&path).and_then(|mut file| file.write_all(&state)) {
+            Ok(_) => println!("Saved state to slot {} ({})", slot, path.display()),
+            Err(err) => println!("Couldn't save state to slot {} ({}): {}", slot, path.display(), err),
+        }
+    }
+
+    fn load_state_slot(&mut self, slot: usize) {
+        let path = self.save_state_slot_path(slot);
+
+        let state = match read_file(&path) {
+            Ok(state) => state,
+            Err(err) => {
+                println!("Couldn't read state from slot {} ({}): {}", slot, path.display(), err);
+                return;
+            }
+        };
+
+        match self.virtual_boy.load_state(&state) {
+            Ok(_) => println!("Loaded state from slot {} ({})", slot, path.display()),
+            Err(err) => println!("Couldn't load state from slot {} ({}): {}", slot, path.display(), err),
+        }
+    }
+
+    fn save_state_slot_path(&self, slot: usize) -> PathBuf {
+        let mut path = self.save_state_base_path.clone().into_os_string();
+        path.push(format!(".ss{}", slot));
+        path.into()
+    }
+
     fn start_debugger(&mut self) {
         self.mode = Mode::Debugging;
 
@@ -213,7 +304,10 @@ impl Emulator {
                     println!("psw: 0x{:08x}", self.virtual_boy.cpu.reg_psw());
                     println!("eipc: 0x{:08x}", self.virtual_boy.cpu.reg_eipc());
                     println!("eipsw: 0x{:08x}", self.virtual_boy.cpu.reg_eipsw());
+                    println!("fepc: 0x{:08x}", self.virtual_boy.cpu.reg_fepc());
+                    println!("fepsw: 0x{:08x}", self.virtual_boy.cpu.reg_fepsw());
                     println!("ecr: 0x{:08x}", self.virtual_boy.cpu.reg_ecr());
+                    println!("adtre: 0x{:08x}", self.virtual_boy.cpu.reg_adtre());
                 }
                 Ok(Command::ShowCpuCache) => {
                     println!("CPU Instruction Cached enable: {}", self.virtual_boy.cpu.cache.is_enabled());
@@ -226,9 +320,13 @@ impl Emulator {
                 },
                 Ok(Command::Step(count)) => {
                     for _ in 0..count {
-                        self.step(video_frame_sink, audio_frame_sink);
+                        let result = self.step(video_frame_sink, audio_frame_sink);
                         self.cursor = self.virtual_boy.cpu.reg_pc();
                         self.disassemble_instruction();
+                        if let Err(e) = result {
+                            println!("{}", e);
+                            break;
+                        }
                     }
                 }
                 Ok(Command::Continue) => {
@@ -259,12 +357,42 @@ impl Emulator {
                         }
                         println!();
                     }
+                    // Reads that hit nothing shouldn't be reported as the emulated program's
+                    self.virtual_boy.interconnect.take_unmapped_access();
                 }
                 Ok(Command::Disassemble(count)) => {
                     for _ in 0..count {
                         self.cursor = self.disassemble_instruction();
                     }
                 }
+                Ok(Command::Assemble(ref source)) => {
+                    let mut assembler = Assembler::new(self.cursor);
+                    for (name, addr) in self.labels.iter() {
+                        assembler.define_label(name, *addr);
+                    }
+                    match assembler.assemble(source) {
+                        Ok(segments) => {
+                            // Writes to ROM are dropped by the bus, so only code in RAM can be patched.
+                            //  Nothing is written unless all of it can be.
+                            let read_only_addr = segments.iter()
+                                .flat_map(|segment| (0..segment.bytes.len()).map(move |i| segment.addr.wrapping_add(i as u32)))
+                                .find(|&addr| !is_writable(addr));
+                            if let Some(addr) = read_only_addr {
+                                println!("Can't assemble to 0x{:08x} ({:?} isn't writable)", addr, MemoryRegion::from_addr(addr));
+                            } else {
+                                for segment in segments.iter() {
+                                    for (i, byte) in segment.bytes.iter().enumerate() {
+                                        self.virtual_boy.interconnect.write_byte(segment.addr.wrapping_add(i as u32), *byte);
+                                    }
+                                    self.cursor = segment.addr.wrapping_add(segment.bytes.len() as u32);
+                                }
+                                // Likewise for writes that hit an unmapped register
+                                self.virtual_boy.interconnect.take_unmapped_access();
+                            }
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
                 Ok(Command::Label) => {
                     for (name, addr) in self.labels.iter() {
                         println!(".{}: 0x{:08x}", name, addr);
@@ -279,15 +407,15 @@ impl Emulator {
                     }
                 }
                 Ok(Command::Breakpoint) => {
-                    for addr in self.breakpoints.iter() {
+                    for addr in self.virtual_boy.cpu.breakpoints.iter() {
                         println!("* 0x{:08x}", addr);
                     }
                 }
                 Ok(Command::AddBreakpoint(addr)) => {
-                    self.breakpoints.insert(addr);
+                    self.virtual_boy.cpu.breakpoints.insert(addr);
                 }
                 Ok(Command::RemoveBreakpoint(addr)) => {
-                    if !self.breakpoints.remove(&addr) {
+                    if !self.virtual_boy.cpu.breakpoints.remove(&addr) {
                         println!("Breakpoint at 0x{:08x} does not exist", addr);
                     }
                 }
@@ -331,7 +459,7 @@ impl Emulator {
     fn disassemble_instruction(&mut self) -> u32 {
         self.print_labels_at_cursor();
 
-        if self.breakpoints.contains(&self.cursor) {
+        if self.virtual_boy.cpu.breakpoints.contains(&self.cursor) {
             print!("* ");
         } else {
             print!("  ");
@@ -343,10 +471,7 @@ impl Emulator {
         let mut next_cursor = self.cursor.wrapping_add(2);
         print!("{:02x}{:02x}", first_halfword & 0xff, first_halfword >> 8);
 
-        let opcode = Opcode::from_halfword(first_halfword);
-        let instruction_format = opcode.instruction_format();
-
-        let second_halfword = if instruction_format.has_second_halfword() {
+        let second_halfword = if is_long_instruction(first_halfword) {
             let second_halfword = self.virtual_boy.interconnect.read_halfword(next_cursor);
             print!("{:02x}{:02x}", second_halfword & 0xff, second_halfword >> 8);
             next_cursor = next_cursor.wrapping_add(2);
@@ -355,76 +480,15 @@ impl Emulator {
             print!("    ");
             0
         };
+        // The debugger's own reads aren't the emulated program's
+        self.virtual_boy.interconnect.take_unmapped_access();
 
         print!("    ");
 
-        match instruction_format {
-            InstructionFormat::I => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                if opcode == Opcode::Jmp {
-                    println!("jmp [r{}]", reg1);
-                } else {
-                    println!("{} r{}, r{}", opcode, reg1, reg2);
-                }
-            }
-            InstructionFormat::II => {
-                let imm5 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                match opcode {
-                    Opcode::BitString => {
-                        let bit_string_op = opcode.bit_string_op(imm5);
-
-                        println!("{}", bit_string_op);
-                    }
-                    Opcode::Cli | Opcode::Reti | Opcode::Halt | Opcode::Sei => println!("{}", opcode),
-                    Opcode::Ldsr => println!("{} r{}, {}", opcode, reg2, opcode.system_register(imm5)),
-                    Opcode::Stsr => println!("{} {}, r{}", opcode, opcode.system_register(imm5), reg2),
-                    Opcode::MovImm | Opcode::AddImm5 | Opcode::CmpImm => println!("{} {}, r{}", opcode, (((imm5 as i32) << 27) >> 27), reg2),
-                    _ => println!("{} {}, r{}", opcode, imm5, reg2)
-                }
-            }
-            InstructionFormat::III => {
-                let disp9 = first_halfword & 0x01ff;
-                let disp = (disp9 as u32) | if disp9 & 0x0100 == 0 { 0x00000000 } else { 0xfffffe00 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {:#x} (0x{:08x})", opcode, disp9, target);
-            }
-            InstructionFormat::IV => {
-                let disp26 = (((first_halfword as u32) & 0x03ff) << 16) | (second_halfword as u32);
-                let disp = disp26 | if disp26 & 0x02000000 == 0 { 0x00000000 } else { 0xfc000000 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {} (0x{:08x})", opcode, disp26 as i32, target);
-            }
-            InstructionFormat::V => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let imm16 = second_halfword;
-
-                println!("{} {:#x}, r{}, r{}", opcode, imm16, reg1, reg2);
-            }
-            InstructionFormat::VI => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let disp16 = second_halfword as i16;
-
-                match opcode {
-                    Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Outb | Opcode::Outh | Opcode::Outw => println!("{} r{}, {}[r{}]", opcode, reg2, disp16, reg1),
-                    _ => println!("{} {}[r{}], r{}", opcode, disp16, reg1, reg2),
-                }
-            }
-            InstructionFormat::VII => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let subop_bits = second_halfword >> 10;
-
-                let subop = opcode.subop(subop_bits);
-
-                println!("{} r{}, r{}", subop, reg1, reg2);
-            }
+        let instruction = Instruction::decode(first_halfword, second_halfword);
+        match instruction.branch_target(self.cursor) {
+            Some(target) => println!("{} (0x{:08x})", instruction, target),
+            _ => println!("{}", instruction),
         }
 
         next_cursor
@@ -437,8 +501,23 @@ impl Emulator {
     }
 }
 
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut vec = Vec::new();
+    file.read_to_end(&mut vec)?;
+    Ok(vec)
+}
+
 fn read_stdin() -> String {
     let mut input = String::new();
     stdin().read_line(&mut input).unwrap();
     input.trim().into()
 }
+
+// Whether the debugger can patch memory at addr; everything but the cartridge ROM and unused space
+fn is_writable(addr: u32) -> bool {
+    match MemoryRegion::from_addr(addr) {
+        MemoryRegion::GamePakRom | MemoryRegion::GamePakExpansion | MemoryRegion::Unused => false,
+        _ => true,
+    }
+}
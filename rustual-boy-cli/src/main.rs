@@ -28,12 +28,21 @@ use rustual_boy_core::vsu::*;
 use cpal_driver::*;
 use emulator::*;
 
+use std::path::{Path, PathBuf};
+use std::process;
+
 fn main() {
     let config = argparse::parse_args();
 
     logln!("Loading ROM file {}", config.rom_path);
 
-    let rom = Rom::load(&config.rom_path).unwrap();
+    let rom = match Rom::load(&config.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("Couldn't load ROM file {}: {}", config.rom_path, err);
+            process::exit(1);
+        }
+    };
 
     log!("ROM size: ");
     if rom.size() >= 1024 * 1024 {
@@ -43,9 +52,19 @@ fn main() {
     }
 
     logln!("Header info:");
-    logln!(" name: \"{}\"", rom.name().unwrap());
-    logln!(" maker code: \"{}\"", rom.maker_code().unwrap());
-    logln!(" game code: \"{}\"", rom.game_code().unwrap());
+    // Homebrew and damaged ROMs don't always have a valid header, which is no reason not to run them
+    match rom.name() {
+        Ok(name) => logln!(" name: \"{}\"", name),
+        Err(err) => logln!(" name: invalid ({})", err),
+    }
+    match rom.maker_code() {
+        Ok(maker_code) => logln!(" maker code: \"{}\"", maker_code),
+        Err(err) => logln!(" maker code: invalid ({})", err),
+    }
+    match rom.game_code() {
+        Ok(game_code) => logln!(" game code: \"{}\"", game_code),
+        Err(err) => logln!(" game code: invalid ({})", err),
+    }
     logln!(" game version: 1.{:#02}", rom.game_version_byte());
 
     logln!("Attempting to load SRAM file: {}", config.sram_path);
@@ -67,11 +86,32 @@ fn main() {
     let audio_buffer_sink = audio_driver.sink();
     let time_source = audio_driver.time_source();
 
-    let mut emulator = Emulator::new(rom, sram, audio_buffer_sink, time_source);
+    let save_state_base_path = save_state_base_path(&config.rom_path, &rom);
+
+    let mut emulator = Emulator::new(rom, sram, save_state_base_path, config.rewind_buffer_size, audio_buffer_sink, time_source);
+    emulator.virtual_boy.interconnect.set_strict_mode(config.strict);
     emulator.run();
 
-    if emulator.virtual_boy.interconnect.sram.size() > 0 {
+    if emulator.virtual_boy.interconnect.sram().size() > 0 {
         logln!("SRAM used, saving to {}", config.sram_path);
-        emulator.virtual_boy.interconnect.sram.save(config.sram_path).unwrap();
+        if let Err(err) = emulator.virtual_boy.interconnect.sram().save(&config.sram_path) {
+            eprintln!("Couldn't save SRAM file {}: {}", config.sram_path, err);
+        }
     }
 }
+
+// Save states live next to the ROM and are keyed by its game code, falling back to the
+//  ROM's file name for ROMs (typically homebrew) without a usable one.
+fn save_state_base_path(rom_path: &str, rom: &Rom) -> PathBuf {
+    let rom_path = Path::new(rom_path);
+
+    let game_code = rom.game_code().unwrap_or_default();
+    let game_code = game_code.trim();
+    let name = if !game_code.is_empty() && game_code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        game_code.to_string()
+    } else {
+        rom_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "rom".into())
+    };
+
+    rom_path.with_file_name(name)
+}
@@ -0,0 +1,577 @@
+use instruction::*;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Assembles V810 source into machine code. The syntax is the one Instruction's Display impl
+//  prints, so disassembled code can be edited and reassembled as-is:
+//
+//      start:
+//          movhi 0x500, r0, r6
+//      loop:
+//          ld.w -4[r6], r7         ; comments run to the end of the line
+//          add -1, r7
+//          bnz loop
+//          .org 0x05000100
+//          .word 0xdeadbeef, loop
+//          .halfword 0x1234
+//
+// Branch and jump operands are either labels or raw displacements (as disassembled). Numbers are
+//  decimal or 0x-prefixed hex, and may be negative. Code starts at the origin given to the
+//  assembler, and .org moves it, starting a new segment.
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u32,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblerError {}
+
+pub fn assemble(source: &str, origin: u32) -> Result<Vec<Segment>, AssemblerError> {
+    Assembler::new(origin).assemble(source)
+}
+
+pub struct Assembler {
+    origin: u32,
+    labels: HashMap<String, u32>,
+}
+
+impl Assembler {
+    pub fn new(origin: u32) -> Assembler {
+        Assembler {
+            origin: origin,
+            labels: HashMap::new(),
+        }
+    }
+
+    // Makes a label available to the source without it having to be defined there
+    pub fn define_label(&mut self, name: &str, addr: u32) {
+        self.labels.insert(name.to_lowercase(), addr);
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Vec<Segment>, AssemblerError> {
+        // Every statement's size is known from the statement alone, so the first pass just collects
+        //  label addresses, and the second one encodes everything with all labels known
+        let mut labels = self.labels.clone();
+        let mut defined_labels = Vec::new();
+        let mut statements = Vec::new();
+        let mut pc = self.origin;
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| AssemblerError { line: line_number, message: message };
+
+            let (line_labels, statement) = parse_line(line).map_err(&error)?;
+            for name in line_labels {
+                let name = name.to_lowercase();
+                if defined_labels.contains(&name) {
+                    return Err(error(format!("Label {} is already defined", name)));
+                }
+                labels.insert(name.clone(), pc);
+                defined_labels.push(name);
+            }
+
+            if let Some(statement) = statement {
+                // Labels defined further down aren't known yet; any address will do for sizing
+                let bytes = statement.encode(pc, &|_| Some(pc)).map_err(&error)?;
+                pc = match statement {
+                    Statement::Org(addr) => addr,
+                    _ => pc.wrapping_add(bytes.len() as u32),
+                };
+                statements.push((line_number, statement));
+            }
+        }
+
+        let mut segments = Vec::new();
+        let mut segment = Segment {
+            addr: self.origin,
+            bytes: Vec::new(),
+        };
+        for (line_number, statement) in statements {
+            let pc = segment.addr.wrapping_add(segment.bytes.len() as u32);
+            let bytes = statement.encode(pc, &|name| labels.get(&name.to_lowercase()).cloned())
+                .map_err(|message| AssemblerError { line: line_number, message: message })?;
+
+            if let Statement::Org(addr) = statement {
+                if !segment.bytes.is_empty() {
+                    segments.push(segment);
+                }
+                segment = Segment {
+                    addr: addr,
+                    bytes: Vec::new(),
+                };
+            } else {
+                segment.bytes.extend(bytes);
+            }
+        }
+        if !segment.bytes.is_empty() {
+            segments.push(segment);
+        }
+
+        Ok(segments)
+    }
+}
+
+enum Statement<'a> {
+    Org(u32),
+    Halfwords(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Instruction(String, Vec<&'a str>),
+}
+
+impl<'a> Statement<'a> {
+    fn encode(&self, pc: u32, labels: &Fn(&str) -> Option<u32>) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        match self {
+            &Statement::Org(_) => (),
+            &Statement::Halfwords(ref values) => {
+                for value in values.iter() {
+                    let value = in_range(resolve(value, labels)?, -0x8000, 0xffff)?;
+                    push_halfword(&mut bytes, value as u16);
+                }
+            }
+            &Statement::Words(ref values) => {
+                for value in values.iter() {
+                    let value = in_range(resolve(value, labels)?, -0x80000000, 0xffffffff)?;
+                    push_halfword(&mut bytes, value as u16);
+                    push_halfword(&mut bytes, (value >> 16) as u16);
+                }
+            }
+            &Statement::Instruction(ref mnemonic, ref operands) => {
+                let instruction = parse_instruction(mnemonic, operands, pc, labels)?;
+                let (first_halfword, second_halfword) = instruction.encode();
+                push_halfword(&mut bytes, first_halfword);
+                if instruction.is_long() {
+                    push_halfword(&mut bytes, second_halfword);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+fn push_halfword(bytes: &mut Vec<u8>, halfword: u16) {
+    bytes.push(halfword as u8);
+    bytes.push((halfword >> 8) as u8);
+}
+
+fn parse_line<'a>(line: &'a str) -> Result<(Vec<&'a str>, Option<Statement<'a>>), String> {
+    let mut line = match line.find(';') {
+        Some(index) => &line[..index],
+        _ => line,
+    }.trim();
+
+    let mut labels = Vec::new();
+    while let Some(index) = line.find(':') {
+        let name = line[..index].trim();
+        if !is_label_name(name) {
+            return Err(format!("Invalid label name: {}", name));
+        }
+        labels.push(name);
+        line = line[index + 1..].trim();
+    }
+
+    if line.is_empty() {
+        return Ok((labels, None));
+    }
+
+    let (mnemonic, operands) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        _ => (line, ""),
+    };
+    let operands = if operands.is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(|operand| operand.trim()).collect()
+    };
+
+    let mnemonic = mnemonic.to_lowercase();
+    let statement = match mnemonic.as_str() {
+        ".org" => {
+            if operands.len() != 1 {
+                return Err(".org takes an address".into());
+            }
+            let addr = parse_number(operands[0]).ok_or_else(|| format!("Invalid address: {}", operands[0]))?;
+            Statement::Org(in_range(addr, 0, 0xffffffff)? as u32)
+        }
+        ".halfword" => Statement::Halfwords(operands),
+        ".word" => Statement::Words(operands),
+        _ if mnemonic.starts_with('.') => return Err(format!("Unknown directive: {}", mnemonic)),
+        _ => Statement::Instruction(mnemonic, operands),
+    };
+
+    Ok((labels, Some(statement)))
+}
+
+fn parse_instruction(mnemonic: &str, operands: &[&str], pc: u32, labels: &Fn(&str) -> Option<u32>) -> Result<Instruction, String> {
+    let expect_operands = |count: usize| if operands.len() == count {
+        Ok(())
+    } else {
+        Err(format!("{} takes {} operand(s), found {}", mnemonic, count, operands.len()))
+    };
+
+    // Branch and jump targets, as a displacement from this instruction
+    let target = |operand: &str, bits: u32| -> Result<i32, String> {
+        let disp = match parse_number(operand) {
+            Some(disp) => disp,
+            _ => (resolve(operand, labels)? as u32).wrapping_sub(pc) as i32 as i64,
+        };
+        if disp & 1 != 0 {
+            return Err(format!("Displacement must be even: {}", disp));
+        }
+        Ok(in_range(disp, -(1 << (bits - 1)), (1 << (bits - 1)) - 1)? as i32)
+    };
+
+    let instruction = match mnemonic {
+        "mov" | "add" | "cmp" | "shl" | "shr" | "sar" => {
+            expect_operands(2)?;
+            let reg2 = parse_reg(operands[1])?;
+            if let Ok(reg1) = parse_reg(operands[0]) {
+                match mnemonic {
+                    "mov" => Instruction::MovReg { reg1: reg1, reg2: reg2 },
+                    "add" => Instruction::AddReg { reg1: reg1, reg2: reg2 },
+                    "cmp" => Instruction::CmpReg { reg1: reg1, reg2: reg2 },
+                    "shl" => Instruction::ShlReg { reg1: reg1, reg2: reg2 },
+                    "shr" => Instruction::ShrReg { reg1: reg1, reg2: reg2 },
+                    _ => Instruction::SarReg { reg1: reg1, reg2: reg2 },
+                }
+            } else {
+                let imm5 = parse_value(operands[0])?;
+                match mnemonic {
+                    "mov" => Instruction::MovImm { imm5: in_range(imm5, -16, 15)? as i32, reg2: reg2 },
+                    "add" => Instruction::AddImm5 { imm5: in_range(imm5, -16, 15)? as i32, reg2: reg2 },
+                    "cmp" => Instruction::CmpImm { imm5: in_range(imm5, -16, 15)? as i32, reg2: reg2 },
+                    "shl" => Instruction::ShlImm { imm5: in_range(imm5, 0, 31)? as u32, reg2: reg2 },
+                    "shr" => Instruction::ShrImm { imm5: in_range(imm5, 0, 31)? as u32, reg2: reg2 },
+                    _ => Instruction::SarImm { imm5: in_range(imm5, 0, 31)? as u32, reg2: reg2 },
+                }
+            }
+        }
+        "sub" | "mul" | "div" | "mulu" | "divu" | "or" | "and" | "xor" | "not" => {
+            expect_operands(2)?;
+            let reg1 = parse_reg(operands[0])?;
+            let reg2 = parse_reg(operands[1])?;
+            match mnemonic {
+                "sub" => Instruction::Sub { reg1: reg1, reg2: reg2 },
+                "mul" => Instruction::Mul { reg1: reg1, reg2: reg2 },
+                "div" => Instruction::Div { reg1: reg1, reg2: reg2 },
+                "mulu" => Instruction::MulU { reg1: reg1, reg2: reg2 },
+                "divu" => Instruction::DivU { reg1: reg1, reg2: reg2 },
+                "or" => Instruction::Or { reg1: reg1, reg2: reg2 },
+                "and" => Instruction::And { reg1: reg1, reg2: reg2 },
+                "xor" => Instruction::Xor { reg1: reg1, reg2: reg2 },
+                _ => Instruction::Not { reg1: reg1, reg2: reg2 },
+            }
+        }
+        "jmp" => {
+            expect_operands(1)?;
+            let operand = operands[0];
+            if !operand.starts_with('[') || !operand.ends_with(']') {
+                return Err(format!("Expected [reg], found {}", operand));
+            }
+            Instruction::Jmp { reg1: parse_reg(&operand[1..operand.len() - 1])? }
+        }
+        "setf" => {
+            expect_operands(2)?;
+            let condition = match parse_number(operands[0]) {
+                Some(bits) => Condition::from_bits(in_range(bits, 0, 15)? as u32),
+                _ => parse_condition(operands[0]).ok_or_else(|| format!("Unknown condition: {}", operands[0]))?,
+            };
+            Instruction::Setf { condition: condition, reg2: parse_reg(operands[1])? }
+        }
+        "cli" | "reti" | "halt" | "sei" | "nop" => {
+            expect_operands(0)?;
+            match mnemonic {
+                "cli" => Instruction::Cli,
+                "reti" => Instruction::Reti,
+                "halt" => Instruction::Halt,
+                "sei" => Instruction::Sei,
+                _ => Instruction::Bcond { condition: Condition::F, disp: 0 },
+            }
+        }
+        "trap" => {
+            expect_operands(1)?;
+            Instruction::Trap { vector: in_range(parse_value(operands[0])?, 0, 31)? as u32 }
+        }
+        "ldsr" => {
+            expect_operands(2)?;
+            Instruction::Ldsr { reg2: parse_reg(operands[0])?, system_register: parse_system_register(operands[1])? }
+        }
+        "stsr" => {
+            expect_operands(2)?;
+            Instruction::Stsr { system_register: parse_system_register(operands[0])?, reg2: parse_reg(operands[1])? }
+        }
+        "jr" | "jal" => {
+            expect_operands(1)?;
+            let disp = target(operands[0], 26)?;
+            if mnemonic == "jr" {
+                Instruction::Jr { disp: disp }
+            } else {
+                Instruction::Jal { disp: disp }
+            }
+        }
+        "movea" | "addi" | "ori" | "andi" | "xori" | "movhi" => {
+            expect_operands(3)?;
+            let imm16 = in_range(parse_value(operands[0])?, -0x8000, 0xffff)? as u16;
+            let reg1 = parse_reg(operands[1])?;
+            let reg2 = parse_reg(operands[2])?;
+            match mnemonic {
+                "movea" => Instruction::Movea { imm16: imm16, reg1: reg1, reg2: reg2 },
+                "addi" => Instruction::AddImm16 { imm16: imm16, reg1: reg1, reg2: reg2 },
+                "ori" => Instruction::OrI { imm16: imm16, reg1: reg1, reg2: reg2 },
+                "andi" => Instruction::AndI { imm16: imm16, reg1: reg1, reg2: reg2 },
+                "xori" => Instruction::XorI { imm16: imm16, reg1: reg1, reg2: reg2 },
+                _ => Instruction::Movhi { imm16: imm16, reg1: reg1, reg2: reg2 },
+            }
+        }
+        "ld.b" | "ld.h" | "ld.w" | "in.b" | "in.h" | "in.w" | "caxi" => {
+            expect_operands(2)?;
+            let (disp16, reg1) = parse_mem(operands[0])?;
+            let reg2 = parse_reg(operands[1])?;
+            match mnemonic {
+                "ld.b" => Instruction::Ldb { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "ld.h" => Instruction::Ldh { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "ld.w" => Instruction::Ldw { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "in.b" => Instruction::Inb { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "in.h" => Instruction::Inh { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "in.w" => Instruction::Inw { disp16: disp16, reg1: reg1, reg2: reg2 },
+                _ => Instruction::Caxi { disp16: disp16, reg1: reg1, reg2: reg2 },
+            }
+        }
+        "st.b" | "st.h" | "st.w" | "out.b" | "out.h" | "out.w" => {
+            expect_operands(2)?;
+            let reg2 = parse_reg(operands[0])?;
+            let (disp16, reg1) = parse_mem(operands[1])?;
+            match mnemonic {
+                "st.b" => Instruction::Stb { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "st.h" => Instruction::Sth { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "st.w" => Instruction::Stw { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "out.b" => Instruction::Outb { disp16: disp16, reg1: reg1, reg2: reg2 },
+                "out.h" => Instruction::Outh { disp16: disp16, reg1: reg1, reg2: reg2 },
+                _ => Instruction::Outw { disp16: disp16, reg1: reg1, reg2: reg2 },
+            }
+        }
+        _ => {
+            if let Some(op) = (0..32).filter_map(BitStringOp::from_bits).find(|op| op.to_string() == mnemonic) {
+                expect_operands(0)?;
+                Instruction::BitString(op)
+            } else if let Some(subop) = (0..64).filter_map(SubOp::from_bits).find(|subop| subop.to_string() == mnemonic) {
+                expect_operands(2)?;
+                Instruction::Extended { subop: subop, reg1: parse_reg(operands[0])?, reg2: parse_reg(operands[1])? }
+            } else {
+                let condition = match mnemonic {
+                    "br" => Some(Condition::T),
+                    _ if mnemonic.starts_with('b') => parse_condition(&mnemonic[1..]).filter(|&condition| condition != Condition::T && condition != Condition::F),
+                    _ => None,
+                };
+                match condition {
+                    Some(condition) => {
+                        expect_operands(1)?;
+                        Instruction::Bcond { condition: condition, disp: target(operands[0], 9)? }
+                    }
+                    _ => return Err(format!("Unknown mnemonic: {}", mnemonic)),
+                }
+            }
+        }
+    };
+
+    Ok(instruction)
+}
+
+fn parse_reg(operand: &str) -> Result<usize, String> {
+    let operand = operand.to_lowercase();
+    if let Some(number) = operand.strip_prefix('r') {
+        if let Ok(reg) = number.parse::<usize>() {
+            if reg < 32 {
+                return Ok(reg);
+            }
+        }
+    }
+    Err(format!("Expected a register, found {}", operand))
+}
+
+// disp[reg], where disp may be omitted
+fn parse_mem(operand: &str) -> Result<(i16, usize), String> {
+    let error = || format!("Expected disp[reg], found {}", operand);
+    let open = operand.find('[').ok_or_else(&error)?;
+    if !operand.ends_with(']') {
+        return Err(error());
+    }
+    let disp = operand[..open].trim();
+    let disp = if disp.is_empty() { 0 } else { in_range(parse_value(disp)?, -0x8000, 0x7fff)? };
+    let reg = parse_reg(operand[open + 1..operand.len() - 1].trim())?;
+    Ok((disp as i16, reg))
+}
+
+fn parse_condition(name: &str) -> Option<Condition> {
+    (0..16).map(Condition::from_bits).find(|condition| condition.to_string() == name.to_lowercase())
+}
+
+fn parse_system_register(operand: &str) -> Result<SystemRegister, String> {
+    match parse_number(operand) {
+        Some(id) => Ok(SystemRegister::from_id(in_range(id, 0, 31)? as u32)),
+        _ => (0..32).map(SystemRegister::from_id)
+            .find(|system_register| system_register.to_string() == operand.to_lowercase())
+            .ok_or_else(|| format!("Unknown system register: {}", operand)),
+    }
+}
+
+fn parse_value(operand: &str) -> Result<i64, String> {
+    parse_number(operand).ok_or_else(|| format!("Expected a number, found {}", operand))
+}
+
+// A number or a label
+fn resolve(operand: &str, labels: &Fn(&str) -> Option<u32>) -> Result<i64, String> {
+    match parse_number(operand) {
+        Some(value) => Ok(value),
+        _ if is_label_name(operand) => labels(operand).map(|addr| addr as i64).ok_or_else(|| format!("Undefined label: {}", operand)),
+        _ => Err(format!("Expected a number or label, found {}", operand)),
+    }
+}
+
+fn parse_number(operand: &str) -> Option<i64> {
+    let (is_negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        _ => (false, operand),
+    };
+    let value = if digits.starts_with("0x") || digits.starts_with("0X") {
+        i64::from_str_radix(&digits[2..], 16).ok()
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse::<i64>().ok()
+    } else {
+        None
+    };
+    value.map(|value| if is_negative { -value } else { value })
+}
+
+fn is_label_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn in_range(value: i64, min: i64, max: i64) -> Result<i64, String> {
+    if value >= min && value <= max {
+        Ok(value)
+    } else {
+        Err(format!("Value out of range: {} (expected {} to {})", value, min, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_segment(segment: &Segment) -> Instruction {
+        let halfword = |index: usize| segment.bytes.get(index * 2).map_or(0, |&low| (low as u16) | ((segment.bytes[index * 2 + 1] as u16) << 8));
+        Instruction::decode(halfword(0), halfword(1))
+    }
+
+    #[test]
+    fn disassembly_reassembles() {
+        let pc = 0x07000100;
+        for first_halfword in 0..0x10000 {
+            let first_halfword = first_halfword as u16;
+            let second_halfwords: &[u16] = if is_long_instruction(first_halfword) { &[0x0000, 0x0001, 0x7fff, 0x8000, 0xffff] } else { &[0x0000] };
+            for &second_halfword in second_halfwords {
+                let instruction = Instruction::decode(first_halfword, second_halfword);
+                match instruction {
+                    Instruction::Illegal { .. } => continue,
+                    // nop doesn't print its displacement
+                    Instruction::Bcond { condition: Condition::F, disp } if disp != 0 => continue,
+                    // The CPU ignores the lowest bit of a displacement, but the assembler won't emit it
+                    Instruction::Bcond { disp, .. } |
+                    Instruction::Jr { disp } |
+                    Instruction::Jal { disp } if disp & 1 != 0 => continue,
+                    _ => (),
+                }
+
+                let text = instruction.to_string();
+                let segments = assemble(&text, pc).unwrap_or_else(|e| panic!("{:04x} {:04x} ({}): {}", first_halfword, second_halfword, text, e));
+                assert_eq!(segments.len(), 1);
+                assert_eq!(segments[0].addr, pc);
+                assert_eq!(segments[0].bytes.len(), if instruction.is_long() { 4 } else { 2 }, "{}", text);
+                assert!(decode_segment(&segments[0]) == instruction, "{:04x} {:04x} ({})", first_halfword, second_halfword, text);
+            }
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let source = "
+            start:
+                mov 1, r10
+            loop: add -1, r10
+                bnz loop
+                jr start
+                jal end
+            end:
+                .word end";
+        let segments = assemble(source, 0x07000000).unwrap();
+        assert_eq!(segments, vec![Segment {
+            addr: 0x07000000,
+            bytes: vec![
+                0x41, 0x41,
+                0x5f, 0x45,
+                0xfe, 0x95,
+                0xff, 0xab, 0xfa, 0xff,
+                0x00, 0xac, 0x04, 0x00,
+                0x0e, 0x00, 0x00, 0x07,
+            ],
+        }]);
+    }
+
+    #[test]
+    fn predefined_labels() {
+        let mut assembler = Assembler::new(0x05000000);
+        assembler.define_label("Target", 0x05000010);
+        assert_eq!(assembler.assemble("br target").unwrap()[0].bytes, vec![0x10, 0x8a]);
+
+        // Labels defined in the source take precedence
+        assert_eq!(assembler.assemble("TARGET: br target").unwrap()[0].bytes, vec![0x00, 0x8a]);
+    }
+
+    #[test]
+    fn org() {
+        let source = "
+            .halfword 0x1234
+            .org 0x05000100
+            here: .word here
+            .org 0x05000200
+            .org 0x05000300
+            .word 0xdeadbeef";
+        let segments = assemble(source, 0x05000000).unwrap();
+        assert_eq!(segments, vec![
+            Segment { addr: 0x05000000, bytes: vec![0x34, 0x12] },
+            Segment { addr: 0x05000100, bytes: vec![0x00, 0x01, 0x00, 0x05] },
+            Segment { addr: 0x05000300, bytes: vec![0xef, 0xbe, 0xad, 0xde] },
+        ]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| assemble(source, 0x07000000).unwrap_err();
+        assert_eq!(error("nop\nbnz nowhere").line, 2);
+        assert_eq!(error("bnz nowhere").message, "Undefined label: nowhere");
+        assert_eq!(error("x:\nx:").message, "Label x is already defined");
+        assert_eq!(error("br 256").message, "Value out of range: 256 (expected -256 to 255)");
+        assert_eq!(error("br 3").message, "Displacement must be even: 3");
+        assert_eq!(error("jr -1").message, "Displacement must be even: -1");
+        assert_eq!(error(".org 0x07000001\nodd:\n.org 0x07000000\nbr odd").message, "Displacement must be even: 1");
+        assert_eq!(error(".org").message, ".org takes an address");
+    }
+}
@@ -0,0 +1,89 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+// Everything the CPU sees of the system it's attached to. Addresses are passed through as-is;
+//  the bus is responsible for any masking or mirroring. Halfword and word accesses are always
+//  aligned by the CPU.
+pub trait Bus {
+    fn read_byte(&mut self, addr: u32) -> u8;
+    fn read_halfword(&mut self, addr: u32) -> u16;
+    fn write_byte(&mut self, addr: u32, value: u8);
+    fn write_halfword(&mut self, addr: u32, value: u16);
+
+    // The V810's data bus is 16 bits wide, so words are transferred as two halfwords, low first
+    fn read_word(&mut self, addr: u32) -> u32 {
+        (self.read_halfword(addr) as u32) |
+        ((self.read_halfword(addr.wrapping_add(2)) as u32) << 16)
+    }
+
+    fn write_word(&mut self, addr: u32, value: u32) {
+        self.write_halfword(addr, value as _);
+        self.write_halfword(addr.wrapping_add(2), (value >> 16) as _);
+    }
+
+    // Instruction fetches. Whether one actually reaches the bus depends on what's been cached, so
+    //  a bus that tracks its own state (like the last value driven on it) should leave it alone here.
+    fn fetch_halfword(&mut self, addr: u32) -> u16 {
+        self.read_halfword(addr)
+    }
+
+    fn fetch_word(&mut self, addr: u32) -> u32 {
+        (self.fetch_halfword(addr) as u32) |
+        ((self.fetch_halfword(addr.wrapping_add(2)) as u32) << 16)
+    }
+
+    // Cycles a data access at addr takes, including wait states
+    fn access_cycles(&self, addr: u32, size: AccessSize) -> u32;
+
+    // Identifies the current contents of the code at addr, for caching decoded instructions. It
+    //  has to change whenever that code is written; None means code at addr is never cached.
+    fn code_generation(&self, _addr: u32) -> Option<u64> {
+        None
+    }
+}
+
+// 64KB of RAM mirrored across the whole address space, for running the CPU on its own
+#[cfg(test)]
+pub(crate) struct FlatBus {
+    pub memory: Vec<u8>,
+}
+
+#[cfg(test)]
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            memory: vec![0; 0x10000],
+        }
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatBus {
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        self.memory[(addr & 0xffff) as usize]
+    }
+
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        (self.read_byte(addr) as u16) | ((self.read_byte(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        self.memory[(addr & 0xffff) as usize] = value;
+    }
+
+    fn write_halfword(&mut self, addr: u32, value: u16) {
+        self.write_byte(addr, value as _);
+        self.write_byte(addr.wrapping_add(1), (value >> 8) as _);
+    }
+
+    fn access_cycles(&self, _addr: u32, size: AccessSize) -> u32 {
+        match size {
+            AccessSize::Word => 4,
+            _ => 2,
+        }
+    }
+}
@@ -1,8 +1,12 @@
+use save_state::*;
+
 pub struct ComPort {
     cdtr: u8,
     cdrr: u8,
 
     c_stat: bool,
+    c_int_inh: bool,
+    interrupt: bool,
 
     transfer_bit_index: u32,
 }
@@ -14,6 +18,8 @@ impl ComPort {
             cdrr: 0,
 
             c_stat: false,
+            c_int_inh: false,
+            interrupt: false,
 
             transfer_bit_index: 0,
         }
@@ -22,11 +28,16 @@ impl ComPort {
     pub fn read_ccr(&self) -> u8 {
         logln!(Log::Ic, "WARNING: Read from CCR not fully implemented");
         0b01101001 |
+        if self.c_int_inh { 1 << 7 } else { 0 } |
         if self.c_stat { 1 << 1 } else { 0 }
     }
 
     pub fn write_ccr(&mut self, value: u8) {
-        logln!(Log::Ic, "WARNING: Write to CCR not yet implemented (value: 0x{:02x})", value);
+        logln!(Log::Ic, "WARNING: Write to CCR not fully implemented (value: 0x{:02x})", value);
+        self.c_int_inh = (value & 0x80) != 0;
+        if self.c_int_inh {
+            self.interrupt = false;
+        }
         if (value & 0x04) != 0 && !self.c_stat {
             self.cdrr = 0;
             self.c_stat = true;
@@ -55,6 +66,42 @@ impl ComPort {
         self.cdrr
     }
 
+    // Latched when a transfer completes, until the CPU takes the interrupt or it's inhibited through CCR
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    pub fn acknowledge_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u8(self.cdtr);
+        writer.write_u8(self.cdrr);
+
+        writer.write_bool(self.c_stat);
+        writer.write_bool(self.c_int_inh);
+        writer.write_bool(self.interrupt);
+
+        writer.write_u32(self.transfer_bit_index);
+    }
+
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.cdtr = reader.read_u8()?;
+        self.cdrr = reader.read_u8()?;
+
+        self.c_stat = reader.read_bool()?;
+        self.c_int_inh = reader.read_bool()?;
+        self.interrupt = reader.read_bool()?;
+
+        self.transfer_bit_index = reader.read_u32()?;
+        if self.transfer_bit_index > 7 {
+            return Err(SaveStateError::InvalidValue("com port transfer bit index"));
+        }
+
+        Ok(())
+    }
+
     // TODO: This covers the case where the VB is slave only, and doesn't properly emulate any possible timing errors that might occur.
     pub fn transfer_slave_clock_bit(&mut self, bit: u32) -> u32 {
         if !self.c_stat {
@@ -67,7 +114,9 @@ impl ComPort {
         if self.transfer_bit_index == 0 {
             self.c_stat = false;
 
-            // TODO: Emulate link port interrupt (if applicable)
+            if !self.c_int_inh {
+                self.interrupt = true;
+            }
         } else {
             self.transfer_bit_index -= 1;
         }
@@ -0,0 +1,108 @@
+use instruction::*;
+use wram::*;
+
+// Host-side cache of decoded instructions, keyed by PC, so hot loops don't have to go through the
+//  bus (and the decoder) on every step. This is entirely separate from the V810's own instruction
+//  cache (Cache in v810.rs), which is still updated on every fetch so its contents and stats are
+//  unaffected.
+
+const NUM_ENTRIES: usize = 4096;
+
+// Writes to WRAM invalidate cached instructions at this granularity
+const CODE_PAGE_SIZE: usize = 256;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    pc: u32,
+    generation: u64,
+    instruction: Instruction,
+}
+
+pub struct DecodeCache {
+    entries: Box<[Entry]>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        let empty = Entry {
+            // Instructions are always halfword-aligned, so this never matches
+            pc: 0xffffffff,
+            generation: 0,
+            instruction: Instruction::Illegal {
+                first_halfword: 0,
+                second_halfword: 0,
+            },
+        };
+
+        DecodeCache {
+            entries: vec![empty; NUM_ENTRIES].into_boxed_slice(),
+        }
+    }
+
+    pub fn get(&self, pc: u32, generation: u64) -> Option<Instruction> {
+        let entry = &self.entries[Self::index(pc)];
+        if entry.pc == pc && entry.generation == generation {
+            Some(entry.instruction)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, pc: u32, generation: u64, instruction: Instruction) {
+        // A 32-bit instruction straddling two code pages could go stale without its first page changing
+        if instruction.is_long() && !is_same_code_page(pc, pc.wrapping_add(2)) {
+            return;
+        }
+
+        self.entries[Self::index(pc)] = Entry {
+            pc: pc,
+            generation: generation,
+            instruction: instruction,
+        };
+    }
+
+    fn index(pc: u32) -> usize {
+        ((pc >> 1) as usize) & (NUM_ENTRIES - 1)
+    }
+}
+
+fn code_page(addr: u32) -> usize {
+    addr as usize / CODE_PAGE_SIZE
+}
+
+pub fn is_same_code_page(a: u32, b: u32) -> bool {
+    code_page(a) == code_page(b)
+}
+
+// Write generation of each WRAM page; a cached instruction is only valid while the generation
+//  of the page it was fetched from hasn't changed
+pub struct CodePages {
+    generations: Box<[u64]>,
+}
+
+impl CodePages {
+    pub fn new() -> CodePages {
+        CodePages {
+            generations: vec![0; WRAM_SIZE / CODE_PAGE_SIZE].into_boxed_slice(),
+        }
+    }
+
+    pub fn generation(&self, offset: u32) -> u64 {
+        self.generations[self.page(offset)]
+    }
+
+    pub fn invalidate(&mut self, offset: u32) {
+        let page = self.page(offset);
+        self.generations[page] += 1;
+    }
+
+    pub fn invalidate_all(&mut self) {
+        for generation in self.generations.iter_mut() {
+            *generation += 1;
+        }
+    }
+
+    fn page(&self, offset: u32) -> usize {
+        code_page(offset) % self.generations.len()
+    }
+}
@@ -1,3 +1,5 @@
+use save_state::*;
+
 pub enum Button {
     A,
     B,
@@ -30,6 +32,9 @@ pub struct GamePad {
     right_d_pad_down_pressed: bool,
     right_d_pad_left_pressed: bool,
     right_d_pad_right_pressed: bool,
+
+    key_interrupt_inhibit: bool,
+    key_interrupt: bool,
 }
 
 impl GamePad {
@@ -49,16 +54,32 @@ impl GamePad {
             right_d_pad_down_pressed: false,
             right_d_pad_left_pressed: false,
             right_d_pad_right_pressed: false,
+
+            key_interrupt_inhibit: false,
+            key_interrupt: false,
         }
     }
 
     pub fn read_scr(&self) -> u8 {
-        logln!(Log::GamePad, "WARNING: Read SCR not yet implemented");
-        0
+        logln!(Log::GamePad, "WARNING: Read SCR not fully implemented");
+        if self.key_interrupt_inhibit { 1 << 7 } else { 0 }
     }
 
     pub fn write_scr(&mut self, value: u8) {
-        logln!(Log::GamePad, "WARNING: Write SCR not yet implemented (value: 0x{:02x})", value);
+        logln!(Log::GamePad, "WARNING: Write SCR not fully implemented (value: 0x{:02x})", value);
+        self.key_interrupt_inhibit = (value & 0x80) != 0;
+        if self.key_interrupt_inhibit {
+            self.key_interrupt = false;
+        }
+    }
+
+    // Latched when a button is pressed, until the CPU takes the interrupt or it's inhibited through SCR
+    pub fn key_interrupt(&self) -> bool {
+        self.key_interrupt
+    }
+
+    pub fn acknowledge_key_interrupt(&mut self) {
+        self.key_interrupt = false;
     }
 
     pub fn read_sdlr(&self) -> u8 {
@@ -86,22 +107,69 @@ impl GamePad {
         if self.left_d_pad_right_pressed { 1 } else { 0 }
     }
 
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_bool(self.a_pressed);
+        writer.write_bool(self.b_pressed);
+        writer.write_bool(self.start_pressed);
+        writer.write_bool(self.select_pressed);
+        writer.write_bool(self.l_pressed);
+        writer.write_bool(self.r_pressed);
+        writer.write_bool(self.left_d_pad_up_pressed);
+        writer.write_bool(self.left_d_pad_down_pressed);
+        writer.write_bool(self.left_d_pad_left_pressed);
+        writer.write_bool(self.left_d_pad_right_pressed);
+        writer.write_bool(self.right_d_pad_up_pressed);
+        writer.write_bool(self.right_d_pad_down_pressed);
+        writer.write_bool(self.right_d_pad_left_pressed);
+        writer.write_bool(self.right_d_pad_right_pressed);
+
+        writer.write_bool(self.key_interrupt_inhibit);
+        writer.write_bool(self.key_interrupt);
+    }
+
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.a_pressed = reader.read_bool()?;
+        self.b_pressed = reader.read_bool()?;
+        self.start_pressed = reader.read_bool()?;
+        self.select_pressed = reader.read_bool()?;
+        self.l_pressed = reader.read_bool()?;
+        self.r_pressed = reader.read_bool()?;
+        self.left_d_pad_up_pressed = reader.read_bool()?;
+        self.left_d_pad_down_pressed = reader.read_bool()?;
+        self.left_d_pad_left_pressed = reader.read_bool()?;
+        self.left_d_pad_right_pressed = reader.read_bool()?;
+        self.right_d_pad_up_pressed = reader.read_bool()?;
+        self.right_d_pad_down_pressed = reader.read_bool()?;
+        self.right_d_pad_left_pressed = reader.read_bool()?;
+        self.right_d_pad_right_pressed = reader.read_bool()?;
+
+        self.key_interrupt_inhibit = reader.read_bool()?;
+        self.key_interrupt = reader.read_bool()?;
+
+        Ok(())
+    }
+
     pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
-        match button {
-            Button::A => self.a_pressed = pressed,
-            Button::B => self.b_pressed = pressed,
-            Button::Start => self.start_pressed = pressed,
-            Button::Select => self.select_pressed = pressed,
-            Button::L => self.l_pressed = pressed,
-            Button::R => self.r_pressed = pressed,
-            Button::LeftDPadUp => self.left_d_pad_up_pressed = pressed,
-            Button::LeftDPadDown => self.left_d_pad_down_pressed = pressed,
-            Button::LeftDPadLeft => self.left_d_pad_left_pressed = pressed,
-            Button::LeftDPadRight => self.left_d_pad_right_pressed = pressed,
-            Button::RightDPadUp => self.right_d_pad_up_pressed = pressed,
-            Button::RightDPadDown => self.right_d_pad_down_pressed = pressed,
-            Button::RightDPadLeft => self.right_d_pad_left_pressed = pressed,
-            Button::RightDPadRight => self.right_d_pad_right_pressed = pressed,
+        let button_pressed = match button {
+            Button::A => &mut self.a_pressed,
+            Button::B => &mut self.b_pressed,
+            Button::Start => &mut self.start_pressed,
+            Button::Select => &mut self.select_pressed,
+            Button::L => &mut self.l_pressed,
+            Button::R => &mut self.r_pressed,
+            Button::LeftDPadUp => &mut self.left_d_pad_up_pressed,
+            Button::LeftDPadDown => &mut self.left_d_pad_down_pressed,
+            Button::LeftDPadLeft => &mut self.left_d_pad_left_pressed,
+            Button::LeftDPadRight => &mut self.left_d_pad_right_pressed,
+            Button::RightDPadUp => &mut self.right_d_pad_up_pressed,
+            Button::RightDPadDown => &mut self.right_d_pad_down_pressed,
+            Button::RightDPadLeft => &mut self.right_d_pad_left_pressed,
+            Button::RightDPadRight => &mut self.right_d_pad_right_pressed,
+        };
+
+        if pressed && !*button_pressed && !self.key_interrupt_inhibit {
+            self.key_interrupt = true;
         }
+        *button_pressed = pressed;
     }
 }
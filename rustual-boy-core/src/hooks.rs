@@ -0,0 +1,185 @@
+use bus::*;
+use interconnect::*;
+use mem_map::*;
+use v810::*;
+use vip::*;
+
+/// Identifies a hook registered with `VirtualBoy`, so it can be removed again
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct HookId(u64);
+
+/// Which part of the address space a memory access went to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MemoryRegion {
+    Vip,
+    Vsu,
+    HardwareControl,
+    /// 0x03000000-0x03ffffff, where nothing is mapped
+    Unused,
+    GamePakExpansion,
+    Wram,
+    GamePakRam,
+    GamePakRom,
+}
+
+impl MemoryRegion {
+    pub fn from_addr(addr: u32) -> MemoryRegion {
+        match addr & 0x07ffffff {
+            VIP_START ... VIP_END => MemoryRegion::Vip,
+            VSU_START ... VSU_END => MemoryRegion::Vsu,
+            HARDWARE_CONTROL_START ... HARDWARE_CONTROL_END => MemoryRegion::HardwareControl,
+            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => MemoryRegion::GamePakExpansion,
+            WRAM_START ... WRAM_END => MemoryRegion::Wram,
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => MemoryRegion::GamePakRam,
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => MemoryRegion::GamePakRom,
+            _ => MemoryRegion::Unused,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MemoryAccessKind {
+    Read,
+    Write,
+}
+
+/// A data access made by the CPU, reported to memory hooks once it's complete. Instruction
+///  fetches aren't included; see `VirtualBoy::add_instruction_hook` for those.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: MemoryAccessKind,
+    /// The address as the CPU issued it, before any masking or mirroring
+    pub addr: u32,
+    pub size: AccessSize,
+    /// The value read or written, zero-extended
+    pub value: u32,
+    pub region: MemoryRegion,
+}
+
+pub(crate) type InstructionHook = (HookId, Box<FnMut(u32)>);
+pub(crate) type MemoryHook = (HookId, Box<FnMut(&MemoryAccess)>);
+pub(crate) type ExceptionHook = (HookId, Box<FnMut(&Exception)>);
+pub(crate) type VipHook = (HookId, Box<FnMut(VipEvent)>);
+
+pub(crate) struct Hooks {
+    next_id: u64,
+
+    pub instruction: Vec<InstructionHook>,
+    pub memory: Vec<MemoryHook>,
+    pub exception: Vec<ExceptionHook>,
+    pub vip: Vec<VipHook>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks {
+            next_id: 0,
+
+            instruction: Vec::new(),
+            memory: Vec::new(),
+            exception: Vec::new(),
+            vip: Vec::new(),
+        }
+    }
+
+    pub fn next_id(&mut self) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    // Returns whether a hook with the given id was registered
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.len();
+        self.instruction.retain(|&(hook_id, _)| hook_id != id);
+        self.memory.retain(|&(hook_id, _)| hook_id != id);
+        self.exception.retain(|&(hook_id, _)| hook_id != id);
+        self.vip.retain(|&(hook_id, _)| hook_id != id);
+        self.len() != len
+    }
+
+    fn len(&self) -> usize {
+        self.instruction.len() + self.memory.len() + self.exception.len() + self.vip.len()
+    }
+
+    // Translated blocks run many instructions at once without going through the hooks, so
+    //  only the interpreter can be used while any of these are registered
+    #[cfg(feature = "dynarec")]
+    pub fn require_interpreter(&self) -> bool {
+        !self.instruction.is_empty() || !self.memory.is_empty() || !self.exception.is_empty()
+    }
+}
+
+// Passes the CPU's accesses through to the interconnect, reporting data accesses to the memory
+//  hooks on the way. Only used while there are any, so the CPU talks to the interconnect
+//  directly otherwise.
+pub(crate) struct ObservedBus<'a> {
+    pub interconnect: &'a mut Interconnect,
+    pub hooks: &'a mut [MemoryHook],
+}
+
+impl<'a> ObservedBus<'a> {
+    fn report(&mut self, kind: MemoryAccessKind, addr: u32, size: AccessSize, value: u32) {
+        let access = MemoryAccess {
+            kind: kind,
+            addr: addr,
+            size: size,
+            value: value,
+            region: MemoryRegion::from_addr(addr),
+        };
+        for &mut (_, ref mut hook) in self.hooks.iter_mut() {
+            hook(&access);
+        }
+    }
+}
+
+impl<'a> Bus for ObservedBus<'a> {
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        let value = self.interconnect.read_byte(addr);
+        self.report(MemoryAccessKind::Read, addr, AccessSize::Byte, value as _);
+        value
+    }
+
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        let value = self.interconnect.read_halfword(addr);
+        self.report(MemoryAccessKind::Read, addr, AccessSize::Halfword, value as _);
+        value
+    }
+
+    fn read_word(&mut self, addr: u32) -> u32 {
+        let value = self.interconnect.read_word(addr);
+        self.report(MemoryAccessKind::Read, addr, AccessSize::Word, value);
+        value
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        self.interconnect.write_byte(addr, value);
+        self.report(MemoryAccessKind::Write, addr, AccessSize::Byte, value as _);
+    }
+
+    fn write_halfword(&mut self, addr: u32, value: u16) {
+        self.interconnect.write_halfword(addr, value);
+        self.report(MemoryAccessKind::Write, addr, AccessSize::Halfword, value as _);
+    }
+
+    fn write_word(&mut self, addr: u32, value: u32) {
+        self.interconnect.write_word(addr, value);
+        self.report(MemoryAccessKind::Write, addr, AccessSize::Word, value);
+    }
+
+    fn fetch_halfword(&mut self, addr: u32) -> u16 {
+        self.interconnect.fetch_halfword(addr)
+    }
+
+    fn fetch_word(&mut self, addr: u32) -> u32 {
+        self.interconnect.fetch_word(addr)
+    }
+
+    fn access_cycles(&self, addr: u32, size: AccessSize) -> u32 {
+        self.interconnect.access_cycles(addr, size)
+    }
+
+    fn code_generation(&self, addr: u32) -> Option<u64> {
+        self.interconnect.code_generation(addr)
+    }
+}
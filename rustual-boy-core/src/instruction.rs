@@ -43,6 +43,7 @@ pub const OPCODE_BITS_SHL_IMM: u16 = 0b010100;
 pub const OPCODE_BITS_SHR_IMM: u16 = 0b010101;
 pub const OPCODE_BITS_CLI: u16 = 0b010110;
 pub const OPCODE_BITS_SAR_IMM: u16 = 0b010111;
+pub const OPCODE_BITS_TRAP: u16 = 0b011000;
 pub const OPCODE_BITS_RETI: u16 = 0b011001;
 pub const OPCODE_BITS_HALT: u16 = 0b011010;
 pub const OPCODE_BITS_LDSR: u16 = 0b011100;
@@ -65,12 +66,17 @@ pub const OPCODE_BITS_STH: u16 = 0b110101;
 pub const OPCODE_BITS_STW: u16 = 0b110111;
 pub const OPCODE_BITS_INB: u16 = 0b111000;
 pub const OPCODE_BITS_INH: u16 = 0b111001;
+pub const OPCODE_BITS_CAXI: u16 = 0b111010;
 pub const OPCODE_BITS_INW: u16 = 0b111011;
 pub const OPCODE_BITS_OUTB: u16 = 0b111100;
 pub const OPCODE_BITS_OUTH: u16 = 0b111101;
 pub const OPCODE_BITS_EXTENDED: u16 = 0b111110;
 pub const OPCODE_BITS_OUTW: u16 = 0b111111;
 
+pub const OPCODE_BITS_BIT_STRING_OP_SCH0BSU: u32 = 0b00000;
+pub const OPCODE_BITS_BIT_STRING_OP_SCH0BSD: u32 = 0b00001;
+pub const OPCODE_BITS_BIT_STRING_OP_SCH1BSU: u32 = 0b00010;
+pub const OPCODE_BITS_BIT_STRING_OP_SCH1BSD: u32 = 0b00011;
 pub const OPCODE_BITS_BIT_STRING_OP_ORBSU: u32 = 0b01000;
 pub const OPCODE_BITS_BIT_STRING_OP_ANDBSU: u32 = 0b01001;
 pub const OPCODE_BITS_BIT_STRING_OP_XORBSU: u32 = 0b01010;
@@ -100,6 +106,7 @@ pub const OPCODE_SYSTEM_REGISTER_ID_FEPSW: u32 = 3;
 pub const OPCODE_SYSTEM_REGISTER_ID_ECR: u32 = 4;
 pub const OPCODE_SYSTEM_REGISTER_ID_PSW: u32 = 5;
 pub const OPCODE_SYSTEM_REGISTER_ID_CHCW: u32 = 24;
+pub const OPCODE_SYSTEM_REGISTER_ID_ADTRE: u32 = 25;
 
 pub const OPCODE_CONDITION_BITS_V: u32 = 0x00;
 pub const OPCODE_CONDITION_BITS_C: u32 = 0x01;
@@ -118,371 +125,12 @@ pub const OPCODE_CONDITION_BITS_F: u32 = 0x0d;
 pub const OPCODE_CONDITION_BITS_GE: u32 = 0x0e;
 pub const OPCODE_CONDITION_BITS_GT: u32 = 0x0f;
 
-#[derive(PartialEq, Eq)]
-pub enum Opcode {
-    MovReg,
-    AddReg,
-    Sub,
-    CmpReg,
-    ShlReg,
-    ShrReg,
-    Jmp,
-    SarReg,
-    Mul,
-    Div,
-    MulU,
-    DivU,
-    Or,
-    And,
-    Xor,
-    Not,
-    MovImm,
-    AddImm5,
-    Setf,
-    CmpImm,
-    ShlImm,
-    ShrImm,
-    Cli,
-    SarImm,
-    Reti,
-    Halt,
-    Ldsr,
-    Stsr,
-    Sei,
-    BitString,
-    Bv,
-    Bc,
-    Bz,
-    Bnh,
-    Bn,
-    Br,
-    Blt,
-    Ble,
-    Bnv,
-    Bnc,
-    Bnz,
-    Bh,
-    Bp,
-    Nop,
-    Bge,
-    Bgt,
-    Movea,
-    AddImm16,
-    Jr,
-    Jal,
-    OrI,
-    AndI,
-    XorI,
-    Movhi,
-    Ldb,
-    Ldh,
-    Ldw,
-    Stb,
-    Sth,
-    Stw,
-    Inb,
-    Inh,
-    Inw,
-    Outb,
-    Outh,
-    Extended,
-    Outw,
-}
-
-impl Opcode {
-    pub fn from_halfword(halfword: u16) -> Opcode {
-        if halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
-            let cond_bits = (halfword >> 9) & 0x0f;
-            match cond_bits {
-                OPCODE_BITS_BCOND_BV => Opcode::Bv,
-                OPCODE_BITS_BCOND_BC => Opcode::Bc,
-                OPCODE_BITS_BCOND_BZ => Opcode::Bz,
-                OPCODE_BITS_BCOND_BNH => Opcode::Bnh,
-                OPCODE_BITS_BCOND_BN => Opcode::Bn,
-                OPCODE_BITS_BCOND_BR => Opcode::Br,
-                OPCODE_BITS_BCOND_BLT => Opcode::Blt,
-                OPCODE_BITS_BCOND_BLE => Opcode::Ble,
-                OPCODE_BITS_BCOND_BNV => Opcode::Bnv,
-                OPCODE_BITS_BCOND_BNC => Opcode::Bnc,
-                OPCODE_BITS_BCOND_BNZ => Opcode::Bnz,
-                OPCODE_BITS_BCOND_BH => Opcode::Bh,
-                OPCODE_BITS_BCOND_BP => Opcode::Bp,
-                OPCODE_BITS_BCOND_NOP => Opcode::Nop,
-                OPCODE_BITS_BCOND_BGE => Opcode::Bge,
-                OPCODE_BITS_BCOND_BGT => Opcode::Bgt,
-                _ => panic!("Unrecognized cond bits: {:04b} (halfword: 0b{:016b})", cond_bits, halfword)
-            }
-        } else {
-            let opcode_bits = halfword >> 10;
-            match opcode_bits {
-                OPCODE_BITS_MOV_REG => Opcode::MovReg,
-                OPCODE_BITS_ADD_REG => Opcode::AddReg,
-                OPCODE_BITS_SUB => Opcode::Sub,
-                OPCODE_BITS_CMP_REG => Opcode::CmpReg,
-                OPCODE_BITS_SHL_REG => Opcode::ShlReg,
-                OPCODE_BITS_SHR_REG => Opcode::ShrReg,
-                OPCODE_BITS_JMP => Opcode::Jmp,
-                OPCODE_BITS_SAR_REG => Opcode::SarReg,
-                OPCODE_BITS_MUL => Opcode::Mul,
-                OPCODE_BITS_DIV => Opcode::Div,
-                OPCODE_BITS_MUL_U => Opcode::MulU,
-                OPCODE_BITS_DIV_U => Opcode::DivU,
-                OPCODE_BITS_OR => Opcode::Or,
-                OPCODE_BITS_AND => Opcode::And,
-                OPCODE_BITS_XOR => Opcode::Xor,
-                OPCODE_BITS_NOT => Opcode::Not,
-                OPCODE_BITS_MOV_IMM => Opcode::MovImm,
-                OPCODE_BITS_ADD_IMM_5 => Opcode::AddImm5,
-                OPCODE_BITS_SETF => Opcode::Setf,
-                OPCODE_BITS_CMP_IMM => Opcode::CmpImm,
-                OPCODE_BITS_SHL_IMM => Opcode::ShlImm,
-                OPCODE_BITS_SHR_IMM => Opcode::ShrImm,
-                OPCODE_BITS_CLI => Opcode::Cli,
-                OPCODE_BITS_SAR_IMM => Opcode::SarImm,
-                OPCODE_BITS_RETI => Opcode::Reti,
-                OPCODE_BITS_HALT => Opcode::Halt,
-                OPCODE_BITS_LDSR => Opcode::Ldsr,
-                OPCODE_BITS_STSR => Opcode::Stsr,
-                OPCODE_BITS_SEI => Opcode::Sei,
-                OPCODE_BITS_BIT_STRING => Opcode::BitString,
-                OPCODE_BITS_MOVEA => Opcode::Movea,
-                OPCODE_BITS_ADD_IMM_16 => Opcode::AddImm16,
-                OPCODE_BITS_JR => Opcode::Jr,
-                OPCODE_BITS_JAL => Opcode::Jal,
-                OPCODE_BITS_OR_I => Opcode::OrI,
-                OPCODE_BITS_AND_I => Opcode::AndI,
-                OPCODE_BITS_XOR_I => Opcode::XorI,
-                OPCODE_BITS_MOVHI => Opcode::Movhi,
-                OPCODE_BITS_LDB => Opcode::Ldb,
-                OPCODE_BITS_LDH => Opcode::Ldh,
-                OPCODE_BITS_LDW => Opcode::Ldw,
-                OPCODE_BITS_STB => Opcode::Stb,
-                OPCODE_BITS_STH => Opcode::Sth,
-                OPCODE_BITS_STW => Opcode::Stw,
-                OPCODE_BITS_INB => Opcode::Inb,
-                OPCODE_BITS_INH => Opcode::Inh,
-                OPCODE_BITS_INW => Opcode::Inw,
-                OPCODE_BITS_OUTB => Opcode::Outb,
-                OPCODE_BITS_OUTH => Opcode::Outh,
-                OPCODE_BITS_EXTENDED => Opcode::Extended,
-                OPCODE_BITS_OUTW => Opcode::Outw,
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, halfword),
-            }
-        }
-    }
-
-    pub fn instruction_format(&self) -> InstructionFormat {
-        match self {
-            &Opcode::MovReg => InstructionFormat::I,
-            &Opcode::AddReg => InstructionFormat::I,
-            &Opcode::Sub => InstructionFormat::I,
-            &Opcode::CmpReg => InstructionFormat::I,
-            &Opcode::ShlReg => InstructionFormat::I,
-            &Opcode::ShrReg => InstructionFormat::I,
-            &Opcode::Jmp => InstructionFormat::I,
-            &Opcode::SarReg => InstructionFormat::I,
-            &Opcode::Mul => InstructionFormat::I,
-            &Opcode::Div => InstructionFormat::I,
-            &Opcode::MulU => InstructionFormat::I,
-            &Opcode::DivU => InstructionFormat::I,
-            &Opcode::Or => InstructionFormat::I,
-            &Opcode::And => InstructionFormat::I,
-            &Opcode::Xor => InstructionFormat::I,
-            &Opcode::Not => InstructionFormat::I,
-            &Opcode::MovImm => InstructionFormat::II,
-            &Opcode::AddImm5 => InstructionFormat::II,
-            &Opcode::Setf => InstructionFormat::II,
-            &Opcode::CmpImm => InstructionFormat::II,
-            &Opcode::ShlImm => InstructionFormat::II,
-            &Opcode::ShrImm => InstructionFormat::II,
-            &Opcode::Cli => InstructionFormat::II,
-            &Opcode::SarImm => InstructionFormat::II,
-            &Opcode::Reti => InstructionFormat::II,
-            &Opcode::Halt => InstructionFormat::II,
-            &Opcode::Ldsr => InstructionFormat::II,
-            &Opcode::Stsr => InstructionFormat::II,
-            &Opcode::Sei => InstructionFormat::II,
-            &Opcode::BitString => InstructionFormat::II,
-            &Opcode::Bv => InstructionFormat::III,
-            &Opcode::Bc => InstructionFormat::III,
-            &Opcode::Bz => InstructionFormat::III,
-            &Opcode::Bnh => InstructionFormat::III,
-            &Opcode::Bn => InstructionFormat::III,
-            &Opcode::Br => InstructionFormat::III,
-            &Opcode::Blt => InstructionFormat::III,
-            &Opcode::Ble => InstructionFormat::III,
-            &Opcode::Bnv => InstructionFormat::III,
-            &Opcode::Bnc => InstructionFormat::III,
-            &Opcode::Bnz => InstructionFormat::III,
-            &Opcode::Bh => InstructionFormat::III,
-            &Opcode::Bp => InstructionFormat::III,
-            &Opcode::Nop => InstructionFormat::III,
-            &Opcode::Bge => InstructionFormat::III,
-            &Opcode::Bgt => InstructionFormat::III,
-            &Opcode::Movea => InstructionFormat::V,
-            &Opcode::AddImm16 => InstructionFormat::V,
-            &Opcode::Jr => InstructionFormat::IV,
-            &Opcode::OrI => InstructionFormat::V,
-            &Opcode::AndI => InstructionFormat::V,
-            &Opcode::XorI => InstructionFormat::V,
-            &Opcode::Jal => InstructionFormat::IV,
-            &Opcode::Movhi => InstructionFormat::V,
-            &Opcode::Ldb => InstructionFormat::VI,
-            &Opcode::Ldh => InstructionFormat::VI,
-            &Opcode::Ldw => InstructionFormat::VI,
-            &Opcode::Stb => InstructionFormat::VI,
-            &Opcode::Sth => InstructionFormat::VI,
-            &Opcode::Stw => InstructionFormat::VI,
-            &Opcode::Inb => InstructionFormat::VI,
-            &Opcode::Inh => InstructionFormat::VI,
-            &Opcode::Inw => InstructionFormat::VI,
-            &Opcode::Outb => InstructionFormat::VI,
-            &Opcode::Outh => InstructionFormat::VI,
-            &Opcode::Extended => InstructionFormat::VII,
-            &Opcode::Outw => InstructionFormat::VI,
-        }
-    }
-
-    pub fn bit_string_op(&self, bit_string_op: u32) -> BitStringOp {
-        match bit_string_op {
-            OPCODE_BITS_BIT_STRING_OP_ORBSU => BitStringOp::Orbsu,
-            OPCODE_BITS_BIT_STRING_OP_ANDBSU => BitStringOp::Andbsu,
-            OPCODE_BITS_BIT_STRING_OP_XORBSU => BitStringOp::Xorbsu,
-            OPCODE_BITS_BIT_STRING_OP_MOVBSU => BitStringOp::Movbsu,
-            OPCODE_BITS_BIT_STRING_OP_ORNBSU => BitStringOp::Ornbsu,
-            OPCODE_BITS_BIT_STRING_OP_ANDNBSU => BitStringOp::Andnbsu,
-            OPCODE_BITS_BIT_STRING_OP_XORNBSU => BitStringOp::Xornbsu,
-            OPCODE_BITS_BIT_STRING_OP_NOTBSU => BitStringOp::Notbsu,
-            _ => panic!("Unrecognized bit string op: {:05b}", bit_string_op)
-        }
-    }
-
-    pub fn subop(&self, subop: u16) -> SubOp {
-        match subop {
-            OPCODE_BITS_SUB_OP_CMPF_S => SubOp::CmpfS,
-            OPCODE_BITS_SUB_OP_CVT_WS => SubOp::CvtWs,
-            OPCODE_BITS_SUB_OP_CVT_SW => SubOp::CvtSw,
-            OPCODE_BITS_SUB_OP_ADDF_S => SubOp::AddfS,
-            OPCODE_BITS_SUB_OP_SUBF_S => SubOp::SubfS,
-            OPCODE_BITS_SUB_OP_MULF_S => SubOp::MulfS,
-            OPCODE_BITS_SUB_OP_DIVF_S => SubOp::DivfS,
-            OPCODE_BITS_SUB_OP_XB => SubOp::Xb,
-            OPCODE_BITS_SUB_OP_XH => SubOp::Xh,
-            OPCODE_BITS_SUB_OP_REV => SubOp::Rev,
-            OPCODE_BITS_SUB_OP_TRNC_SW => SubOp::TrncSw,
-            OPCODE_BITS_SUB_OP_MPYHW => SubOp::Mpyhw,
-            _ => panic!("Unrecognized subop bits: {:06b}", subop),
-        }
-    }
-
-    pub fn system_register(&self, imm5: u32) -> SystemRegister {
-        match imm5 {
-            OPCODE_SYSTEM_REGISTER_ID_EIPC => SystemRegister::Eipc,
-            OPCODE_SYSTEM_REGISTER_ID_EIPSW => SystemRegister::Eipsw,
-            OPCODE_SYSTEM_REGISTER_ID_FEPC => SystemRegister::Fepc,
-            OPCODE_SYSTEM_REGISTER_ID_FEPSW => SystemRegister::Fepsw,
-            OPCODE_SYSTEM_REGISTER_ID_ECR => SystemRegister::Ecr,
-            OPCODE_SYSTEM_REGISTER_ID_PSW => SystemRegister::Psw,
-            OPCODE_SYSTEM_REGISTER_ID_CHCW => SystemRegister::Chcw,
-            _ => SystemRegister::Unknown(imm5),
-        }
-    }
-}
-
-impl fmt::Display for Opcode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mnemonic = match self {
-            &Opcode::MovReg | &Opcode::MovImm => "mov",
-            &Opcode::AddReg | &Opcode::AddImm5 => "add",
-            &Opcode::Sub => "sub",
-            &Opcode::CmpReg | &Opcode::CmpImm => "cmp",
-            &Opcode::ShlReg | &Opcode::ShlImm => "shl",
-            &Opcode::ShrReg | &Opcode::ShrImm => "shr",
-            &Opcode::Jmp => "jmp",
-            &Opcode::SarReg | &Opcode::SarImm => "sar",
-            &Opcode::Mul => "mul",
-            &Opcode::Div => "div",
-            &Opcode::MulU => "mulu",
-            &Opcode::DivU => "divu",
-            &Opcode::Or => "or",
-            &Opcode::And => "and",
-            &Opcode::Xor => "xor",
-            &Opcode::Not => "not",
-            &Opcode::Setf => "setf",
-            &Opcode::Cli => "cli",
-            &Opcode::Reti => "reti",
-            &Opcode::Halt => "halt",
-            &Opcode::Ldsr => "ldsr",
-            &Opcode::Stsr => "stsr",
-            &Opcode::Sei => "sei",
-            &Opcode::BitString => unreachable!(), // TODO: Better pattern
-            &Opcode::Bv => "bv",
-            &Opcode::Bc => "bc",
-            &Opcode::Bz => "bz",
-            &Opcode::Bnh => "bnh",
-            &Opcode::Bn => "bn",
-            &Opcode::Br => "br",
-            &Opcode::Blt => "blt",
-            &Opcode::Ble => "ble",
-            &Opcode::Bnv => "bnv",
-            &Opcode::Bnc => "bnc",
-            &Opcode::Bnz => "bnz",
-            &Opcode::Bh => "bh",
-            &Opcode::Bp => "bp",
-            &Opcode::Nop => "nop",
-            &Opcode::Bge => "bge",
-            &Opcode::Bgt => "bgt",
-            &Opcode::Movea => "movea",
-            &Opcode::AddImm16 => "addi",
-            &Opcode::Jr => "jr",
-            &Opcode::Jal => "jal",
-            &Opcode::OrI => "ori",
-            &Opcode::AndI => "andi",
-            &Opcode::XorI => "xori",
-            &Opcode::Movhi => "movhi",
-            &Opcode::Ldb => "ld.b",
-            &Opcode::Ldh => "ld.h",
-            &Opcode::Ldw => "ld.w",
-            &Opcode::Stb => "st.b",
-            &Opcode::Sth => "st.h",
-            &Opcode::Stw => "st.w",
-            &Opcode::Inb => "in.b",
-            &Opcode::Inh => "in.h",
-            &Opcode::Inw => "in.w",
-            &Opcode::Outb => "out.b",
-            &Opcode::Outh => "out.h",
-            &Opcode::Extended => unreachable!(), // TODO: Better pattern
-            &Opcode::Outw => "out.w",
-        };
-        write!(f, "{}", mnemonic)
-    }
-}
-
-pub enum InstructionFormat {
-    I,
-    II,
-    III,
-    IV,
-    V,
-    VI,
-    VII,
-}
-
-impl InstructionFormat {
-    pub fn has_second_halfword(&self) -> bool {
-        match self {
-            &InstructionFormat::I => false,
-            &InstructionFormat::II => false,
-            &InstructionFormat::III => false,
-            &InstructionFormat::IV => true,
-            &InstructionFormat::V => true,
-            &InstructionFormat::VI => true,
-            &InstructionFormat::VII => true,
-        }
-    }
-}
-
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum BitStringOp {
+    Sch0bsu,
+    Sch0bsd,
+    Sch1bsu,
+    Sch1bsd,
     Orbsu,
     Andbsu,
     Xorbsu,
@@ -493,9 +141,48 @@ pub enum BitStringOp {
     Notbsu,
 }
 
-impl fmt::Display for BitStringOp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mnemonic = match self {
+impl BitStringOp {
+    pub fn from_bits(bits: u32) -> Option<BitStringOp> {
+        match bits {
+            OPCODE_BITS_BIT_STRING_OP_SCH0BSU => Some(BitStringOp::Sch0bsu),
+            OPCODE_BITS_BIT_STRING_OP_SCH0BSD => Some(BitStringOp::Sch0bsd),
+            OPCODE_BITS_BIT_STRING_OP_SCH1BSU => Some(BitStringOp::Sch1bsu),
+            OPCODE_BITS_BIT_STRING_OP_SCH1BSD => Some(BitStringOp::Sch1bsd),
+            OPCODE_BITS_BIT_STRING_OP_ORBSU => Some(BitStringOp::Orbsu),
+            OPCODE_BITS_BIT_STRING_OP_ANDBSU => Some(BitStringOp::Andbsu),
+            OPCODE_BITS_BIT_STRING_OP_XORBSU => Some(BitStringOp::Xorbsu),
+            OPCODE_BITS_BIT_STRING_OP_MOVBSU => Some(BitStringOp::Movbsu),
+            OPCODE_BITS_BIT_STRING_OP_ORNBSU => Some(BitStringOp::Ornbsu),
+            OPCODE_BITS_BIT_STRING_OP_ANDNBSU => Some(BitStringOp::Andnbsu),
+            OPCODE_BITS_BIT_STRING_OP_XORNBSU => Some(BitStringOp::Xornbsu),
+            OPCODE_BITS_BIT_STRING_OP_NOTBSU => Some(BitStringOp::Notbsu),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            &BitStringOp::Sch0bsu => OPCODE_BITS_BIT_STRING_OP_SCH0BSU,
+            &BitStringOp::Sch0bsd => OPCODE_BITS_BIT_STRING_OP_SCH0BSD,
+            &BitStringOp::Sch1bsu => OPCODE_BITS_BIT_STRING_OP_SCH1BSU,
+            &BitStringOp::Sch1bsd => OPCODE_BITS_BIT_STRING_OP_SCH1BSD,
+            &BitStringOp::Orbsu => OPCODE_BITS_BIT_STRING_OP_ORBSU,
+            &BitStringOp::Andbsu => OPCODE_BITS_BIT_STRING_OP_ANDBSU,
+            &BitStringOp::Xorbsu => OPCODE_BITS_BIT_STRING_OP_XORBSU,
+            &BitStringOp::Movbsu => OPCODE_BITS_BIT_STRING_OP_MOVBSU,
+            &BitStringOp::Ornbsu => OPCODE_BITS_BIT_STRING_OP_ORNBSU,
+            &BitStringOp::Andnbsu => OPCODE_BITS_BIT_STRING_OP_ANDNBSU,
+            &BitStringOp::Xornbsu => OPCODE_BITS_BIT_STRING_OP_XORNBSU,
+            &BitStringOp::Notbsu => OPCODE_BITS_BIT_STRING_OP_NOTBSU,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            &BitStringOp::Sch0bsu => "sch0bsu",
+            &BitStringOp::Sch0bsd => "sch0bsd",
+            &BitStringOp::Sch1bsu => "sch1bsu",
+            &BitStringOp::Sch1bsd => "sch1bsd",
             &BitStringOp::Orbsu => "orbsu",
             &BitStringOp::Andbsu => "andbsu",
             &BitStringOp::Xorbsu => "xorbsu",
@@ -504,11 +191,17 @@ impl fmt::Display for BitStringOp {
             &BitStringOp::Andnbsu => "andnbsu",
             &BitStringOp::Xornbsu => "xornbsu",
             &BitStringOp::Notbsu => "notbsu",
-        };
-        write!(f, "{}", mnemonic)
+        }
     }
 }
 
+impl fmt::Display for BitStringOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SubOp {
     CmpfS,
     CvtWs,
@@ -524,9 +217,44 @@ pub enum SubOp {
     Mpyhw,
 }
 
-impl fmt::Display for SubOp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mnemonic = match self {
+impl SubOp {
+    pub fn from_bits(bits: u16) -> Option<SubOp> {
+        match bits {
+            OPCODE_BITS_SUB_OP_CMPF_S => Some(SubOp::CmpfS),
+            OPCODE_BITS_SUB_OP_CVT_WS => Some(SubOp::CvtWs),
+            OPCODE_BITS_SUB_OP_CVT_SW => Some(SubOp::CvtSw),
+            OPCODE_BITS_SUB_OP_ADDF_S => Some(SubOp::AddfS),
+            OPCODE_BITS_SUB_OP_SUBF_S => Some(SubOp::SubfS),
+            OPCODE_BITS_SUB_OP_MULF_S => Some(SubOp::MulfS),
+            OPCODE_BITS_SUB_OP_DIVF_S => Some(SubOp::DivfS),
+            OPCODE_BITS_SUB_OP_XB => Some(SubOp::Xb),
+            OPCODE_BITS_SUB_OP_XH => Some(SubOp::Xh),
+            OPCODE_BITS_SUB_OP_REV => Some(SubOp::Rev),
+            OPCODE_BITS_SUB_OP_TRNC_SW => Some(SubOp::TrncSw),
+            OPCODE_BITS_SUB_OP_MPYHW => Some(SubOp::Mpyhw),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u16 {
+        match self {
+            &SubOp::CmpfS => OPCODE_BITS_SUB_OP_CMPF_S,
+            &SubOp::CvtWs => OPCODE_BITS_SUB_OP_CVT_WS,
+            &SubOp::CvtSw => OPCODE_BITS_SUB_OP_CVT_SW,
+            &SubOp::AddfS => OPCODE_BITS_SUB_OP_ADDF_S,
+            &SubOp::SubfS => OPCODE_BITS_SUB_OP_SUBF_S,
+            &SubOp::MulfS => OPCODE_BITS_SUB_OP_MULF_S,
+            &SubOp::DivfS => OPCODE_BITS_SUB_OP_DIVF_S,
+            &SubOp::Xb => OPCODE_BITS_SUB_OP_XB,
+            &SubOp::Xh => OPCODE_BITS_SUB_OP_XH,
+            &SubOp::Rev => OPCODE_BITS_SUB_OP_REV,
+            &SubOp::TrncSw => OPCODE_BITS_SUB_OP_TRNC_SW,
+            &SubOp::Mpyhw => OPCODE_BITS_SUB_OP_MPYHW,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
             &SubOp::CmpfS => "cmpf.s",
             &SubOp::CvtWs => "cvt.ws",
             &SubOp::CvtSw => "cvt.sw",
@@ -539,11 +267,17 @@ impl fmt::Display for SubOp {
             &SubOp::Rev => "rev",
             &SubOp::TrncSw => "trnc.sw",
             &SubOp::Mpyhw => "mpyhw",
-        };
-        write!(f, "{}", mnemonic)
+        }
     }
 }
 
+impl fmt::Display for SubOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SystemRegister {
     Eipc,
     Eipsw,
@@ -552,9 +286,40 @@ pub enum SystemRegister {
     Ecr,
     Psw,
     Chcw,
+    Adtre,
     Unknown(u32),
 }
 
+impl SystemRegister {
+    pub fn from_id(id: u32) -> SystemRegister {
+        match id {
+            OPCODE_SYSTEM_REGISTER_ID_EIPC => SystemRegister::Eipc,
+            OPCODE_SYSTEM_REGISTER_ID_EIPSW => SystemRegister::Eipsw,
+            OPCODE_SYSTEM_REGISTER_ID_FEPC => SystemRegister::Fepc,
+            OPCODE_SYSTEM_REGISTER_ID_FEPSW => SystemRegister::Fepsw,
+            OPCODE_SYSTEM_REGISTER_ID_ECR => SystemRegister::Ecr,
+            OPCODE_SYSTEM_REGISTER_ID_PSW => SystemRegister::Psw,
+            OPCODE_SYSTEM_REGISTER_ID_CHCW => SystemRegister::Chcw,
+            OPCODE_SYSTEM_REGISTER_ID_ADTRE => SystemRegister::Adtre,
+            _ => SystemRegister::Unknown(id),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            &SystemRegister::Eipc => OPCODE_SYSTEM_REGISTER_ID_EIPC,
+            &SystemRegister::Eipsw => OPCODE_SYSTEM_REGISTER_ID_EIPSW,
+            &SystemRegister::Fepc => OPCODE_SYSTEM_REGISTER_ID_FEPC,
+            &SystemRegister::Fepsw => OPCODE_SYSTEM_REGISTER_ID_FEPSW,
+            &SystemRegister::Ecr => OPCODE_SYSTEM_REGISTER_ID_ECR,
+            &SystemRegister::Psw => OPCODE_SYSTEM_REGISTER_ID_PSW,
+            &SystemRegister::Chcw => OPCODE_SYSTEM_REGISTER_ID_CHCW,
+            &SystemRegister::Adtre => OPCODE_SYSTEM_REGISTER_ID_ADTRE,
+            &SystemRegister::Unknown(id) => id,
+        }
+    }
+}
+
 impl fmt::Display for SystemRegister {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -565,7 +330,483 @@ impl fmt::Display for SystemRegister {
             &SystemRegister::Ecr => write!(f, "{}", "ecr"),
             &SystemRegister::Psw => write!(f, "{}", "psw"),
             &SystemRegister::Chcw => write!(f, "{}", "chcw"),
+            &SystemRegister::Adtre => write!(f, "{}", "adtre"),
             &SystemRegister::Unknown(imm5) => write!(f, "??? ({})", imm5),
         }
     }
 }
+
+// Shared by bcond (the 4 bits after the prefix) and setf (the low 4 bits of imm5). The upper 8
+//  conditions are the negations of the lower 8.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    V = 0x00,
+    C = 0x01,
+    Z = 0x02,
+    Nh = 0x03,
+    N = 0x04,
+    T = 0x05,
+    Lt = 0x06,
+    Le = 0x07,
+    Nv = 0x08,
+    Nc = 0x09,
+    Nz = 0x0a,
+    H = 0x0b,
+    P = 0x0c,
+    F = 0x0d,
+    Ge = 0x0e,
+    Gt = 0x0f,
+}
+
+impl Condition {
+    pub fn from_bits(bits: u32) -> Condition {
+        match bits & 0x0f {
+            OPCODE_CONDITION_BITS_V => Condition::V,
+            OPCODE_CONDITION_BITS_C => Condition::C,
+            OPCODE_CONDITION_BITS_Z => Condition::Z,
+            OPCODE_CONDITION_BITS_NH => Condition::Nh,
+            OPCODE_CONDITION_BITS_N => Condition::N,
+            OPCODE_CONDITION_BITS_T => Condition::T,
+            OPCODE_CONDITION_BITS_LT => Condition::Lt,
+            OPCODE_CONDITION_BITS_LE => Condition::Le,
+            OPCODE_CONDITION_BITS_NV => Condition::Nv,
+            OPCODE_CONDITION_BITS_NC => Condition::Nc,
+            OPCODE_CONDITION_BITS_NZ => Condition::Nz,
+            OPCODE_CONDITION_BITS_H => Condition::H,
+            OPCODE_CONDITION_BITS_P => Condition::P,
+            OPCODE_CONDITION_BITS_F => Condition::F,
+            OPCODE_CONDITION_BITS_GE => Condition::Ge,
+            _ => Condition::Gt,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &Condition::V => "v",
+            &Condition::C => "c",
+            &Condition::Z => "z",
+            &Condition::Nh => "nh",
+            &Condition::N => "n",
+            &Condition::T => "t",
+            &Condition::Lt => "lt",
+            &Condition::Le => "le",
+            &Condition::Nv => "nv",
+            &Condition::Nc => "nc",
+            &Condition::Nz => "nz",
+            &Condition::H => "h",
+            &Condition::P => "p",
+            &Condition::F => "f",
+            &Condition::Ge => "ge",
+            &Condition::Gt => "gt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Formats IV-VII are the only 32-bit formats, and their opcodes all sort after the bcond prefix
+pub fn is_long_instruction(first_halfword: u16) -> bool {
+    first_halfword >> 13 > OPCODE_BITS_BCOND_PREFIX
+}
+
+// A fully decoded instruction. Register numbers and immediates are pulled out of the encoding up
+//  front, with immediates the CPU treats as signed already sign-extended. Branch and jump
+//  displacements are kept as encoded; the CPU ignores their lowest bit.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    MovReg { reg1: usize, reg2: usize },
+    AddReg { reg1: usize, reg2: usize },
+    Sub { reg1: usize, reg2: usize },
+    CmpReg { reg1: usize, reg2: usize },
+    ShlReg { reg1: usize, reg2: usize },
+    ShrReg { reg1: usize, reg2: usize },
+    Jmp { reg1: usize },
+    SarReg { reg1: usize, reg2: usize },
+    Mul { reg1: usize, reg2: usize },
+    Div { reg1: usize, reg2: usize },
+    MulU { reg1: usize, reg2: usize },
+    DivU { reg1: usize, reg2: usize },
+    Or { reg1: usize, reg2: usize },
+    And { reg1: usize, reg2: usize },
+    Xor { reg1: usize, reg2: usize },
+    Not { reg1: usize, reg2: usize },
+    MovImm { imm5: i32, reg2: usize },
+    AddImm5 { imm5: i32, reg2: usize },
+    Setf { condition: Condition, reg2: usize },
+    CmpImm { imm5: i32, reg2: usize },
+    ShlImm { imm5: u32, reg2: usize },
+    ShrImm { imm5: u32, reg2: usize },
+    Cli,
+    SarImm { imm5: u32, reg2: usize },
+    Trap { vector: u32 },
+    Reti,
+    Halt,
+    Ldsr { reg2: usize, system_register: SystemRegister },
+    Stsr { system_register: SystemRegister, reg2: usize },
+    Sei,
+    BitString(BitStringOp),
+    Bcond { condition: Condition, disp: i32 },
+    Movea { imm16: u16, reg1: usize, reg2: usize },
+    AddImm16 { imm16: u16, reg1: usize, reg2: usize },
+    Jr { disp: i32 },
+    Jal { disp: i32 },
+    OrI { imm16: u16, reg1: usize, reg2: usize },
+    AndI { imm16: u16, reg1: usize, reg2: usize },
+    XorI { imm16: u16, reg1: usize, reg2: usize },
+    Movhi { imm16: u16, reg1: usize, reg2: usize },
+    Ldb { disp16: i16, reg1: usize, reg2: usize },
+    Ldh { disp16: i16, reg1: usize, reg2: usize },
+    Ldw { disp16: i16, reg1: usize, reg2: usize },
+    Stb { disp16: i16, reg1: usize, reg2: usize },
+    Sth { disp16: i16, reg1: usize, reg2: usize },
+    Stw { disp16: i16, reg1: usize, reg2: usize },
+    Inb { disp16: i16, reg1: usize, reg2: usize },
+    Inh { disp16: i16, reg1: usize, reg2: usize },
+    Caxi { disp16: i16, reg1: usize, reg2: usize },
+    Inw { disp16: i16, reg1: usize, reg2: usize },
+    Outb { disp16: i16, reg1: usize, reg2: usize },
+    Outh { disp16: i16, reg1: usize, reg2: usize },
+    Outw { disp16: i16, reg1: usize, reg2: usize },
+    Extended { subop: SubOp, reg1: usize, reg2: usize },
+    // Unused opcode, bit string op or subop; executing one raises an invalid opcode exception
+    Illegal { first_halfword: u16, second_halfword: u16 },
+}
+
+impl Instruction {
+    // second_halfword is ignored for 16-bit instructions (see is_long_instruction)
+    pub fn decode(first_halfword: u16, second_halfword: u16) -> Instruction {
+        let reg1 = (first_halfword & 0x1f) as usize;
+        let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
+        let imm5 = (first_halfword & 0x1f) as u32;
+        let simm5 = ((imm5 as i32) << 27) >> 27;
+        let imm16 = second_halfword;
+        let disp16 = second_halfword as i16;
+
+        if first_halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
+            return Instruction::Bcond {
+                condition: Condition::from_bits((first_halfword >> 9) as u32),
+                disp: (((first_halfword as i16) << 7) >> 7) as i32,
+            };
+        }
+
+        match first_halfword >> 10 {
+            OPCODE_BITS_MOV_REG => Instruction::MovReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_ADD_REG => Instruction::AddReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_SUB => Instruction::Sub { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_CMP_REG => Instruction::CmpReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_SHL_REG => Instruction::ShlReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_SHR_REG => Instruction::ShrReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_JMP => Instruction::Jmp { reg1: reg1 },
+            OPCODE_BITS_SAR_REG => Instruction::SarReg { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_MUL => Instruction::Mul { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_DIV => Instruction::Div { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_MUL_U => Instruction::MulU { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_DIV_U => Instruction::DivU { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_OR => Instruction::Or { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_AND => Instruction::And { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_XOR => Instruction::Xor { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_NOT => Instruction::Not { reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_MOV_IMM => Instruction::MovImm { imm5: simm5, reg2: reg2 },
+            OPCODE_BITS_ADD_IMM_5 => Instruction::AddImm5 { imm5: simm5, reg2: reg2 },
+            OPCODE_BITS_SETF => Instruction::Setf { condition: Condition::from_bits(imm5), reg2: reg2 },
+            OPCODE_BITS_CMP_IMM => Instruction::CmpImm { imm5: simm5, reg2: reg2 },
+            OPCODE_BITS_SHL_IMM => Instruction::ShlImm { imm5: imm5, reg2: reg2 },
+            OPCODE_BITS_SHR_IMM => Instruction::ShrImm { imm5: imm5, reg2: reg2 },
+            OPCODE_BITS_CLI => Instruction::Cli,
+            OPCODE_BITS_SAR_IMM => Instruction::SarImm { imm5: imm5, reg2: reg2 },
+            OPCODE_BITS_TRAP => Instruction::Trap { vector: imm5 },
+            OPCODE_BITS_RETI => Instruction::Reti,
+            OPCODE_BITS_HALT => Instruction::Halt,
+            OPCODE_BITS_LDSR => Instruction::Ldsr { reg2: reg2, system_register: SystemRegister::from_id(imm5) },
+            OPCODE_BITS_STSR => Instruction::Stsr { system_register: SystemRegister::from_id(imm5), reg2: reg2 },
+            OPCODE_BITS_SEI => Instruction::Sei,
+            OPCODE_BITS_BIT_STRING => match BitStringOp::from_bits(imm5) {
+                Some(op) => Instruction::BitString(op),
+                _ => Instruction::Illegal { first_halfword: first_halfword, second_halfword: 0 },
+            },
+            OPCODE_BITS_MOVEA => Instruction::Movea { imm16: imm16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_ADD_IMM_16 => Instruction::AddImm16 { imm16: imm16, reg1: reg1, reg2: reg2 },
+            opcode_bits @ OPCODE_BITS_JR | opcode_bits @ OPCODE_BITS_JAL => {
+                let disp = ((((first_halfword as i16) << 6) >> 6) as i32) << 16 | (second_halfword as i32);
+                if opcode_bits == OPCODE_BITS_JR {
+                    Instruction::Jr { disp: disp }
+                } else {
+                    Instruction::Jal { disp: disp }
+                }
+            }
+            OPCODE_BITS_OR_I => Instruction::OrI { imm16: imm16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_AND_I => Instruction::AndI { imm16: imm16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_XOR_I => Instruction::XorI { imm16: imm16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_MOVHI => Instruction::Movhi { imm16: imm16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_LDB => Instruction::Ldb { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_LDH => Instruction::Ldh { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_LDW => Instruction::Ldw { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_STB => Instruction::Stb { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_STH => Instruction::Sth { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_STW => Instruction::Stw { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_INB => Instruction::Inb { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_INH => Instruction::Inh { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_CAXI => Instruction::Caxi { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_INW => Instruction::Inw { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_OUTB => Instruction::Outb { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_OUTH => Instruction::Outh { disp16: disp16, reg1: reg1, reg2: reg2 },
+            OPCODE_BITS_EXTENDED => match SubOp::from_bits(second_halfword >> 10) {
+                Some(subop) => Instruction::Extended { subop: subop, reg1: reg1, reg2: reg2 },
+                _ => Instruction::Illegal { first_halfword: first_halfword, second_halfword: second_halfword },
+            },
+            OPCODE_BITS_OUTW => Instruction::Outw { disp16: disp16, reg1: reg1, reg2: reg2 },
+            _ => Instruction::Illegal {
+                first_halfword: first_halfword,
+                second_halfword: if is_long_instruction(first_halfword) { second_halfword } else { 0 },
+            },
+        }
+    }
+
+    // The inverse of decode. The second halfword is 0 for 16-bit instructions.
+    pub fn encode(&self) -> (u16, u16) {
+        fn format_i(opcode_bits: u16, reg1: usize, reg2: usize) -> u16 {
+            (opcode_bits << 10) | (((reg2 & 0x1f) as u16) << 5) | ((reg1 & 0x1f) as u16)
+        }
+
+        fn format_iv(opcode_bits: u16, disp: i32) -> (u16, u16) {
+            ((opcode_bits << 10) | (((disp >> 16) as u16) & 0x03ff), disp as u16)
+        }
+
+        match self {
+            &Instruction::MovReg { reg1, reg2 } => (format_i(OPCODE_BITS_MOV_REG, reg1, reg2), 0),
+            &Instruction::AddReg { reg1, reg2 } => (format_i(OPCODE_BITS_ADD_REG, reg1, reg2), 0),
+            &Instruction::Sub { reg1, reg2 } => (format_i(OPCODE_BITS_SUB, reg1, reg2), 0),
+            &Instruction::CmpReg { reg1, reg2 } => (format_i(OPCODE_BITS_CMP_REG, reg1, reg2), 0),
+            &Instruction::ShlReg { reg1, reg2 } => (format_i(OPCODE_BITS_SHL_REG, reg1, reg2), 0),
+            &Instruction::ShrReg { reg1, reg2 } => (format_i(OPCODE_BITS_SHR_REG, reg1, reg2), 0),
+            &Instruction::Jmp { reg1 } => (format_i(OPCODE_BITS_JMP, reg1, 0), 0),
+            &Instruction::SarReg { reg1, reg2 } => (format_i(OPCODE_BITS_SAR_REG, reg1, reg2), 0),
+            &Instruction::Mul { reg1, reg2 } => (format_i(OPCODE_BITS_MUL, reg1, reg2), 0),
+            &Instruction::Div { reg1, reg2 } => (format_i(OPCODE_BITS_DIV, reg1, reg2), 0),
+            &Instruction::MulU { reg1, reg2 } => (format_i(OPCODE_BITS_MUL_U, reg1, reg2), 0),
+            &Instruction::DivU { reg1, reg2 } => (format_i(OPCODE_BITS_DIV_U, reg1, reg2), 0),
+            &Instruction::Or { reg1, reg2 } => (format_i(OPCODE_BITS_OR, reg1, reg2), 0),
+            &Instruction::And { reg1, reg2 } => (format_i(OPCODE_BITS_AND, reg1, reg2), 0),
+            &Instruction::Xor { reg1, reg2 } => (format_i(OPCODE_BITS_XOR, reg1, reg2), 0),
+            &Instruction::Not { reg1, reg2 } => (format_i(OPCODE_BITS_NOT, reg1, reg2), 0),
+            &Instruction::MovImm { imm5, reg2 } => (format_i(OPCODE_BITS_MOV_IMM, imm5 as usize, reg2), 0),
+            &Instruction::AddImm5 { imm5, reg2 } => (format_i(OPCODE_BITS_ADD_IMM_5, imm5 as usize, reg2), 0),
+            &Instruction::Setf { condition, reg2 } => (format_i(OPCODE_BITS_SETF, condition as usize, reg2), 0),
+            &Instruction::CmpImm { imm5, reg2 } => (format_i(OPCODE_BITS_CMP_IMM, imm5 as usize, reg2), 0),
+            &Instruction::ShlImm { imm5, reg2 } => (format_i(OPCODE_BITS_SHL_IMM, imm5 as usize, reg2), 0),
+            &Instruction::ShrImm { imm5, reg2 } => (format_i(OPCODE_BITS_SHR_IMM, imm5 as usize, reg2), 0),
+            &Instruction::Cli => (format_i(OPCODE_BITS_CLI, 0, 0), 0),
+            &Instruction::SarImm { imm5, reg2 } => (format_i(OPCODE_BITS_SAR_IMM, imm5 as usize, reg2), 0),
+            &Instruction::Trap { vector } => (format_i(OPCODE_BITS_TRAP, vector as usize, 0), 0),
+            &Instruction::Reti => (format_i(OPCODE_BITS_RETI, 0, 0), 0),
+            &Instruction::Halt => (format_i(OPCODE_BITS_HALT, 0, 0), 0),
+            &Instruction::Ldsr { reg2, system_register } => (format_i(OPCODE_BITS_LDSR, system_register.id() as usize, reg2), 0),
+            &Instruction::Stsr { system_register, reg2 } => (format_i(OPCODE_BITS_STSR, system_register.id() as usize, reg2), 0),
+            &Instruction::Sei => (format_i(OPCODE_BITS_SEI, 0, 0), 0),
+            &Instruction::BitString(op) => (format_i(OPCODE_BITS_BIT_STRING, op.bits() as usize, 0), 0),
+            &Instruction::Bcond { condition, disp } => ((OPCODE_BITS_BCOND_PREFIX << 13) | ((condition as u16) << 9) | ((disp as u16) & 0x01ff), 0),
+            &Instruction::Movea { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_MOVEA, reg1, reg2), imm16),
+            &Instruction::AddImm16 { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_ADD_IMM_16, reg1, reg2), imm16),
+            &Instruction::Jr { disp } => format_iv(OPCODE_BITS_JR, disp),
+            &Instruction::Jal { disp } => format_iv(OPCODE_BITS_JAL, disp),
+            &Instruction::OrI { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_OR_I, reg1, reg2), imm16),
+            &Instruction::AndI { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_AND_I, reg1, reg2), imm16),
+            &Instruction::XorI { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_XOR_I, reg1, reg2), imm16),
+            &Instruction::Movhi { imm16, reg1, reg2 } => (format_i(OPCODE_BITS_MOVHI, reg1, reg2), imm16),
+            &Instruction::Ldb { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_LDB, reg1, reg2), disp16 as u16),
+            &Instruction::Ldh { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_LDH, reg1, reg2), disp16 as u16),
+            &Instruction::Ldw { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_LDW, reg1, reg2), disp16 as u16),
+            &Instruction::Stb { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_STB, reg1, reg2), disp16 as u16),
+            &Instruction::Sth { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_STH, reg1, reg2), disp16 as u16),
+            &Instruction::Stw { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_STW, reg1, reg2), disp16 as u16),
+            &Instruction::Inb { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_INB, reg1, reg2), disp16 as u16),
+            &Instruction::Inh { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_INH, reg1, reg2), disp16 as u16),
+            &Instruction::Caxi { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_CAXI, reg1, reg2), disp16 as u16),
+            &Instruction::Inw { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_INW, reg1, reg2), disp16 as u16),
+            &Instruction::Outb { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_OUTB, reg1, reg2), disp16 as u16),
+            &Instruction::Outh { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_OUTH, reg1, reg2), disp16 as u16),
+            &Instruction::Outw { disp16, reg1, reg2 } => (format_i(OPCODE_BITS_OUTW, reg1, reg2), disp16 as u16),
+            &Instruction::Extended { subop, reg1, reg2 } => (format_i(OPCODE_BITS_EXTENDED, reg1, reg2), subop.bits() << 10),
+            &Instruction::Illegal { first_halfword, second_halfword } => (first_halfword, second_halfword),
+        }
+    }
+
+    pub fn is_long(&self) -> bool {
+        match self {
+            &Instruction::Jr { .. } |
+            &Instruction::Jal { .. } |
+            &Instruction::Movea { .. } |
+            &Instruction::AddImm16 { .. } |
+            &Instruction::OrI { .. } |
+            &Instruction::AndI { .. } |
+            &Instruction::XorI { .. } |
+            &Instruction::Movhi { .. } |
+            &Instruction::Ldb { .. } |
+            &Instruction::Ldh { .. } |
+            &Instruction::Ldw { .. } |
+            &Instruction::Stb { .. } |
+            &Instruction::Sth { .. } |
+            &Instruction::Stw { .. } |
+            &Instruction::Inb { .. } |
+            &Instruction::Inh { .. } |
+            &Instruction::Caxi { .. } |
+            &Instruction::Inw { .. } |
+            &Instruction::Outb { .. } |
+            &Instruction::Outh { .. } |
+            &Instruction::Outw { .. } |
+            &Instruction::Extended { .. } => true,
+            &Instruction::Illegal { first_halfword, .. } => is_long_instruction(first_halfword),
+            _ => false,
+        }
+    }
+
+    // Where a branch or jump with a fixed target at pc goes if it's taken
+    pub fn branch_target(&self, pc: u32) -> Option<u32> {
+        match self {
+            &Instruction::Bcond { condition: Condition::F, .. } => None,
+            &Instruction::Bcond { disp, .. } | &Instruction::Jr { disp } | &Instruction::Jal { disp } => Some(pc.wrapping_add((disp as u32) & 0xfffffffe)),
+            _ => None,
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            &Instruction::MovReg { .. } | &Instruction::MovImm { .. } => "mov",
+            &Instruction::AddReg { .. } | &Instruction::AddImm5 { .. } => "add",
+            &Instruction::Sub { .. } => "sub",
+            &Instruction::CmpReg { .. } | &Instruction::CmpImm { .. } => "cmp",
+            &Instruction::ShlReg { .. } | &Instruction::ShlImm { .. } => "shl",
+            &Instruction::ShrReg { .. } | &Instruction::ShrImm { .. } => "shr",
+            &Instruction::Jmp { .. } => "jmp",
+            &Instruction::SarReg { .. } | &Instruction::SarImm { .. } => "sar",
+            &Instruction::Mul { .. } => "mul",
+            &Instruction::Div { .. } => "div",
+            &Instruction::MulU { .. } => "mulu",
+            &Instruction::DivU { .. } => "divu",
+            &Instruction::Or { .. } => "or",
+            &Instruction::And { .. } => "and",
+            &Instruction::Xor { .. } => "xor",
+            &Instruction::Not { .. } => "not",
+            &Instruction::Setf { .. } => "setf",
+            &Instruction::Cli => "cli",
+            &Instruction::Trap { .. } => "trap",
+            &Instruction::Reti => "reti",
+            &Instruction::Halt => "halt",
+            &Instruction::Ldsr { .. } => "ldsr",
+            &Instruction::Stsr { .. } => "stsr",
+            &Instruction::Sei => "sei",
+            &Instruction::Movea { .. } => "movea",
+            &Instruction::AddImm16 { .. } => "addi",
+            &Instruction::Jr { .. } => "jr",
+            &Instruction::Jal { .. } => "jal",
+            &Instruction::OrI { .. } => "ori",
+            &Instruction::AndI { .. } => "andi",
+            &Instruction::XorI { .. } => "xori",
+            &Instruction::Movhi { .. } => "movhi",
+            &Instruction::Ldb { .. } => "ld.b",
+            &Instruction::Ldh { .. } => "ld.h",
+            &Instruction::Ldw { .. } => "ld.w",
+            &Instruction::Stb { .. } => "st.b",
+            &Instruction::Sth { .. } => "st.h",
+            &Instruction::Stw { .. } => "st.w",
+            &Instruction::Inb { .. } => "in.b",
+            &Instruction::Inh { .. } => "in.h",
+            &Instruction::Caxi { .. } => "caxi",
+            &Instruction::Inw { .. } => "in.w",
+            &Instruction::Outb { .. } => "out.b",
+            &Instruction::Outh { .. } => "out.h",
+            &Instruction::Outw { .. } => "out.w",
+            &Instruction::BitString(op) => op.mnemonic(),
+            &Instruction::Extended { subop, .. } => subop.mnemonic(),
+            &Instruction::Bcond { condition, .. } => match condition {
+                Condition::V => "bv",
+                Condition::C => "bc",
+                Condition::Z => "bz",
+                Condition::Nh => "bnh",
+                Condition::N => "bn",
+                Condition::T => "br",
+                Condition::Lt => "blt",
+                Condition::Le => "ble",
+                Condition::Nv => "bnv",
+                Condition::Nc => "bnc",
+                Condition::Nz => "bnz",
+                Condition::H => "bh",
+                Condition::P => "bp",
+                Condition::F => "nop",
+                Condition::Ge => "bge",
+                Condition::Gt => "bgt",
+            },
+            &Instruction::Illegal { .. } => ".halfword",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Instruction::Jmp { reg1 } => write!(f, "jmp [r{}]", reg1),
+            &Instruction::MovReg { reg1, reg2 } |
+            &Instruction::AddReg { reg1, reg2 } |
+            &Instruction::Sub { reg1, reg2 } |
+            &Instruction::CmpReg { reg1, reg2 } |
+            &Instruction::ShlReg { reg1, reg2 } |
+            &Instruction::ShrReg { reg1, reg2 } |
+            &Instruction::SarReg { reg1, reg2 } |
+            &Instruction::Mul { reg1, reg2 } |
+            &Instruction::Div { reg1, reg2 } |
+            &Instruction::MulU { reg1, reg2 } |
+            &Instruction::DivU { reg1, reg2 } |
+            &Instruction::Or { reg1, reg2 } |
+            &Instruction::And { reg1, reg2 } |
+            &Instruction::Xor { reg1, reg2 } |
+            &Instruction::Not { reg1, reg2 } => write!(f, "{} r{}, r{}", self.mnemonic(), reg1, reg2),
+            &Instruction::MovImm { imm5, reg2 } |
+            &Instruction::AddImm5 { imm5, reg2 } |
+            &Instruction::CmpImm { imm5, reg2 } => write!(f, "{} {}, r{}", self.mnemonic(), imm5, reg2),
+            &Instruction::ShlImm { imm5, reg2 } |
+            &Instruction::ShrImm { imm5, reg2 } |
+            &Instruction::SarImm { imm5, reg2 } => write!(f, "{} {}, r{}", self.mnemonic(), imm5, reg2),
+            &Instruction::Setf { condition, reg2 } => write!(f, "setf {}, r{}", condition, reg2),
+            &Instruction::Cli |
+            &Instruction::Reti |
+            &Instruction::Halt |
+            &Instruction::Sei => write!(f, "{}", self.mnemonic()),
+            &Instruction::Trap { vector } => write!(f, "trap {}", vector),
+            &Instruction::Ldsr { reg2, system_register } => write!(f, "ldsr r{}, {}", reg2, system_register),
+            &Instruction::Stsr { system_register, reg2 } => write!(f, "stsr {}, r{}", system_register, reg2),
+            &Instruction::BitString(_) |
+            &Instruction::Bcond { condition: Condition::F, .. } => write!(f, "{}", self.mnemonic()),
+            &Instruction::Bcond { disp, .. } => write!(f, "{} {}", self.mnemonic(), disp),
+            &Instruction::Jr { disp } |
+            &Instruction::Jal { disp } => write!(f, "{} {}", self.mnemonic(), disp),
+            &Instruction::Movea { imm16, reg1, reg2 } |
+            &Instruction::AddImm16 { imm16, reg1, reg2 } |
+            &Instruction::OrI { imm16, reg1, reg2 } |
+            &Instruction::AndI { imm16, reg1, reg2 } |
+            &Instruction::XorI { imm16, reg1, reg2 } |
+            &Instruction::Movhi { imm16, reg1, reg2 } => write!(f, "{} {:#x}, r{}, r{}", self.mnemonic(), imm16, reg1, reg2),
+            &Instruction::Stb { disp16, reg1, reg2 } |
+            &Instruction::Sth { disp16, reg1, reg2 } |
+            &Instruction::Stw { disp16, reg1, reg2 } |
+            &Instruction::Outb { disp16, reg1, reg2 } |
+            &Instruction::Outh { disp16, reg1, reg2 } |
+            &Instruction::Outw { disp16, reg1, reg2 } => write!(f, "{} r{}, {}[r{}]", self.mnemonic(), reg2, disp16, reg1),
+            &Instruction::Ldb { disp16, reg1, reg2 } |
+            &Instruction::Ldh { disp16, reg1, reg2 } |
+            &Instruction::Ldw { disp16, reg1, reg2 } |
+            &Instruction::Inb { disp16, reg1, reg2 } |
+            &Instruction::Inh { disp16, reg1, reg2 } |
+            &Instruction::Caxi { disp16, reg1, reg2 } |
+            &Instruction::Inw { disp16, reg1, reg2 } => write!(f, "{} {}[r{}], r{}", self.mnemonic(), disp16, reg1, reg2),
+            &Instruction::Extended { reg1, reg2, .. } => write!(f, "{} r{}, r{}", self.mnemonic(), reg1, reg2),
+            &Instruction::Illegal { first_halfword, second_halfword } => {
+                if is_long_instruction(first_halfword) {
+                    write!(f, "{} {:#06x}, {:#06x}", self.mnemonic(), first_halfword, second_halfword)
+                } else {
+                    write!(f, "{} {:#06x}", self.mnemonic(), first_halfword)
+                }
+            }
+        }
+    }
+}
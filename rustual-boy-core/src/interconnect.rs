@@ -1,7 +1,12 @@
+use bus::*;
 use com_port::*;
+use decode_cache::*;
 use game_pad::*;
+use interrupt_controller::*;
 use mem_map::*;
 use rom::*;
+use save_state::*;
+use scheduler::*;
 use sinks::*;
 use sram::*;
 use timer::*;
@@ -9,20 +14,93 @@ use vip::*;
 use vsu::*;
 use wram::*;
 
+use std::ptr;
+
+// Each bus cycle takes 2 cycles plus the region's wait states. The VIP and VSU hold the bus for
+//  longer than memory does; ROM and expansion wait states are selected through WCR.
+const VIP_WAIT_STATES: u32 = 2;
+const VSU_WAIT_STATES: u32 = 1;
+const GAME_PAK_RAM_WAIT_STATES: u32 = 1;
+
+// Memory accesses are dispatched through a table of 64KB pages covering the 128MB address space
+const PAGE_SHIFT: u32 = 16;
+const NUM_PAGES: usize = 1 << (27 - PAGE_SHIFT);
+
+// Pages backed by plain memory (ROM, WRAM and SRAM) are accessed directly through a host pointer;
+//  everything else goes through the device handlers.
+#[derive(Clone, Copy)]
+struct Page {
+    // Backing memory, indexed by addr & mask
+    ptr: *mut u8,
+    mask: u32,
+    // Offsets at or past these go through the device handlers instead. They're 0 for device pages,
+    //  and SRAM's grow along with it.
+    read_limit: u32,
+    write_limit: u32,
+    // Whether writes have to invalidate cached code
+    is_code: bool,
+}
+
+impl Page {
+    fn device() -> Page {
+        Page {
+            ptr: ptr::null_mut(),
+            mask: 0,
+            read_limit: 0,
+            write_limit: 0,
+            is_code: false,
+        }
+    }
+}
+
+// Machine state read back from a save state, held aside until the whole state has been
+//  validated so that a failed load never leaves the interconnect half-restored.
+pub struct InterconnectState<'a> {
+    wram: Wram,
+    sram: &'a [u8],
+    vip: Vip,
+    vsu: Vsu,
+    timer: Timer,
+    game_pad: GamePad,
+    com_port: ComPort,
+    wcr: u8,
+    open_bus: u16,
+
+    cycle: u64,
+    timer_clock: DeviceClock,
+    vip_clock: DeviceClock,
+    vsu_clock: DeviceClock,
+}
+
 pub struct Interconnect {
     rom: Rom,
     wram: Wram,
-    pub sram: Sram,
+    sram: Sram,
     vip: Vip,
     vsu: Vsu,
     timer: Timer,
     pub game_pad: GamePad,
     pub com_port: ComPort,
+    interrupt_controller: InterruptController,
+    wcr: u8,
+    // The last halfword driven on the data bus, which is what reads from addresses nothing responds to return
+    open_bus: u16,
+
+    code_pages: CodePages,
+    pages: Box<[Page; NUM_PAGES]>,
+
+    is_strict: bool,
+    unmapped_access_addr: Option<u32>,
+
+    cycle: u64,
+    timer_clock: DeviceClock,
+    vip_clock: DeviceClock,
+    vsu_clock: DeviceClock,
 }
 
 impl Interconnect {
     pub fn new(rom: Rom, sram: Sram) -> Interconnect {
-        Interconnect {
+        let mut ret = Interconnect {
             rom: rom,
             wram: Wram::new(),
             sram: sram,
@@ -31,14 +109,248 @@ impl Interconnect {
             timer: Timer::new(),
             game_pad: GamePad::new(),
             com_port: ComPort::new(),
+            interrupt_controller: InterruptController::new(),
+            wcr: 0,
+            open_bus: 0,
+
+            code_pages: CodePages::new(),
+            pages: Box::new([Page::device(); NUM_PAGES]),
+
+            is_strict: false,
+            unmapped_access_addr: None,
+
+            cycle: 0,
+            timer_clock: DeviceClock::new(),
+            vip_clock: DeviceClock::new(),
+            vsu_clock: DeviceClock::new(),
+        };
+        ret.reschedule_devices();
+        ret.map_pages();
+        ret
+    }
+
+    pub fn sram(&self) -> &Sram {
+        &self.sram
+    }
+
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u32(self.rom.size() as _);
+        writer.write_u32(self.rom.checksum());
+
+        self.wram.save_state(writer);
+        self.sram.save_state(writer);
+        self.vip.save_state(writer);
+        self.vsu.save_state(writer);
+        self.timer.save_state(writer);
+        self.game_pad.save_state(writer);
+        self.com_port.save_state(writer);
+        writer.write_u8(self.wcr);
+        writer.write_u16(self.open_bus);
+
+        writer.write_u64(self.cycle);
+        self.timer_clock.save_state(writer);
+        self.vip_clock.save_state(writer);
+        self.vsu_clock.save_state(writer);
+    }
+
+    pub fn read_state<'a>(&self, reader: &mut SaveStateReader<'a>) -> Result<InterconnectState<'a>, SaveStateError> {
+        let rom_size = reader.read_u32()? as usize;
+        let rom_checksum = reader.read_u32()?;
+        if rom_size != self.rom.size() || rom_checksum != self.rom.checksum() {
+            return Err(SaveStateError::RomMismatch);
         }
+
+        let mut wram = Wram::new();
+        wram.load_state(reader)?;
+        let sram = self.sram.read_state(reader)?;
+        let mut vip = Vip::new();
+        vip.load_state(reader)?;
+        let mut vsu = Vsu::new();
+        vsu.load_state(reader)?;
+        let mut timer = Timer::new();
+        timer.load_state(reader)?;
+        let mut game_pad = GamePad::new();
+        game_pad.load_state(reader)?;
+        let mut com_port = ComPort::new();
+        com_port.load_state(reader)?;
+        let wcr = reader.read_u8()?;
+        let open_bus = reader.read_u16()?;
+
+        let cycle = reader.read_u64()?;
+        let mut timer_clock = DeviceClock::new();
+        timer_clock.load_state(reader, cycle, timer.cycles_until_next_event())?;
+        let mut vip_clock = DeviceClock::new();
+        vip_clock.load_state(reader, cycle, vip.cycles_until_next_event())?;
+        let mut vsu_clock = DeviceClock::new();
+        vsu_clock.load_state(reader, cycle, vsu.cycles_until_next_event())?;
+
+        Ok(InterconnectState {
+            wram: wram,
+            sram: sram,
+            vip: vip,
+            vsu: vsu,
+            timer: timer,
+            game_pad: game_pad,
+            com_port: com_port,
+            wcr: wcr,
+            open_bus: open_bus,
+
+            cycle: cycle,
+            timer_clock: timer_clock,
+            vip_clock: vip_clock,
+            vsu_clock: vsu_clock,
+        })
+    }
+
+    pub fn restore_state(&mut self, state: InterconnectState) {
+        self.wram = state.wram;
+        self.sram.restore_state(state.sram);
+        let is_recording_vip_events = self.vip.is_recording_events();
+        self.vip = state.vip;
+        self.vip.set_recording_events(is_recording_vip_events);
+        self.vsu = state.vsu;
+        self.timer = state.timer;
+        self.game_pad = state.game_pad;
+        self.com_port = state.com_port;
+        self.wcr = state.wcr;
+        self.open_bus = state.open_bus;
+
+        self.code_pages.invalidate_all();
+        self.map_pages();
+        self.unmapped_access_addr = None;
+
+        self.cycle = state.cycle;
+        self.timer_clock = state.timer_clock;
+        self.vip_clock = state.vip_clock;
+        self.vsu_clock = state.vsu_clock;
+    }
+
+    // In strict mode, data accesses that only hit something through a mirror are treated as
+    //  unmapped too, so they're reported through take_unmapped_access. Otherwise they behave as
+    //  they do on hardware. ROM and SRAM mirrors are always allowed, since the cartridge only
+    //  decodes as many address lines as it needs (the reset vector relies on this).
+    pub fn set_strict_mode(&mut self, is_strict: bool) {
+        self.is_strict = is_strict;
+        self.map_pages();
     }
 
-    pub fn read_byte(&mut self, addr: u32) -> u8 {
+    pub fn set_recording_vip_events(&mut self, is_recording: bool) {
+        self.vip.set_recording_events(is_recording);
+    }
+
+    pub fn take_vip_events(&mut self) -> Vec<VipEvent> {
+        self.vip.take_events()
+    }
+
+    // The first unmapped address accessed since the last call, if any
+    pub fn take_unmapped_access(&mut self) -> Option<u32> {
+        self.unmapped_access_addr.take()
+    }
+
+    // Unmapped reads return whatever is left on the bus and unmapped writes are dropped. Only the
+    //  first access is remembered, as that's the one that matters for tracking down what went wrong.
+    fn unmapped_access(&mut self, addr: u32) {
+        logln!(Log::Ic, "WARNING: Access to unmapped address 0x{:08x}", addr);
+        if self.unmapped_access_addr.is_none() {
+            self.unmapped_access_addr = Some(addr);
+        }
+    }
+
+    // Folds addr onto the address of whatever responds to it, or None if nothing does. Each region
+    //  repeats throughout its 16MB window: VIP every 512KB, VSU every 2KB, the hardware control
+    //  registers every 64 bytes and WRAM every 64KB. Game Pak RAM and ROM mask addresses themselves.
+    fn resolve(&mut self, addr: u32, is_fetch: bool) -> Option<u32> {
         let addr = addr & 0x07ffffff;
+        let (canonical_addr, is_cartridge) = match addr {
+            VIP_START ... VIP_END => (VIP_START + (addr & VIP_MIRROR_MASK), false),
+            VSU_START ... VSU_END => (VSU_START + (addr & VSU_MIRROR_MASK), false),
+            HARDWARE_CONTROL_START ... HARDWARE_CONTROL_END => (HARDWARE_CONTROL_START + (addr & HARDWARE_CONTROL_MIRROR_MASK), false),
+            WRAM_START ... WRAM_END => (WRAM_START + (addr & WRAM_MIRROR_MASK), false),
+            _ => (addr, true),
+        };
+        let is_mapped = match canonical_addr {
+            VIP_START ... VIP_END => Vip::is_mapped(canonical_addr - VIP_START),
+            VSU_START ... VSU_END => Vsu::is_mapped(canonical_addr - VSU_START),
+            CCR | CCSR | CDTR | CDRR | SDLR | SDHR | TLR | THR | TCR | WCR | SCR => true,
+            WRAM_START ... WRAM_END |
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END |
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => true,
+            // Nothing in 0x03000000-0x03ffffff, and no cartridge has used the expansion area
+            _ => false,
+        };
+
+        // Code running from a mirror isn't reported, as whether each fetch reaches the bus depends
+        //  on what's been cached
+        let is_strict_mirror = self.is_strict && !is_fetch && !is_cartridge && canonical_addr != addr;
+        if !is_mapped || is_strict_mirror {
+            self.unmapped_access(addr);
+            return None;
+        }
+        Some(canonical_addr)
+    }
+
+    // Has to be called whenever the backing memory moves, SRAM grows or strict mode changes
+    fn map_pages(&mut self) {
+        let rom_page = Page {
+            ptr: self.rom.bytes_ptr(),
+            mask: (self.rom.size() - 1) as _,
+            read_limit: self.rom.size() as _,
+            write_limit: 0,
+            is_code: false,
+        };
+        let wram_page = Page {
+            ptr: self.wram.bytes_ptr(),
+            mask: WRAM_MIRROR_MASK,
+            read_limit: WRAM_SIZE as _,
+            write_limit: WRAM_SIZE as _,
+            is_code: true,
+        };
+        let sram_page = Page {
+            ptr: self.sram.bytes_ptr(),
+            mask: (MAX_SRAM_SIZE - 1) as _,
+            read_limit: self.sram.size() as _,
+            write_limit: self.sram.size() as _,
+            is_code: false,
+        };
+
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            let addr = (index as u32) << PAGE_SHIFT;
+            *page = match addr {
+                // WRAM mirrors have to go through resolve in strict mode so they're reported
+                WRAM_START ... WRAM_END if !self.is_strict || addr == WRAM_START => wram_page,
+                GAME_PAK_RAM_START ... GAME_PAK_RAM_END => sram_page,
+                GAME_PAK_ROM_START ... GAME_PAK_ROM_END => rom_page,
+                _ => Page::device(),
+            };
+        }
+    }
+
+    // The SRAM page only covers what's been accessed so far, so it has to be extended whenever an
+    //  access through the slow path grows SRAM
+    fn remap_if_sram_grew(&mut self, old_size: usize) {
+        if self.sram.size() != old_size {
+            self.map_pages();
+        }
+    }
+
+    fn page(&self, addr: u32) -> Page {
+        self.pages[((addr & 0x07ffffff) >> PAGE_SHIFT) as usize]
+    }
+
+    fn open_bus_byte(&self, addr: u32) -> u8 {
+        (self.open_bus >> ((addr & 0x01) * 8)) as _
+    }
+
+    fn drive_bus_byte(&mut self, addr: u32, value: u8) {
+        let shift = (addr & 0x01) * 8;
+        self.open_bus = (self.open_bus & !(0xff << shift)) | ((value as u16) << shift);
+    }
+
+    fn read_mapped_byte(&mut self, addr: u32) -> u8 {
         match addr {
             VIP_START ... VIP_END => self.vip.read_byte(addr - VIP_START),
-            VSU_START ... VSU_END => self.vsu.read_byte(addr - VSU_START),
+            // The VSU is write-only
+            VSU_START ... VSU_END => self.open_bus_byte(addr),
             CCR => self.com_port.read_ccr(),
             CCSR => self.com_port.read_ccsr(),
             CDTR => self.com_port.read_cdtr(),
@@ -48,28 +360,24 @@ impl Interconnect {
             TLR => self.timer.read_tlr(),
             THR => self.timer.read_thr(),
             TCR => self.timer.read_tcr(),
-            WCR => {
-                logln!(Log::Ic, "WARNING: Read byte from WCR not yet implemented");
-                0
-            }
+            WCR => self.read_wcr(),
             SCR => self.game_pad.read_scr(),
-            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
-                logln!(Log::Ic, "WARNING: Read byte from Game Pak Expansion not yet implemented (addr: 0x{:08x})", addr - GAME_PAK_EXPANSION_START);
-                0
-            }
             WRAM_START ... WRAM_END => self.wram.read_byte(addr - WRAM_START),
-            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.read_byte(addr - GAME_PAK_RAM_START),
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => {
+                let size = self.sram.size();
+                let value = self.sram.read_byte(addr - GAME_PAK_RAM_START);
+                self.remap_if_sram_grew(size);
+                value
+            }
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => self.rom.read_byte(addr - GAME_PAK_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => unreachable!(),
         }
     }
 
-    pub fn read_halfword(&mut self, addr: u32) -> u16 {
-        let addr = addr & 0x07ffffff;
-        let addr = addr & 0xfffffffe;
+    fn read_mapped_halfword(&mut self, addr: u32) -> u16 {
         match addr {
             VIP_START ... VIP_END => self.vip.read_halfword(addr - VIP_START),
-            VSU_START ... VSU_END => self.vsu.read_halfword(addr - VSU_START),
+            VSU_START ... VSU_END => self.open_bus,
             CCR => self.com_port.read_ccr() as _,
             CCSR => self.com_port.read_ccsr() as _,
             CDTR => self.com_port.read_cdtr() as _,
@@ -79,24 +387,212 @@ impl Interconnect {
             TLR => self.timer.read_tlr() as _,
             THR => self.timer.read_thr() as _,
             TCR => self.timer.read_tcr() as _,
-            WCR => {
-                logln!(Log::Ic, "Read halfword from WCR not yet implemented");
-                0
-            }
+            WCR => self.read_wcr() as _,
             SCR => self.game_pad.read_scr() as _,
-            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
-                logln!(Log::Ic, "WARNING: Read halfword from Game Pak Expansion not yet implemented (addr: 0x{:08x})", addr - GAME_PAK_EXPANSION_START);
-                0
-            }
             WRAM_START ... WRAM_END => self.wram.read_halfword(addr - WRAM_START),
-            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.read_halfword(addr - GAME_PAK_RAM_START),
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => {
+                let size = self.sram.size();
+                let value = self.sram.read_halfword(addr - GAME_PAK_RAM_START);
+                self.remap_if_sram_grew(size);
+                value
+            }
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => self.rom.read_halfword(addr - GAME_PAK_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => unreachable!(),
         }
     }
 
-    pub fn write_byte(&mut self, addr: u32, value: u8) {
-        let addr = addr & 0x07ffffff;
+    fn read_wcr(&self) -> u8 {
+        0xfc | self.wcr
+    }
+
+    fn write_wcr(&mut self, value: u8) {
+        self.wcr = value & 0x03;
+        logln!(Log::Ic, "WCR written: 0x{:02x}", value);
+        logln!(Log::Ic, " Game Pak ROM Waits: {}", self.game_pak_rom_wait_states());
+        logln!(Log::Ic, " Game Pak Expansion Waits: {}", self.game_pak_expansion_wait_states());
+    }
+
+    fn game_pak_rom_wait_states(&self) -> u32 {
+        if self.wcr & 0x01 == 0 { 2 } else { 1 }
+    }
+
+    fn game_pak_expansion_wait_states(&self) -> u32 {
+        if self.wcr & 0x02 == 0 { 2 } else { 1 }
+    }
+
+    pub fn cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) {
+        self.cycle += cycles as u64;
+
+        if self.timer_clock.is_due(self.cycle) {
+            let cycles = self.timer_clock.advance_to(self.cycle);
+            self.timer.cycles(cycles);
+            self.timer_clock.schedule(self.timer.cycles_until_next_event());
+        }
+
+        if self.vip_clock.is_due(self.cycle) {
+            let cycles = self.vip_clock.advance_to(self.cycle);
+            self.vip.cycles(cycles, video_frame_sink);
+            self.vip_clock.schedule(self.vip.cycles_until_next_event());
+        }
+
+        if self.vsu_clock.is_due(self.cycle) {
+            let cycles = self.vsu_clock.advance_to(self.cycle);
+            self.vsu.cycles(cycles, audio_frame_sink);
+            self.vsu_clock.schedule(self.vsu.cycles_until_next_event());
+        }
+
+        // Interrupt lines only change on events or register writes, so they're accurate even for devices that are behind
+        self.interrupt_controller.set_line(InterruptLine::GamePad, self.game_pad.key_interrupt());
+        self.interrupt_controller.set_line(InterruptLine::Timer, self.timer.zero_interrupt());
+        self.interrupt_controller.set_line(InterruptLine::ComPort, self.com_port.interrupt());
+        self.interrupt_controller.set_line(InterruptLine::Vip, self.vip.interrupt_pending());
+    }
+
+    // Highest priority interrupt the CPU would currently accept at the given mask level. Lines stay
+    //  asserted until they're acknowledged, so an interrupt that can't be taken yet isn't lost.
+    pub fn pending_interrupt(&self, mask_level: u32) -> Option<InterruptLine> {
+        self.interrupt_controller.pending(mask_level)
+    }
+
+    // Called once the CPU has taken an interrupt. Level-triggered sources (timer, VIP) keep their
+    //  lines asserted until the program clears them; latched requests are consumed here.
+    pub fn acknowledge_interrupt(&mut self, line: InterruptLine) {
+        match line {
+            InterruptLine::GamePad => self.game_pad.acknowledge_key_interrupt(),
+            InterruptLine::ComPort => self.com_port.acknowledge_interrupt(),
+            _ => {}
+        }
+        self.interrupt_controller.set_line(line, false);
+    }
+
+    // Number of cycles until the next device event, which is the earliest an interrupt can be raised
+    pub fn cycles_until_next_event(&self) -> u32 {
+        let next_event_at = self.timer_clock.next_event_at.min(self.vip_clock.next_event_at).min(self.vsu_clock.next_event_at);
+        (next_event_at - self.cycle) as u32
+    }
+
+    // Advances the clock without running any devices, so that the dynarec can keep memory accesses in
+    //  the middle of a block in sync. The caller has to make sure no device event is skipped over.
+    #[cfg(feature = "dynarec")]
+    pub(crate) fn advance_cycles(&mut self, cycles: u32) {
+        self.cycle += cycles as u64;
+    }
+
+    // Brings the device mapped at addr up to the current cycle before it's accessed. This
+    //  never crosses an event, since any device with a due event was already run in cycles.
+    fn sync_device(&mut self, addr: u32) {
+        match addr {
+            VIP_START ... VIP_END => {
+                let cycles = self.vip_clock.advance_to(self.cycle);
+                self.vip.catch_up(cycles);
+            }
+            VSU_START ... VSU_END => {
+                let cycles = self.vsu_clock.advance_to(self.cycle);
+                self.vsu.catch_up(cycles);
+            }
+            TLR | THR | TCR => {
+                let cycles = self.timer_clock.advance_to(self.cycle);
+                self.timer.catch_up(cycles);
+            }
+            _ => {}
+        }
+    }
+
+    // Register writes can move a device's next event, so it has to be rescheduled afterwards
+    fn reschedule_device(&mut self, addr: u32) {
+        match addr {
+            VIP_START ... VIP_END => self.vip_clock.schedule(self.vip.cycles_until_next_event()),
+            VSU_START ... VSU_END => self.vsu_clock.schedule(self.vsu.cycles_until_next_event()),
+            TLR | THR | TCR => self.timer_clock.schedule(self.timer.cycles_until_next_event()),
+            _ => {}
+        }
+    }
+
+    fn reschedule_devices(&mut self) {
+        self.timer_clock.schedule(self.timer.cycles_until_next_event());
+        self.vip_clock.schedule(self.vip.cycles_until_next_event());
+        self.vsu_clock.schedule(self.vsu.cycles_until_next_event());
+    }
+}
+
+impl Bus for Interconnect {
+    fn read_byte(&mut self, addr: u32) -> u8 {
+        let page = self.page(addr);
+        let offset = addr & page.mask;
+        if offset < page.read_limit {
+            let value = unsafe { *page.ptr.offset(offset as _) };
+            self.drive_bus_byte(addr, value);
+            return value;
+        }
+
+        match self.resolve(addr, false) {
+            Some(addr) => {
+                self.sync_device(addr);
+                let value = self.read_mapped_byte(addr);
+                self.drive_bus_byte(addr, value);
+                value
+            }
+            None => self.open_bus_byte(addr),
+        }
+    }
+
+    fn read_halfword(&mut self, addr: u32) -> u16 {
+        let addr = addr & 0xfffffffe;
+        let page = self.page(addr);
+        let offset = addr & page.mask;
+        if offset < page.read_limit {
+            let value = unsafe { read_halfword_at(page.ptr, offset) };
+            self.open_bus = value;
+            return value;
+        }
+
+        match self.resolve(addr, false) {
+            Some(addr) => {
+                self.sync_device(addr);
+                let value = self.read_mapped_halfword(addr);
+                self.open_bus = value;
+                value
+            }
+            None => self.open_bus,
+        }
+    }
+
+    fn fetch_halfword(&mut self, addr: u32) -> u16 {
+        let addr = addr & 0xfffffffe;
+        let page = self.page(addr);
+        let offset = addr & page.mask;
+        if offset < page.read_limit {
+            return unsafe { read_halfword_at(page.ptr, offset) };
+        }
+
+        match self.resolve(addr, true) {
+            Some(addr) => {
+                self.sync_device(addr);
+                self.read_mapped_halfword(addr)
+            }
+            None => self.open_bus,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        self.drive_bus_byte(addr, value);
+        let page = self.page(addr);
+        let offset = addr & page.mask;
+        if offset < page.write_limit {
+            unsafe {
+                *page.ptr.offset(offset as _) = value;
+            }
+            if page.is_code {
+                self.code_pages.invalidate(offset);
+            }
+            return;
+        }
+
+        let addr = match self.resolve(addr, false) {
+            Some(addr) => addr,
+            None => return,
+        };
+        self.sync_device(addr);
         match addr {
             VIP_START ... VIP_END => self.vip.write_byte(addr - VIP_START, value),
             VSU_START ... VSU_END => self.vsu.write_byte(addr - VSU_START, value),
@@ -115,27 +611,47 @@ impl Interconnect {
             TLR => self.timer.write_tlr(value),
             THR => self.timer.write_thr(value),
             TCR => self.timer.write_tcr(value),
-            WCR => {
-                logln!(Log::Ic, "WCR (0x{:08x}) written: 0x{:02x}", addr, value);
-                logln!(Log::Ic, " Game Pak ROM Waits: {}", if value & 0x01 == 0 { 2 } else { 1 });
-                logln!(Log::Ic, " Game Pak Expansion Waits: {}", if value & 0x02 == 0 { 2 } else { 1 });
-            }
+            WCR => self.write_wcr(value),
             SCR => self.game_pad.write_scr(value),
-            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
-                logln!(Log::Ic, "WARNING: Write byte to Game Pak Expansion not yet implemented (addr: 0x{:08x}, value: 0x{:02x})", addr - GAME_PAK_EXPANSION_START, value);
+            WRAM_START ... WRAM_END => {
+                self.wram.write_byte(addr - WRAM_START, value);
+                self.code_pages.invalidate(addr - WRAM_START);
+            }
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => {
+                let size = self.sram.size();
+                self.sram.write_byte(addr - GAME_PAK_RAM_START, value);
+                self.remap_if_sram_grew(size);
             }
-            WRAM_START ... WRAM_END => self.wram.write_byte(addr - WRAM_START, value),
-            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.write_byte(addr - GAME_PAK_RAM_START, value),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Game Pak ROM at 0x{:08x}", addr - GAME_PAK_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => unreachable!(),
         }
+
+        self.reschedule_device(addr);
     }
 
-    pub fn write_halfword(&mut self, addr: u32, value: u16) {
-        let addr = addr & 0x07ffffff;
+    fn write_halfword(&mut self, addr: u32, value: u16) {
         let addr = addr & 0xfffffffe;
+        self.open_bus = value;
+        let page = self.page(addr);
+        let offset = addr & page.mask;
+        if offset < page.write_limit {
+            unsafe {
+                *page.ptr.offset(offset as _) = value as _;
+                *page.ptr.offset((offset + 1) as _) = (value >> 8) as _;
+            }
+            if page.is_code {
+                self.code_pages.invalidate(offset);
+            }
+            return;
+        }
+
+        let addr = match self.resolve(addr, false) {
+            Some(addr) => addr,
+            None => return,
+        };
+        self.sync_device(addr);
         match addr {
             VIP_START ... VIP_END => self.vip.write_halfword(addr - VIP_START, value),
             VSU_START ... VSU_END => self.vsu.write_halfword(addr - VSU_START, value),
@@ -154,35 +670,60 @@ impl Interconnect {
             TLR => self.timer.write_tlr(value as _),
             THR => self.timer.write_thr(value as _),
             TCR => self.timer.write_tcr(value as _),
-            WCR => {
-                logln!(Log::Ic, "WARNING: Write halfword to WCR not yet implemented (value: 0x{:04x})", value);
-            }
+            WCR => self.write_wcr(value as _),
             SCR => self.game_pad.write_scr(value as _),
-            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
-                logln!(Log::Ic, "WARNING: Write halfword to Game Pak Expansion not yet implemented (addr: 0x{:08x}, value: 0x{:04x})", addr - GAME_PAK_EXPANSION_START, value);
+            WRAM_START ... WRAM_END => {
+                self.wram.write_halfword(addr - WRAM_START, value);
+                self.code_pages.invalidate(addr - WRAM_START);
+            }
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => {
+                let size = self.sram.size();
+                self.sram.write_halfword(addr - GAME_PAK_RAM_START, value);
+                self.remap_if_sram_grew(size);
             }
-            WRAM_START ... WRAM_END => self.wram.write_halfword(addr - WRAM_START, value),
-            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.write_halfword(addr - GAME_PAK_RAM_START, value),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Game Pak ROM at 0x{:08x}", addr - GAME_PAK_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => unreachable!(),
         }
-    }
 
-    pub fn cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Option<u16> {
-        let mut interrupt = None;
+        self.reschedule_device(addr);
+    }
 
-        if self.timer.cycles(cycles) {
-            interrupt = Some(0xfe10);
-        }
+    // Cycles a data access takes on the bus. Accesses wider than the region's bus are split into
+    //  several bus cycles (so words always take at least two).
+    fn access_cycles(&self, addr: u32, size: AccessSize) -> u32 {
+        let addr = addr & 0x07ffffff;
+        let (bus_width, wait_states) = match addr {
+            VIP_START ... VIP_END => (2, VIP_WAIT_STATES),
+            VSU_START ... VSU_END => (1, VSU_WAIT_STATES),
+            HARDWARE_CONTROL_START ... HARDWARE_CONTROL_END => (1, 0),
+            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => (2, self.game_pak_expansion_wait_states()),
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => (1, GAME_PAK_RAM_WAIT_STATES),
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => (2, self.game_pak_rom_wait_states()),
+            _ => (2, 0),
+        };
+        let size = match size {
+            AccessSize::Byte => 1,
+            AccessSize::Halfword => 2,
+            AccessSize::Word => 4,
+        };
+        let bus_cycles = if size > bus_width { size / bus_width } else { 1 };
+        bus_cycles * (2 + wait_states)
+    }
 
-        if self.vip.cycles(cycles, video_frame_sink) {
-            interrupt = Some(0xfe40);
+    // Only ROM and WRAM are cached; the generation changes whenever the containing WRAM page is written
+    fn code_generation(&self, addr: u32) -> Option<u64> {
+        let addr = addr & 0x07ffffff;
+        match addr {
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => Some(0),
+            WRAM_START ... WRAM_END => Some(self.code_pages.generation(addr - WRAM_START)),
+            _ => None
         }
-
-        self.vsu.cycles(cycles, audio_frame_sink);
-
-        interrupt
     }
 }
+
+unsafe fn read_halfword_at(ptr: *mut u8, offset: u32) -> u16 {
+    (*ptr.offset(offset as _) as u16) |
+    ((*ptr.offset((offset + 1) as _) as u16) << 8)
+}
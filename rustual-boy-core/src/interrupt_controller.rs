@@ -0,0 +1,63 @@
+// The V810 has a single interrupt input with a 4-bit level, so the five interrupt sources are
+//  combined here. Each source drives its own line, and when several are asserted at once the one
+//  with the highest level wins.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InterruptLine {
+    GamePad,
+    Timer,
+    GamePak,
+    ComPort,
+    Vip,
+}
+
+const LINES: [InterruptLine; 5] = [
+    InterruptLine::GamePad,
+    InterruptLine::Timer,
+    InterruptLine::GamePak,
+    InterruptLine::ComPort,
+    InterruptLine::Vip,
+];
+
+impl InterruptLine {
+    pub fn level(self) -> u32 {
+        self as u32
+    }
+
+    pub fn exception_code(self) -> u16 {
+        0xfe00 | ((self.level() as u16) << 4)
+    }
+}
+
+pub struct InterruptController {
+    asserted: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            asserted: 0,
+        }
+    }
+
+    pub fn set_line(&mut self, line: InterruptLine, asserted: bool) {
+        let mask = 1 << line.level();
+        if asserted {
+            self.asserted |= mask;
+        } else {
+            self.asserted &= !mask;
+        }
+    }
+
+    pub fn is_asserted(&self, line: InterruptLine) -> bool {
+        (self.asserted & (1 << line.level())) != 0
+    }
+
+    // Highest priority asserted line that isn't masked by the PSW's interrupt mask level, if any
+    pub fn pending(&self, mask_level: u32) -> Option<InterruptLine> {
+        LINES.iter()
+            .rev()
+            .cloned()
+            .find(|&line| self.is_asserted(line) && line.level() >= mask_level)
+    }
+}
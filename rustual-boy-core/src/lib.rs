@@ -1,14 +1,26 @@
 extern crate encoding;
+#[cfg(feature = "dynarec")]
+extern crate libc;
+
+#[cfg(all(feature = "dynarec", not(all(target_arch = "x86_64", unix))))]
+compile_error!("The dynarec feature is only supported on x86-64 Unix targets");
 
 #[macro_use]
 mod logging;
+mod decode_cache;
 mod mem_map;
+mod scheduler;
 
+pub mod assembler;
+pub mod bus;
 pub mod com_port;
 pub mod game_pad;
+pub mod hooks;
 pub mod instruction;
 pub mod interconnect;
+pub mod interrupt_controller;
 pub mod rom;
+pub mod save_state;
 pub mod sinks;
 pub mod sram;
 pub mod time_source;
@@ -1,10 +1,17 @@
 pub const VIP_START: u32 = 0x00000000;
 pub const VIP_LENGTH: u32 = 0x01000000;
 pub const VIP_END: u32 = VIP_START + VIP_LENGTH - 1;
+pub const VIP_MIRROR_MASK: u32 = 0x0007ffff;
 
 pub const VSU_START: u32 = 0x01000000;
 pub const VSU_LENGTH: u32 = 0x01000000;
 pub const VSU_END: u32 = VSU_START + VSU_LENGTH - 1;
+pub const VSU_MIRROR_MASK: u32 = 0x000007ff;
+
+pub const HARDWARE_CONTROL_START: u32 = 0x02000000;
+pub const HARDWARE_CONTROL_LENGTH: u32 = 0x01000000;
+pub const HARDWARE_CONTROL_END: u32 = HARDWARE_CONTROL_START + HARDWARE_CONTROL_LENGTH - 1;
+pub const HARDWARE_CONTROL_MIRROR_MASK: u32 = 0x0000003f;
 
 pub const CCR: u32 = 0x02000000;
 pub const CCSR: u32 = 0x02000004;
@@ -25,6 +32,7 @@ pub const GAME_PAK_EXPANSION_END: u32 = GAME_PAK_EXPANSION_START + GAME_PAK_EXPA
 pub const WRAM_START: u32 = 0x05000000;
 pub const WRAM_LENGTH: u32 = 0x01000000;
 pub const WRAM_END: u32 = WRAM_START + WRAM_LENGTH - 1;
+pub const WRAM_MIRROR_MASK: u32 = 0x0000ffff;
 
 pub const GAME_PAK_RAM_START: u32 = 0x06000000;
 pub const GAME_PAK_RAM_LENGTH: u32 = 0x01000000;
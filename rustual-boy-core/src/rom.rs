@@ -14,6 +14,8 @@ pub const MAX_ROM_SIZE: usize = 16 * 1024 * 1024;
 pub struct Rom {
     bytes: Box<[u8]>,
     bytes_ptr: *mut u8,
+
+    checksum: u32,
 }
 
 impl Rom {
@@ -33,12 +35,17 @@ impl Rom {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid ROM size"));
         }
 
+        // FNV-1a; only used to identify the ROM (e.g. for save states), so it doesn't need to be strong
+        let checksum = bytes_copy.iter().fold(0x811c9dc5, |hash: u32, &byte| (hash ^ (byte as u32)).wrapping_mul(0x01000193));
+
         let mut bytes_box = bytes_copy.into_boxed_slice();
         let bytes_ptr = bytes_box.as_mut_ptr();
 
         Ok(Rom {
             bytes: bytes_box,
             bytes_ptr: bytes_ptr,
+
+            checksum: checksum,
         })
     }
 
@@ -46,6 +53,14 @@ impl Rom {
         self.bytes.len()
     }
 
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub(crate) fn bytes_ptr(&self) -> *mut u8 {
+        self.bytes_ptr
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         let addr = self.mask_addr(addr);
         unsafe {
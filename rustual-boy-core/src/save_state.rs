@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::fmt;
+
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"RBSS";
+
+// Bump this whenever the serialized layout of any component changes; older states
+//  are rejected rather than misinterpreted.
+pub const SAVE_STATE_VERSION: u32 = 9;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SaveStateError {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    RomMismatch,
+    UnexpectedEnd,
+    TrailingData,
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveStateError::InvalidMagic => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "Unsupported save state version: {} (expected {})", version, SAVE_STATE_VERSION),
+            SaveStateError::RomMismatch => write!(f, "Save state was created with a different ROM"),
+            SaveStateError::UnexpectedEnd => write!(f, "Save state is truncated"),
+            SaveStateError::TrailingData => write!(f, "Save state has trailing data"),
+            SaveStateError::InvalidValue(name) => write!(f, "Save state contains an invalid value for {}", name),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+pub struct SaveStateWriter {
+    bytes: Vec<u8>,
+}
+
+impl SaveStateWriter {
+    pub fn new() -> SaveStateWriter {
+        SaveStateWriter {
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(if value { 1 } else { 0 });
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_u32(value as _);
+        self.write_u32((value >> 32) as _);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct SaveStateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SaveStateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> SaveStateReader<'a> {
+        SaveStateReader {
+            bytes: bytes,
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("bool"))
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok((bytes[0] as u16) | ((bytes[1] as u16) << 8))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.read_bytes(4)?;
+        Ok((bytes[0] as u32) |
+            ((bytes[1] as u32) << 8) |
+            ((bytes[2] as u32) << 16) |
+            ((bytes[3] as u32) << 24))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        let low = self.read_u32()? as u64;
+        let high = self.read_u32()? as u64;
+        Ok(low | (high << 32))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if len > self.bytes.len() {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+
+        let (ret, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(ret)
+    }
+
+    pub fn read_bytes_into(&mut self, dest: &mut [u8]) -> Result<(), SaveStateError> {
+        let bytes = self.read_bytes(dest.len())?;
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), SaveStateError> {
+        if !self.bytes.is_empty() {
+            return Err(SaveStateError::TrailingData);
+        }
+
+        Ok(())
+    }
+}
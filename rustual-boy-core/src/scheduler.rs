@@ -0,0 +1,78 @@
+use save_state::*;
+
+// Rather than stepping every device one cycle at a time, the interconnect only runs a device
+//  when its next event is due (a cycle where something happens that can't be computed in bulk,
+//  like a timer underflow or a VIP frame clock). In between, devices are caught up in bulk
+//  whenever the CPU accesses them, which by construction never crosses an event.
+
+// Upper bound on how far ahead a device is scheduled, so elapsed cycle counts always fit in a u32
+const MAX_EVENT_DISTANCE: u32 = 20000000;
+
+pub struct DeviceClock {
+    pub synced_at: u64,
+    pub next_event_at: u64,
+}
+
+impl DeviceClock {
+    pub fn new() -> DeviceClock {
+        DeviceClock {
+            synced_at: 0,
+            next_event_at: 0,
+        }
+    }
+
+    // Marks the device as synced up to the given cycle, returning how many cycles it needs to run to get there
+    pub fn advance_to(&mut self, cycle: u64) -> u32 {
+        let ret = (cycle - self.synced_at) as u32;
+        self.synced_at = cycle;
+        ret
+    }
+
+    pub fn schedule(&mut self, cycles_until_next_event: u32) {
+        self.next_event_at = self.synced_at.saturating_add(cycles_until_next_event.min(MAX_EVENT_DISTANCE) as u64);
+    }
+
+    pub fn is_due(&self, cycle: u64) -> bool {
+        cycle >= self.next_event_at
+    }
+
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u64(self.synced_at);
+    }
+
+    // A device can never lag behind the current cycle by its next event or more
+    pub fn load_state(&mut self, reader: &mut SaveStateReader, cycle: u64, cycles_until_next_event: u32) -> Result<(), SaveStateError> {
+        self.synced_at = reader.read_u64()?;
+        self.schedule(cycles_until_next_event);
+        if self.synced_at > cycle || self.is_due(cycle) {
+            return Err(SaveStateError::InvalidValue("device clock"));
+        }
+
+        Ok(())
+    }
+}
+
+// Number of cycles until a counter that's incremented every cycle fires, where firing means
+//  reaching (or exceeding) period and resetting to 0
+pub fn cycles_until(counter: u32, period: u32) -> u32 {
+    if counter + 1 >= period {
+        1
+    } else {
+        period - counter
+    }
+}
+
+// Advances such a counter by the given number of cycles, returning how many times it fired
+pub fn advance_counter(counter: &mut u32, period: u32, cycles: u32) -> u32 {
+    let period = period.max(1);
+
+    let first = cycles_until(*counter, period);
+    if cycles < first {
+        *counter += cycles;
+        return 0;
+    }
+
+    let rest = cycles - first;
+    *counter = rest % period;
+    1 + rest / period
+}
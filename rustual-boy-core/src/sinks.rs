@@ -4,6 +4,13 @@ pub trait Sink<T> {
     fn append(&mut self, value: T);
 }
 
+/// Collects every value pushed into it.
+impl<T> Sink<T> for Vec<T> {
+    fn append(&mut self, value: T) {
+        self.push(value);
+    }
+}
+
 /// Represents a sink of value references.
 pub trait SinkRef<T: ?Sized> {
     fn append(&mut self, value: &T);
@@ -1,3 +1,5 @@
+use save_state::*;
+
 use std::io::{self, Read, Write, Error, ErrorKind};
 use std::fs::File;
 use std::path::Path;
@@ -35,7 +37,9 @@ impl Sram {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid SRAM size"));
         }
 
-        let mut bytes = vec.into_boxed_slice();
+        // The whole address range is allocated up front (as in new), so that SRAM can grow in place
+        let mut bytes = vec![0xff; MAX_SRAM_SIZE].into_boxed_slice();
+        bytes[..size].copy_from_slice(&vec);
         let bytes_ptr = bytes.as_mut_ptr();
 
         Ok(Sram {
@@ -48,13 +52,46 @@ impl Sram {
 
     pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
         let mut file = File::create(file_name)?;
-        file.write_all(&self.bytes)
+        file.write_all(&self.bytes[..self.size])
     }
 
     pub fn size(&self) -> usize {
         self.size
     }
 
+    // Only the first size bytes can be accessed directly; anything past that has to go through
+    //  the read/write functions so the size can grow
+    pub(crate) fn bytes_ptr(&self) -> *mut u8 {
+        self.bytes_ptr
+    }
+
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u32(self.size as _);
+        writer.write_bytes(&self.bytes[..self.size]);
+    }
+
+    // Validates and returns the serialized contents without touching the current ones,
+    //  so the caller can commit them with restore_state once the rest of the state has loaded.
+    pub fn read_state<'a>(&self, reader: &mut SaveStateReader<'a>) -> Result<&'a [u8], SaveStateError> {
+        let size = reader.read_u32()? as usize;
+        if size != 0 && (size < MIN_SRAM_SIZE || size > self.bytes.len() || !size.is_power_of_two()) {
+            return Err(SaveStateError::InvalidValue("SRAM size"));
+        }
+
+        reader.read_bytes(size)
+    }
+
+    pub fn restore_state(&mut self, bytes: &[u8]) {
+        let size = bytes.len();
+        self.bytes[..size].copy_from_slice(bytes);
+        if self.size > size {
+            for byte in &mut self.bytes[size..self.size] {
+                *byte = 0xff;
+            }
+        }
+        self.size = size;
+    }
+
     pub fn read_byte(&mut self, addr: u32) -> u8 {
         let addr = self.mask_addr(addr);
         unsafe {
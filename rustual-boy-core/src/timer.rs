@@ -1,3 +1,6 @@
+use save_state::*;
+use scheduler::*;
+
 // 20mhz / (1s / 100us) = 2000
 const LARGE_INTERVAL_PERIOD: u32 = 2000;
 
@@ -81,31 +84,106 @@ impl Timer {
         self.counter = self.reload;
     }
 
-    pub fn cycles(&mut self, cycles: u32) -> bool {
-        if self.t_enb {
-            for _ in 0..cycles {
-                let tick_period = match self.t_clk_sel {
-                    Interval::Large => LARGE_INTERVAL_PERIOD,
-                    Interval::Small => SMALL_INTERVAL_PERIOD,
-                };
-                self.tick_counter += 1;
-                if self.tick_counter >= tick_period {
-                    self.tick_counter = 0;
-
-                    self.counter = match self.counter {
-                        0 => {
-                            self.z_stat = true;
-                            if self.tim_z_int {
-                                self.zero_interrupt = true;
-                            }
-                            self.reload
-                        }
-                        _ => self.counter - 1
-                    };
-                }
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u8(match self.t_clk_sel {
+            Interval::Large => 0,
+            Interval::Small => 1,
+        });
+        writer.write_bool(self.tim_z_int);
+        writer.write_bool(self.z_stat);
+        writer.write_bool(self.t_enb);
+        writer.write_u16(self.reload);
+        writer.write_u16(self.counter);
+
+        writer.write_u32(self.tick_counter);
+        writer.write_bool(self.zero_interrupt);
+    }
+
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.t_clk_sel = match reader.read_u8()? {
+            0 => Interval::Large,
+            1 => Interval::Small,
+            _ => return Err(SaveStateError::InvalidValue("timer interval"))
+        };
+        self.tim_z_int = reader.read_bool()?;
+        self.z_stat = reader.read_bool()?;
+        self.t_enb = reader.read_bool()?;
+        self.reload = reader.read_u16()?;
+        self.counter = reader.read_u16()?;
+
+        self.tick_counter = reader.read_u32()?;
+        if self.tick_counter >= LARGE_INTERVAL_PERIOD {
+            return Err(SaveStateError::InvalidValue("timer tick counter"));
+        }
+        self.zero_interrupt = reader.read_bool()?;
+
+        Ok(())
+    }
+
+    pub fn cycles(&mut self, mut cycles: u32) -> bool {
+        while cycles > 0 {
+            let cycles_until_next_event = self.cycles_until_next_event();
+            if cycles < cycles_until_next_event {
+                self.catch_up(cycles);
+                break;
             }
+
+            self.catch_up(cycles_until_next_event - 1);
+            self.cycle();
+            cycles -= cycles_until_next_event;
         }
 
         self.zero_interrupt
     }
+
+    pub fn zero_interrupt(&self) -> bool {
+        self.zero_interrupt
+    }
+
+    // The only event is the counter reaching zero; regular ticks are handled in bulk
+    pub fn cycles_until_next_event(&self) -> u32 {
+        if !self.t_enb {
+            return u32::MAX;
+        }
+
+        let tick_period = self.tick_period();
+        cycles_until(self.tick_counter, tick_period) + (self.counter as u32) * tick_period
+    }
+
+    // Advances the timer without crossing an event (cycles must be less than cycles_until_next_event)
+    pub fn catch_up(&mut self, cycles: u32) {
+        if self.t_enb {
+            let tick_period = self.tick_period();
+            let ticks = advance_counter(&mut self.tick_counter, tick_period, cycles);
+            self.counter -= ticks as u16;
+        }
+    }
+
+    fn tick_period(&self) -> u32 {
+        match self.t_clk_sel {
+            Interval::Large => LARGE_INTERVAL_PERIOD,
+            Interval::Small => SMALL_INTERVAL_PERIOD,
+        }
+    }
+
+    fn cycle(&mut self) {
+        if self.t_enb {
+            let tick_period = self.tick_period();
+            self.tick_counter += 1;
+            if self.tick_counter >= tick_period {
+                self.tick_counter = 0;
+
+                self.counter = match self.counter {
+                    0 => {
+                        self.z_stat = true;
+                        if self.tim_z_int {
+                            self.zero_interrupt = true;
+                        }
+                        self.reload
+                    }
+                    _ => self.counter - 1
+                };
+            }
+        }
+    }
 }
@@ -1,14 +1,25 @@
+use bus::*;
+use decode_cache::*;
 use instruction::*;
-use interconnect::*;
+use save_state::*;
 
 use std::collections::HashSet;
 use std::fmt;
 
+#[cfg(feature = "dynarec")]
+mod dynarec;
+
+#[cfg(feature = "dynarec")]
+pub(crate) use self::dynarec::Dynarec;
+
 #[derive(Copy, Clone, Default)]
 pub struct CacheEntry {
     pub tag: u32,
     pub base_addr: u32,
     pub subblock_valid: [bool; 2],
+    // Copied from memory when each subblock is filled. Instructions are still fetched from memory,
+    //  so this is only visible through cache dumps.
+    pub data: [u32; 2],
 }
 
 impl fmt::Display for CacheEntry {
@@ -23,6 +34,27 @@ pub enum CacheResult {
     Disabled,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExceptionKind {
+    Interrupt,
+    // Raised by an instruction while no other exception was being handled
+    Exception,
+    // Raised while handling an exception
+    DuplexedException,
+    // Raised while handling a duplexed exception, which halts the CPU
+    FatalException,
+}
+
+// An exception the CPU has started handling
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Exception {
+    pub kind: ExceptionKind,
+    pub code: u16,
+    // PC when the exception was taken, which is where the handler returns to (except after a
+    //  halt, where it returns past the halt instruction)
+    pub pc: u32,
+}
+
 pub struct Cache {
     hits: u64,
     misses: u64,
@@ -67,11 +99,28 @@ impl Cache {
         return self.is_enabled;
     }
 
-    pub fn read_halfword(&mut self, interconnect: &mut Interconnect, addr: u32) -> (u16, CacheResult) {
-        let halfword = interconnect.read_halfword(addr);
+    pub fn read_halfword<B: Bus>(&mut self, bus: &mut B, addr: u32) -> (u16, CacheResult) {
+        let halfword = bus.fetch_halfword(addr);
+
+        (halfword, self.access(bus, addr))
+    }
+
+    // Whether a fetch from addr would hit, without updating the cache
+    #[cfg(feature = "dynarec")]
+    fn is_hit(&self, addr: u32) -> bool {
+        if !self.is_enabled {
+            return false;
+        }
+
+        let entry = &self.entries[((addr >> 3) & 0x7f) as usize];
+        let subblock = ((addr >> 2) & 0x01) as usize;
+        entry.tag == addr >> 10 && entry.subblock_valid[subblock]
+    }
 
+    // Updates the cache for a fetch from addr. The bus is only accessed to fill a subblock on a miss.
+    fn access<B: Bus>(&mut self, bus: &mut B, addr: u32) -> CacheResult {
         if !self.is_enabled {
-            return (halfword, CacheResult::Disabled);
+            return CacheResult::Disabled;
         }
 
         let byte_offset = (addr & 0x07) as usize;
@@ -82,19 +131,62 @@ impl Cache {
         if self.entries[entry].tag == tag {
             if self.entries[entry].subblock_valid[subblock] {
                 self.hits += 1;
-                return (halfword, CacheResult::Hit);
+                return CacheResult::Hit;
             }
             self.entries[entry].subblock_valid[subblock] = true;
+            self.entries[entry].data[subblock] = bus.fetch_word(addr & 0xfffffffc);
             self.misses += 1;
-            return (halfword, CacheResult::Miss);
+            return CacheResult::Miss;
         } else {
             self.entries[entry].tag = tag;
             self.entries[entry].subblock_valid = [false; 2];
             self.entries[entry].subblock_valid[subblock] = true;
+            self.entries[entry].data[subblock] = bus.fetch_word(addr & 0xfffffffc);
             self.entries[entry].base_addr = addr & 0xfffffff8;
             self.misses += 1;
-            return (halfword, CacheResult::Miss);
+            return CacheResult::Miss;
+        }
+    }
+
+    // Writes all 128 entries' data (8 bytes each) to memory starting at addr, followed by their
+    //  tags (4 bytes each, with the subblock valid bits in bits 22 and 23). Returns the number of
+    //  cycles spent on the bus.
+    fn dump<B: Bus>(&self, bus: &mut B, addr: u32) -> u32 {
+        let mut cycles = 0;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let entry_addr = addr.wrapping_add((index * 8) as u32);
+            bus.write_word(entry_addr, entry.data[0]);
+            bus.write_word(entry_addr.wrapping_add(4), entry.data[1]);
+            cycles += 2 * bus.access_cycles(entry_addr, AccessSize::Word);
+
+            let tag_addr = addr.wrapping_add(1024 + (index * 4) as u32);
+            let tag =
+                (entry.tag & 0x003fffff) |
+                (if entry.subblock_valid[0] { 1 << 22 } else { 0 }) |
+                (if entry.subblock_valid[1] { 1 << 23 } else { 0 });
+            bus.write_word(tag_addr, tag);
+            cycles += bus.access_cycles(tag_addr, AccessSize::Word);
         }
+        cycles
+    }
+
+    // Reads back a dump in the format written by dump. Returns the number of cycles spent on the bus.
+    fn restore<B: Bus>(&mut self, bus: &mut B, addr: u32) -> u32 {
+        let mut cycles = 0;
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            let entry_addr = addr.wrapping_add((index * 8) as u32);
+            entry.data[0] = bus.read_word(entry_addr);
+            entry.data[1] = bus.read_word(entry_addr.wrapping_add(4));
+            cycles += 2 * bus.access_cycles(entry_addr, AccessSize::Word);
+
+            let tag_addr = addr.wrapping_add(1024 + (index * 4) as u32);
+            let tag = bus.read_word(tag_addr);
+            entry.tag = tag & 0x003fffff;
+            entry.subblock_valid = [(tag >> 22) & 0x01 != 0, (tag >> 23) & 0x01 != 0];
+            entry.base_addr = (entry.tag << 10) | ((index as u32) << 3);
+            cycles += bus.access_cycles(tag_addr, AccessSize::Word);
+        }
+        cycles
     }
 
     pub fn entry(&self, entry: usize) -> CacheEntry {
@@ -104,6 +196,36 @@ impl Cache {
     pub fn stats(&self) -> (u64, u64) {
         return (self.hits, self.misses);
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u64(self.hits);
+        writer.write_u64(self.misses);
+        writer.write_bool(self.is_enabled);
+        for entry in self.entries.iter() {
+            writer.write_u32(entry.tag);
+            writer.write_u32(entry.base_addr);
+            writer.write_bool(entry.subblock_valid[0]);
+            writer.write_bool(entry.subblock_valid[1]);
+            writer.write_u32(entry.data[0]);
+            writer.write_u32(entry.data[1]);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.hits = reader.read_u64()?;
+        self.misses = reader.read_u64()?;
+        self.is_enabled = reader.read_bool()?;
+        for entry in self.entries.iter_mut() {
+            entry.tag = reader.read_u32()?;
+            entry.base_addr = reader.read_u32()?;
+            entry.subblock_valid[0] = reader.read_bool()?;
+            entry.subblock_valid[1] = reader.read_bool()?;
+            entry.data[0] = reader.read_u32()?;
+            entry.data[1] = reader.read_u32()?;
+        }
+
+        Ok(())
+    }
 }
 
 pub struct V810 {
@@ -114,9 +236,11 @@ pub struct V810 {
 
     reg_eipc: u32,
     reg_eipsw: u32,
-    reg_ecr: u16,
+    // Upper halfword is FECC (duplexed exceptions), lower halfword is EICC
+    reg_ecr: u32,
     reg_fepc: u32,
     reg_fepsw: u32,
+    reg_adtre: u32,
 
     psw_zero: bool,
     psw_sign: bool,
@@ -135,10 +259,17 @@ pub struct V810 {
     psw_interrupt_mask_level: u32,
 
     is_halted: bool,
+    // Set when an exception returns to ADTRE with the address trap enabled, so that the instruction
+    //  there runs once instead of trapping again straight away
+    is_address_trap_skipped: bool,
 
     pub cache: Cache,
+    decode_cache: DecodeCache,
 
+    pub breakpoints: HashSet<u32>,
     pub watchpoints: HashSet<u32>,
+
+    last_exception: Option<Exception>,
 }
 
 impl V810 {
@@ -155,9 +286,10 @@ impl V810 {
 
             reg_eipc: 0xdeadbeee, // lowest bit is always 0
             reg_eipsw: 0xdeadbeef & 0x000ff3ff,
-            reg_ecr: 0xfff0,
+            reg_ecr: 0x0000fff0,
             reg_fepc: 0xdeadbeee, // lowest bit is always 0
             reg_fepsw: 0xdeadbeef & 0x000ff3ff,
+            reg_adtre: 0xdeadbeee, // lowest bit is always 0
 
             psw_zero: false,
             psw_sign: false,
@@ -176,10 +308,15 @@ impl V810 {
             psw_interrupt_mask_level: 0,
 
             is_halted: false,
+            is_address_trap_skipped: false,
 
             cache: Cache::new(),
+            decode_cache: DecodeCache::new(),
 
+            breakpoints: HashSet::new(),
             watchpoints: HashSet::new(),
+
+            last_exception: None,
         }
     }
 
@@ -187,6 +324,16 @@ impl V810 {
         self.reg_pc
     }
 
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    // The most recent exception taken since the last call, if any. Only one can be taken per
+    //  instruction (or interrupt request), so callers that check after each of those see them all.
+    pub fn take_exception(&mut self) -> Option<Exception> {
+        self.last_exception.take()
+    }
+
     pub fn reg_gpr(&self, index: usize) -> u32 {
         unsafe {
             let reg_ptr = self.reg_gpr_ptr.offset(index as _);
@@ -230,10 +377,26 @@ impl V810 {
         self.reg_eipsw
     }
 
-    pub fn reg_ecr(&self) -> u16 {
+    pub fn reg_ecr(&self) -> u32 {
         self.reg_ecr
     }
 
+    pub fn reg_fepc(&self) -> u32 {
+        self.reg_fepc
+    }
+
+    pub fn reg_fepsw(&self) -> u32 {
+        self.reg_fepsw
+    }
+
+    pub fn reg_adtre(&self) -> u32 {
+        self.reg_adtre
+    }
+
+    pub fn interrupt_mask_level(&self) -> u32 {
+        self.psw_interrupt_mask_level
+    }
+
     pub fn reg_psw(&self) -> u32 {
         (if self.psw_zero { 1 << 0 } else { 0 }) |
         (if self.psw_sign { 1 << 1 } else { 0 }) |
@@ -270,668 +433,790 @@ impl V810 {
         self.psw_interrupt_mask_level = (value >> 16) & 0x0f;
     }
 
-    pub fn step(&mut self, interconnect: &mut Interconnect) -> (u32, bool) {
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u32(self.reg_pc);
+        for index in 0..32 {
+            writer.write_u32(self.reg_gpr(index));
+        }
+
+        writer.write_u32(self.reg_eipc);
+        writer.write_u32(self.reg_eipsw);
+        writer.write_u32(self.reg_ecr);
+        writer.write_u32(self.reg_fepc);
+        writer.write_u32(self.reg_fepsw);
+        writer.write_u32(self.reg_adtre);
+
+        writer.write_u32(self.reg_psw());
+
+        writer.write_bool(self.is_halted);
+        writer.write_bool(self.is_address_trap_skipped);
+
+        self.cache.save_state(writer);
+    }
+
+    // Breakpoints and watchpoints are debugger state rather than machine state, so they're left untouched.
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.reg_pc = reader.read_u32()?;
+        if reader.read_u32()? != 0 {
+            return Err(SaveStateError::InvalidValue("r0"));
+        }
+        for index in 1..32 {
+            let value = reader.read_u32()?;
+            self.set_reg_gpr(index, value);
+        }
+
+        self.reg_eipc = reader.read_u32()?;
+        self.reg_eipsw = reader.read_u32()?;
+        self.reg_ecr = reader.read_u32()?;
+        self.reg_fepc = reader.read_u32()?;
+        self.reg_fepsw = reader.read_u32()?;
+        self.reg_adtre = reader.read_u32()?;
+
+        let psw = reader.read_u32()?;
+        self.set_reg_psw(psw);
+
+        self.is_halted = reader.read_bool()?;
+        self.is_address_trap_skipped = reader.read_bool()?;
+
+        self.cache.load_state(reader)
+    }
+
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> (u32, bool) {
         if self.is_halted {
             return (1, false);
         }
 
+        // The address trap is taken before the instruction at ADTRE is fetched. Returning from it
+        //  restores AE and lands back on ADTRE, so the check is skipped for that one instruction;
+        //  the trap is armed again once it has executed.
+        let is_address_trap_skipped = self.is_address_trap_skipped;
+        self.is_address_trap_skipped = false;
+        if self.psw_address_trap_enable && self.reg_pc == self.reg_adtre && !is_address_trap_skipped {
+            self.reg_pc = self.raise_exception(bus, 0xffc0);
+            return (1, false);
+        }
+
         let original_pc = self.reg_pc;
 
-        let (first_halfword, _) = self.cache.read_halfword(interconnect, original_pc);
-        let mut next_pc = original_pc.wrapping_add(2);
+        let (instruction, fetch_cycles) = self.fetch(bus, original_pc);
+        let mut next_pc = original_pc.wrapping_add(if instruction.is_long() { 4 } else { 2 });
 
         let mut num_cycles = 1;
         let mut trigger_watchpoint = false;
 
-        if first_halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
-            let cond_bits = (first_halfword >> 9) & 0x0f;
-            let take_branch = match cond_bits {
-                OPCODE_BITS_BCOND_BV => self.psw_overflow,
-                OPCODE_BITS_BCOND_BC => self.psw_carry,
-                OPCODE_BITS_BCOND_BZ => self.psw_zero,
-                OPCODE_BITS_BCOND_BNH => self.psw_carry || self.psw_zero,
-                OPCODE_BITS_BCOND_BN => self.psw_sign,
-                OPCODE_BITS_BCOND_BR => true,
-                OPCODE_BITS_BCOND_BLT => self.psw_sign != self.psw_overflow,
-                OPCODE_BITS_BCOND_BLE => (self.psw_sign != self.psw_overflow) || self.psw_zero,
-                OPCODE_BITS_BCOND_BNV => !self.psw_overflow,
-                OPCODE_BITS_BCOND_BNC => !self.psw_carry,
-                OPCODE_BITS_BCOND_BNZ => !self.psw_zero,
-                OPCODE_BITS_BCOND_BH => !(self.psw_carry || self.psw_zero),
-                OPCODE_BITS_BCOND_BP => !self.psw_sign,
-                OPCODE_BITS_BCOND_NOP => false,
-                OPCODE_BITS_BCOND_BGE => !(self.psw_sign != self.psw_overflow),
-                OPCODE_BITS_BCOND_BGT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                _ => panic!("Unrecognized cond bits: {:04b} (halfword: 0b{:016b})", cond_bits, first_halfword)
-            };
-
-            if take_branch {
-                let disp = ((((first_halfword as i16) << 7) >> 7) as u32) & 0xfffffffe;
-                next_pc = self.reg_pc.wrapping_add(disp);
-                num_cycles = 3;
+        match instruction {
+            Instruction::Bcond { condition, disp } => {
+                if self.condition(condition) {
+                    next_pc = original_pc.wrapping_add((disp as u32) & 0xfffffffe);
+                    num_cycles = 3;
+                }
             }
-        } else {
-            macro_rules! format_i {
-                ($f:expr) => ({
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-
-                    $f(reg1, reg2);
-                });
+            Instruction::MovReg { reg1, reg2 } => {
+                let value = self.reg_gpr(reg1);
+                self.set_reg_gpr(reg2, value);
             }
-
-            macro_rules! format_ii {
-                ($f:expr) => ({
-                    let imm5 = (first_halfword & 0x1f) as u32;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    $f(imm5, reg2);
-                })
+            Instruction::AddReg { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                self.add(lhs, rhs, reg2);
             }
-
-            macro_rules! format_iv {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
-
-                    let disp = ((((((first_halfword as i16) << 6) >> 6) as u32) << 16) | (second_halfword as u32)) & 0xfffffffe;
-                    let target = self.reg_pc.wrapping_add(disp);
-                    $f(target);
-                })
+            Instruction::Sub { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.sub_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
             }
-
-            macro_rules! format_v {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
-
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    let imm16 = second_halfword;
-                    $f(reg1, reg2, imm16);
-                })
+            Instruction::CmpReg { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                self.sub_and_set_flags(lhs, rhs);
             }
-
-            macro_rules! format_vi {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
-
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    let disp16 = second_halfword as i16;
-                    $f(reg1, reg2, disp16);
-                })
+            Instruction::ShlReg { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.shl_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
             }
-
-            let opcode_bits = first_halfword >> 10;
-            match opcode_bits {
-                OPCODE_BITS_MOV_REG => format_i!(|reg1, reg2| {
-                    let value = self.reg_gpr(reg1);
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_ADD_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_SUB => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.sub_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_CMP_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    self.sub_and_set_flags(lhs, rhs);
-                }),
-                OPCODE_BITS_SHL_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.shl_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_SHR_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.shr_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_JMP => format_i!(|reg1, _| {
-                    next_pc = self.reg_gpr(reg1) & 0xfffffffe;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_SAR_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.sar_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_MUL => format_i!(|reg1, reg2| {
-                    let lhs = (self.reg_gpr(reg2) as i32) as i64;
-                    let rhs = (self.reg_gpr(reg1) as i32) as i64;
-                    let res = (lhs * rhs) as u64;
-                    let res_low = res as u32;
-                    let res_high = (res >> 32) as u32;
-                    let overflow = res != ((res_low as i32) as u64);
-                    self.set_reg_gpr(30, res_high);
-                    self.set_reg_gpr(reg2, res_low);
-                    self.set_zero_sign_flags(res_low);
-                    self.psw_overflow = overflow;
-                    num_cycles = 13;
-                }),
-                OPCODE_BITS_DIV => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    if rhs == 0 {
-                        next_pc = self.enter_exception(0xff80);
-                    } else {
-                        let (res, rem, overflow) = if lhs == 0x80000000 && rhs == 0xffffffff {
-                            (lhs, 0, true)
-                        } else {
-                            let lhs = lhs as i32;
-                            let rhs = rhs as i32;
-                            let res = (lhs / rhs) as u32;
-                            let rem = (lhs % rhs) as u32;
-                            (res, rem, false)
-                        };
-                        self.set_reg_gpr(30, rem);
-                        self.set_reg_gpr(reg2, res);
-                        self.set_zero_sign_flags(res);
-                        self.psw_overflow = overflow;
-                    }
-                    num_cycles = 38;
-                }),
-                OPCODE_BITS_MUL_U => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2) as u64;
-                    let rhs = self.reg_gpr(reg1) as u64;
-                    let res = lhs * rhs;
-                    let res_low = res as u32;
-                    let res_high = (res >> 32) as u32;
-                    let overflow = res != (res_low as u64);
-                    self.set_reg_gpr(30, res_high);
-                    self.set_reg_gpr(reg2, res_low);
-                    self.set_zero_sign_flags(res_low);
-                    self.psw_overflow = overflow;
-                    num_cycles = 13;
-                }),
-                OPCODE_BITS_DIV_U => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    if rhs == 0 {
-                        next_pc = self.enter_exception(0xff80);
+            Instruction::ShrReg { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.shr_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::Jmp { reg1 } => {
+                next_pc = self.reg_gpr(reg1) & 0xfffffffe;
+                num_cycles = 3;
+            }
+            Instruction::SarReg { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.sar_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::Mul { reg1, reg2 } => {
+                let lhs = (self.reg_gpr(reg2) as i32) as i64;
+                let rhs = (self.reg_gpr(reg1) as i32) as i64;
+                let res = (lhs * rhs) as u64;
+                let res_low = res as u32;
+                let res_high = (res >> 32) as u32;
+                let overflow = res != ((res_low as i32) as u64);
+                self.set_reg_gpr(30, res_high);
+                self.set_reg_gpr(reg2, res_low);
+                self.set_zero_sign_flags(res_low);
+                self.psw_overflow = overflow;
+                num_cycles = 13;
+            }
+            Instruction::Div { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                if rhs == 0 {
+                    next_pc = self.raise_exception(bus, 0xff80);
+                } else {
+                    let (res, rem, overflow) = if lhs == 0x80000000 && rhs == 0xffffffff {
+                        (lhs, 0, true)
                     } else {
-                        let res = lhs / rhs;
-                        let rem = lhs % rhs;
-                        self.set_reg_gpr(30, rem);
-                        self.set_reg_gpr(reg2, res);
-                        self.set_zero_sign_flags(res);
-                        self.psw_overflow = false;
-                    }
-                    num_cycles = 36;
-                }),
-                OPCODE_BITS_OR => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs | rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_AND => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs & rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_XOR => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs ^ rhs;
+                        let lhs = lhs as i32;
+                        let rhs = rhs as i32;
+                        let res = (lhs / rhs) as u32;
+                        let rem = (lhs % rhs) as u32;
+                        (res, rem, false)
+                    };
+                    self.set_reg_gpr(30, rem);
                     self.set_reg_gpr(reg2, res);
                     self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_NOT => format_i!(|reg1, reg2| {
-                    let res = !self.reg_gpr(reg1);
+                    self.psw_overflow = overflow;
+                }
+                num_cycles = 38;
+            }
+            Instruction::MulU { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2) as u64;
+                let rhs = self.reg_gpr(reg1) as u64;
+                let res = lhs * rhs;
+                let res_low = res as u32;
+                let res_high = (res >> 32) as u32;
+                let overflow = res != (res_low as u64);
+                self.set_reg_gpr(30, res_high);
+                self.set_reg_gpr(reg2, res_low);
+                self.set_zero_sign_flags(res_low);
+                self.psw_overflow = overflow;
+                num_cycles = 13;
+            }
+            Instruction::DivU { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                if rhs == 0 {
+                    next_pc = self.raise_exception(bus, 0xff80);
+                } else {
+                    let res = lhs / rhs;
+                    let rem = lhs % rhs;
+                    self.set_reg_gpr(30, rem);
                     self.set_reg_gpr(reg2, res);
                     self.set_zero_sign_flags(res);
                     self.psw_overflow = false;
-                }),
-                OPCODE_BITS_MOV_IMM => format_ii!(|imm5, reg2| {
-                    let value = sign_extend_imm5(imm5);
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_ADD_IMM_5 => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = sign_extend_imm5(imm5);
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_SETF => format_ii!(|imm5, reg2| {
-                    let set = match imm5 & 0x0f {
-                        OPCODE_CONDITION_BITS_V => self.psw_overflow,
-                        OPCODE_CONDITION_BITS_C => self.psw_carry,
-                        OPCODE_CONDITION_BITS_Z => self.psw_zero,
-                        OPCODE_CONDITION_BITS_NH => self.psw_carry || self.psw_zero,
-                        OPCODE_CONDITION_BITS_N => self.psw_sign,
-                        OPCODE_CONDITION_BITS_T => true,
-                        OPCODE_CONDITION_BITS_LT => self.psw_sign != self.psw_overflow,
-                        OPCODE_CONDITION_BITS_LE => (self.psw_sign != self.psw_overflow) || self.psw_zero,
-                        OPCODE_CONDITION_BITS_NV => !self.psw_overflow,
-                        OPCODE_CONDITION_BITS_NC => !self.psw_carry,
-                        OPCODE_CONDITION_BITS_NZ => !self.psw_zero,
-                        OPCODE_CONDITION_BITS_H => !(self.psw_carry || self.psw_zero),
-                        OPCODE_CONDITION_BITS_P => !self.psw_sign,
-                        OPCODE_CONDITION_BITS_F => false,
-                        OPCODE_CONDITION_BITS_GE => !(self.psw_sign != self.psw_overflow),
-                        OPCODE_CONDITION_BITS_GT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                        _ => panic!("Unrecognized condition: {}", imm5),
-                    };
-                    self.set_reg_gpr(reg2, if set { 1 } else { 0 });
-                }),
-                OPCODE_BITS_CMP_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = sign_extend_imm5(imm5);
-                    self.sub_and_set_flags(lhs, rhs);
-                }),
-                OPCODE_BITS_SHL_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.shl_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_SHR_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.shr_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_CLI => format_ii!(|_, _| {
-                    self.psw_interrupt_disable = false;
-
-                    num_cycles = 12;
-                }),
-                OPCODE_BITS_SAR_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.sar_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_RETI => format_ii!(|_, _| {
-                    next_pc = self.return_from_exception();
-                    num_cycles = 10;
-                }),
-                OPCODE_BITS_HALT => format_ii!(|_, _| {
-                    next_pc = original_pc;
-                    self.is_halted = true;
-                }),
-                OPCODE_BITS_LDSR => format_ii!(|imm5, reg2| {
-                    let value = self.reg_gpr(reg2);
-                    match imm5 {
-                        OPCODE_SYSTEM_REGISTER_ID_EIPC => {
-                            self.reg_eipc = value & 0xfffffffe;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_EIPSW => {
-                            self.reg_eipsw = value & 0x000ff3ff;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_FEPC => {
-                            self.reg_fepc = value & 0xfffffffe;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_FEPSW => {
-                            self.reg_fepsw = value & 0x000ff3ff;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_ECR => {
-                            self.reg_ecr = value as _;
+                }
+                num_cycles = 36;
+            }
+            Instruction::Or { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs | rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::And { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs & rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::Xor { reg1, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs ^ rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::Not { reg1, reg2 } => {
+                let res = !self.reg_gpr(reg1);
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::MovImm { imm5, reg2 } => {
+                let value = imm5 as u32;
+                self.set_reg_gpr(reg2, value);
+            }
+            Instruction::AddImm5 { imm5, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5 as u32;
+                self.add(lhs, rhs, reg2);
+            }
+            Instruction::Setf { condition, reg2 } => {
+                let set = self.condition(condition);
+                self.set_reg_gpr(reg2, if set { 1 } else { 0 });
+            }
+            Instruction::CmpImm { imm5, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5 as u32;
+                self.sub_and_set_flags(lhs, rhs);
+            }
+            Instruction::ShlImm { imm5, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.shl_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::ShrImm { imm5, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.shr_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::Cli => {
+                self.psw_interrupt_disable = false;
+
+                num_cycles = 12;
+            }
+            Instruction::SarImm { imm5, reg2 } => {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.sar_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::Trap { vector } => {
+                // Returning from the exception resumes at the instruction after the trap
+                self.reg_pc = next_pc;
+                next_pc = self.raise_exception(bus, 0xffa0 + vector as u16);
+                num_cycles = 15;
+            }
+            Instruction::Reti => {
+                next_pc = self.return_from_exception();
+                self.is_address_trap_skipped = self.psw_address_trap_enable && next_pc == self.reg_adtre;
+                num_cycles = 10;
+            }
+            Instruction::Halt => {
+                next_pc = original_pc;
+                self.is_halted = true;
+            }
+            Instruction::Ldsr { reg2, system_register } => {
+                let value = self.reg_gpr(reg2);
+                match system_register {
+                    SystemRegister::Eipc => {
+                        self.reg_eipc = value & 0xfffffffe;
+                    }
+                    SystemRegister::Eipsw => {
+                        self.reg_eipsw = value & 0x000ff3ff;
+                    }
+                    SystemRegister::Fepc => {
+                        self.reg_fepc = value & 0xfffffffe;
+                    }
+                    SystemRegister::Fepsw => {
+                        self.reg_fepsw = value & 0x000ff3ff;
+                    }
+                    SystemRegister::Ecr => {
+                        self.reg_ecr = value;
+                    }
+                    SystemRegister::Psw => self.set_reg_psw(value),
+                    SystemRegister::Adtre => {
+                        self.reg_adtre = value & 0xfffffffe;
+                    }
+                    SystemRegister::Chcw => {
+                        logln!(Log::Cpu, "WARNING: ldsr chcw not fully implemented (value: 0x{:08x})", value);
+                        let enable = (value >> 1) & 0x01 == 1;
+                        if enable != self.cache.is_enabled() {
+                            logln!(Log::Cpu, "ldsr chcw cache enable changed to {}", enable);
+                            self.cache.set_is_enabled(enable);
                         }
-                        OPCODE_SYSTEM_REGISTER_ID_PSW => self.set_reg_psw(value),
-                        OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: ldsr chcw not fully implemented (value: 0x{:08x})", value);
-                            let enable = (value >> 1) & 0x01 == 1;
-                            if enable != self.cache.is_enabled() {
-                                logln!(Log::Cpu, "ldsr chcw cache enable changed to {}", enable);
-                                self.cache.set_is_enabled(enable);
-                            }
 
-                            if value & 0x01 == 1 {
-                                let entry_count = ((value >> 8) & 0x7ffff) as usize;
-                                let entry_start = (value >> 20) as usize;
-                                logln!(Log::Cpu, "ldsr chcw request to clear cache for start entry: {}, entry count: {}", entry_start, entry_count);
-                                self.cache.clear_entries(entry_start, entry_count);
-                            } else if (value >> 4) & 0x01 == 1 {
-                                let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to dump instruction cache to 0x{:08x} not implemented yet", addr);
-                            } else if (value >> 5) & 0x01 == 1 {
-                                let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to restore instruction cache from 0x{:08x} not implemented yet", addr);
-                            }
+                        if value & 0x01 == 1 {
+                            let entry_count = ((value >> 8) & 0x7ffff) as usize;
+                            let entry_start = (value >> 20) as usize;
+                            logln!(Log::Cpu, "ldsr chcw request to clear cache for start entry: {}, entry count: {}", entry_start, entry_count);
+                            self.cache.clear_entries(entry_start, entry_count);
+                        } else if (value >> 4) & 0x01 == 1 {
+                            let addr = value & 0xffffff00;
+                            logln!(Log::Cpu, "ldsr chcw request to dump instruction cache to 0x{:08x}", addr);
+                            num_cycles += self.cache.dump(bus, addr);
+                        } else if (value >> 5) & 0x01 == 1 {
+                            let addr = value & 0xffffff00;
+                            logln!(Log::Cpu, "ldsr chcw request to restore instruction cache from 0x{:08x}", addr);
+                            num_cycles += self.cache.restore(bus, addr);
                         }
-                        _ => logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", imm5),
                     }
-                }),
-                OPCODE_BITS_STSR => format_ii!(|imm5, reg2| {
-                    let value = match imm5 {
-                        OPCODE_SYSTEM_REGISTER_ID_EIPC => self.reg_eipc,
-                        OPCODE_SYSTEM_REGISTER_ID_EIPSW => self.reg_eipsw,
-                        OPCODE_SYSTEM_REGISTER_ID_FEPC => self.reg_fepc,
-                        OPCODE_SYSTEM_REGISTER_ID_FEPSW => self.reg_fepsw,
-                        OPCODE_SYSTEM_REGISTER_ID_ECR => self.reg_ecr as _,
-                        OPCODE_SYSTEM_REGISTER_ID_PSW => self.reg_psw(),
-                        OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: stsr chcw not fully implemented");
-                            match self.cache.is_enabled() {
-                                true => 2,
-                                false => 0,
-                            }
-                        }
-                        _ => {
-                            logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", imm5);
-                            0
+                    SystemRegister::Unknown(id) => logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", id),
+                }
+            }
+            Instruction::Stsr { system_register, reg2 } => {
+                let value = match system_register {
+                    SystemRegister::Eipc => self.reg_eipc,
+                    SystemRegister::Eipsw => self.reg_eipsw,
+                    SystemRegister::Fepc => self.reg_fepc,
+                    SystemRegister::Fepsw => self.reg_fepsw,
+                    SystemRegister::Ecr => self.reg_ecr,
+                    SystemRegister::Psw => self.reg_psw(),
+                    SystemRegister::Adtre => self.reg_adtre,
+                    SystemRegister::Chcw => {
+                        logln!(Log::Cpu, "WARNING: stsr chcw not fully implemented");
+                        match self.cache.is_enabled() {
+                            true => 2,
+                            false => 0,
                         }
-                    };
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_SEI => format_ii!(|_, _| {
-                    self.psw_interrupt_disable = true;
-
-                    num_cycles = 12;
-                }),
-                OPCODE_BITS_BIT_STRING => format_ii!(|imm5, _| {
-                    macro_rules! bsu {
-                        ($f:expr) => ({
-                            let mut src_word_addr = self.reg_gpr(30) & 0xfffffffc;
-                            let mut dst_word_addr = self.reg_gpr(29) & 0xfffffffc;
-                            let mut src_bit_offset = self.reg_gpr(27) & 0x1f;
-                            let mut dst_bit_offset = self.reg_gpr(26) & 0x1f;
-                            let mut num_bits = self.reg_gpr(28);
-
-                            while num_bits > 0 {
-                                let src_word = read_word(interconnect, src_word_addr);
-                                let dst_word = read_word(interconnect, dst_word_addr);
+                    }
+                    SystemRegister::Unknown(id) => {
+                        logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", id);
+                        0
+                    }
+                };
+                self.set_reg_gpr(reg2, value);
+            }
+            Instruction::Sei => {
+                self.psw_interrupt_disable = true;
+
+                num_cycles = 12;
+            }
+            Instruction::BitString(bit_string_op) => {
+                // Bit string ops can run for a long time, so they're interruptible: each step only
+                //  processes bits up to the end of the current destination word (or source word, for
+                //  searches), leaving the PC on the instruction until there are none left. r26-r30
+                //  always hold the progress so far, so the op picks up where it left off.
+                macro_rules! bsu {
+                    ($f:expr) => ({
+                        let mut src_word_addr = self.reg_gpr(30) & 0xfffffffc;
+                        let mut dst_word_addr = self.reg_gpr(29) & 0xfffffffc;
+                        let mut src_bit_offset = self.reg_gpr(27) & 0x1f;
+                        let mut dst_bit_offset = self.reg_gpr(26) & 0x1f;
+                        let mut num_bits = self.reg_gpr(28);
+
+                        if num_bits > 0 {
+                            let mut src_word = bus.read_word(src_word_addr);
+                            let mut dst_word = bus.read_word(dst_word_addr);
+                            num_cycles =
+                                6 +
+                                bus.access_cycles(src_word_addr, AccessSize::Word) +
+                                2 * bus.access_cycles(dst_word_addr, AccessSize::Word);
+
+                            while num_bits > 0 && dst_bit_offset < 32 {
+                                if src_bit_offset >= 32 {
+                                    src_bit_offset = 0;
+                                    src_word_addr = src_word_addr.wrapping_add(4);
+                                    src_word = bus.read_word(src_word_addr);
+                                    num_cycles += bus.access_cycles(src_word_addr, AccessSize::Word);
+                                }
+
                                 let src_bit = (src_word >> src_bit_offset) & 0x01;
                                 let dst_bit = (dst_word >> dst_bit_offset) & 0x01;
                                 let res_bit = $f(src_bit, dst_bit) & 0x01;
                                 let dst_bit_mask = !(1 << dst_bit_offset);
-                                let res_word = (dst_word & dst_bit_mask) | (res_bit << dst_bit_offset);
-                                write_word(interconnect, dst_word_addr, res_word);
+                                dst_word = (dst_word & dst_bit_mask) | (res_bit << dst_bit_offset);
 
                                 src_bit_offset += 1;
-                                if src_bit_offset >= 32 {
-                                    src_bit_offset = 0;
-                                    src_word_addr += 4;
-                                }
                                 dst_bit_offset += 1;
-                                if dst_bit_offset >= 32 {
-                                    dst_bit_offset = 0;
-                                    dst_word_addr += 4;
-                                }
-
                                 num_bits -= 1;
                             }
 
-                            self.set_reg_gpr(30, src_word_addr);
-                            self.set_reg_gpr(29, dst_word_addr);
-                            self.set_reg_gpr(27, src_bit_offset);
-                            self.set_reg_gpr(26, dst_bit_offset);
-                            self.set_reg_gpr(28, num_bits);
-                        });
-                    }
-
-                    match imm5 {
-                        OPCODE_BITS_BIT_STRING_OP_ORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit | dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ANDBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit & dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_XORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit ^ dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_MOVBSU => bsu!(|src_bit: u32, _| src_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit | dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ANDNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit & dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_XORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit ^ dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_NOTBSU => bsu!(|src_bit: u32, _| !src_bit),
-                        _ => panic!("Unrecognized bit string op: {:05b}", imm5)
-                    }
-                }),
-                OPCODE_BITS_MOVEA => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as i16) as u32;
-                    let res = lhs.wrapping_add(rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_ADD_IMM_16 => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as i16) as u32;
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_JR => format_iv!(|target| {
-                    next_pc = target;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_JAL => format_iv!(|target| {
-                    self.set_reg_gpr(31, next_pc);
-                    next_pc = target;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_OR_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs | rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_AND_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs & rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_XOR_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs ^ rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_MOVHI => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as u32) << 16;
-                    let res = lhs.wrapping_add(rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_LDB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_byte(addr) as i8) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_LDH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_halfword(addr) as i16) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_LDW | OPCODE_BITS_INW => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = read_word(interconnect, addr);
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STB | OPCODE_BITS_OUTB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2) as u8;
-                    interconnect.write_byte(addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STH | OPCODE_BITS_OUTH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2) as u16;
-                    interconnect.write_halfword(addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STW | OPCODE_BITS_OUTW => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2);
-                    write_word(interconnect, addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_INB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_byte(addr) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_INH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_halfword(addr) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_EXTENDED => {
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
-
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-
-                    let subop_bits = second_halfword >> 10;
-
-                    match subop_bits {
-                        OPCODE_BITS_SUB_OP_CMPF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs - rhs;
-
-                            self.set_fp_flags(value);
+                            bus.write_word(dst_word_addr, dst_word);
 
-                            num_cycles = 10;
+                            if src_bit_offset >= 32 {
+                                src_bit_offset = 0;
+                                src_word_addr = src_word_addr.wrapping_add(4);
+                            }
+                            if dst_bit_offset >= 32 {
+                                dst_bit_offset = 0;
+                                dst_word_addr = dst_word_addr.wrapping_add(4);
+                            }
                         }
-                        OPCODE_BITS_SUB_OP_CVT_WS => {
-                            let value = (self.reg_gpr(reg1) as i32) as f32;
-                            self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                        self.set_reg_gpr(30, src_word_addr);
+                        self.set_reg_gpr(29, dst_word_addr);
+                        self.set_reg_gpr(27, src_bit_offset);
+                        self.set_reg_gpr(26, dst_bit_offset);
+                        self.set_reg_gpr(28, num_bits);
 
-                            num_cycles = 16;
+                        if num_bits > 0 {
+                            next_pc = original_pc;
                         }
-                        OPCODE_BITS_SUB_OP_CVT_SW => {
-                            let value = (self.reg_gpr_float(reg1).round() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
+                    });
+                }
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+                // Searches for the first bit equal to $bit, stopping just past it, so that repeating the
+                //  search finds the next one. r29 counts every bit passed over, including the one found.
+                macro_rules! sch {
+                    ($bit:expr, $is_upward:expr) => ({
+                        let mut src_word_addr = self.reg_gpr(30) & 0xfffffffc;
+                        let mut src_bit_offset = self.reg_gpr(27) & 0x1f;
+                        let mut num_bits = self.reg_gpr(28);
+                        let mut num_skipped_bits = self.reg_gpr(29);
+
+                        let mut found = false;
+                        if num_bits > 0 {
+                            let src_word = bus.read_word(src_word_addr);
+                            num_cycles = 6 + bus.access_cycles(src_word_addr, AccessSize::Word);
+
+                            let mut is_end_of_word = false;
+                            while num_bits > 0 && !found && !is_end_of_word {
+                                found = (src_word >> src_bit_offset) & 0x01 == $bit;
+
+                                if $is_upward {
+                                    src_bit_offset += 1;
+                                    if src_bit_offset >= 32 {
+                                        src_bit_offset = 0;
+                                        src_word_addr = src_word_addr.wrapping_add(4);
+                                        is_end_of_word = true;
+                                    }
+                                } else if src_bit_offset == 0 {
+                                    src_bit_offset = 31;
+                                    src_word_addr = src_word_addr.wrapping_sub(4);
+                                    is_end_of_word = true;
+                                } else {
+                                    src_bit_offset -= 1;
+                                }
 
-                            num_cycles = 14;
+                                num_skipped_bits = num_skipped_bits.wrapping_add(1);
+                                num_bits -= 1;
+                            }
                         }
-                        OPCODE_BITS_SUB_OP_ADDF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs + rhs;
-                            self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                        self.set_reg_gpr(30, src_word_addr);
+                        self.set_reg_gpr(27, src_bit_offset);
+                        self.set_reg_gpr(28, num_bits);
+                        self.set_reg_gpr(29, num_skipped_bits);
+                        self.psw_zero = !found;
 
-                            num_cycles = 28;
+                        if num_bits > 0 && !found {
+                            next_pc = original_pc;
                         }
-                        OPCODE_BITS_SUB_OP_SUBF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
+                    });
+                }
+
+                match bit_string_op {
+                    BitStringOp::Sch0bsu => sch!(0, true),
+                    BitStringOp::Sch0bsd => sch!(0, false),
+                    BitStringOp::Sch1bsu => sch!(1, true),
+                    BitStringOp::Sch1bsd => sch!(1, false),
+                    BitStringOp::Orbsu => bsu!(|src_bit: u32, dst_bit: u32| src_bit | dst_bit),
+                    BitStringOp::Andbsu => bsu!(|src_bit: u32, dst_bit: u32| src_bit & dst_bit),
+                    BitStringOp::Xorbsu => bsu!(|src_bit: u32, dst_bit: u32| src_bit ^ dst_bit),
+                    BitStringOp::Movbsu => bsu!(|src_bit: u32, _| src_bit),
+                    BitStringOp::Ornbsu => bsu!(|src_bit: u32, dst_bit: u32| !src_bit | dst_bit),
+                    BitStringOp::Andnbsu => bsu!(|src_bit: u32, dst_bit: u32| !src_bit & dst_bit),
+                    BitStringOp::Xornbsu => bsu!(|src_bit: u32, dst_bit: u32| !src_bit ^ dst_bit),
+                    BitStringOp::Notbsu => bsu!(|src_bit: u32, _| !src_bit),
+                }
+            }
+            Instruction::Movea { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as i16) as u32;
+                let res = lhs.wrapping_add(rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::AddImm16 { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as i16) as u32;
+                self.add(lhs, rhs, reg2);
+            }
+            Instruction::Jr { disp } => {
+                next_pc = original_pc.wrapping_add((disp as u32) & 0xfffffffe);
+                num_cycles = 3;
+            }
+            Instruction::Jal { disp } => {
+                self.set_reg_gpr(31, next_pc);
+                next_pc = original_pc.wrapping_add((disp as u32) & 0xfffffffe);
+                num_cycles = 3;
+            }
+            Instruction::OrI { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs | rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::AndI { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs & rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::XorI { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs ^ rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }
+            Instruction::Movhi { reg1, reg2, imm16 } => {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as u32) << 16;
+                let res = lhs.wrapping_add(rhs);
+                self.set_reg_gpr(reg2, res);
+            }
+            Instruction::Ldb { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = (bus.read_byte(addr) as i8) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Byte);
+            }
+            Instruction::Ldh { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = (bus.read_halfword(addr) as i16) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Halfword);
+            }
+            Instruction::Ldw { reg1, reg2, disp16 } | Instruction::Inw { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffc;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = bus.read_word(addr);
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Word);
+            }
+            Instruction::Caxi { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffc;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = bus.read_word(addr);
+                let compare_value = self.reg_gpr(reg2);
+                self.sub_and_set_flags(compare_value, value);
+                // The word is always written back; it's only changed if the comparison succeeded
+                let exchange_value = if compare_value == value { self.reg_gpr(30) } else { value };
+                bus.write_word(addr, exchange_value);
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 22 + 2 * bus.access_cycles(addr, AccessSize::Word);
+            }
+            Instruction::Stb { reg1, reg2, disp16 } | Instruction::Outb { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = self.reg_gpr(reg2) as u8;
+                bus.write_byte(addr, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Byte);
+            }
+            Instruction::Sth { reg1, reg2, disp16 } | Instruction::Outh { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = self.reg_gpr(reg2) as u16;
+                bus.write_halfword(addr, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Halfword);
+            }
+            Instruction::Stw { reg1, reg2, disp16 } | Instruction::Outw { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffc;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = self.reg_gpr(reg2);
+                bus.write_word(addr, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Word);
+            }
+            Instruction::Inb { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = bus.read_byte(addr) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Byte);
+            }
+            Instruction::Inh { reg1, reg2, disp16 } => {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                trigger_watchpoint |= self.check_watchpoints(addr);
+                let value = bus.read_halfword(addr) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 2 + bus.access_cycles(addr, AccessSize::Halfword);
+            }
+            Instruction::Extended { subop, reg1, reg2 } => {
+                match subop {
+                    SubOp::CmpfS => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        if is_reserved_operand(lhs) || is_reserved_operand(rhs) {
+                            self.psw_fp_reserved_operand = true;
+                            next_pc = self.raise_exception(bus, 0xff60);
+                        } else {
                             let value = lhs - rhs;
-                            self.set_reg_gpr_float(reg2, value);
 
                             self.set_fp_flags(value);
+                        }
 
-                            num_cycles = 28;
+                        num_cycles = 10;
+                    }
+                    SubOp::CvtWs => {
+                        let original = self.reg_gpr(reg1) as i32;
+                        let value = original as f32;
+                        if value as f64 != original as f64 {
+                            self.psw_fp_precision_degredation = true;
                         }
-                        OPCODE_BITS_SUB_OP_MULF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs * rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                        self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                        self.set_fp_flags(value);
 
-                            num_cycles = 30;
+                        num_cycles = 16;
+                    }
+                    SubOp::CvtSw => {
+                        let original = self.reg_gpr_float(reg1);
+                        if let Some(exception_code) = self.fp_to_int(original, original.round(), reg2) {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        OPCODE_BITS_SUB_OP_DIVF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs / rhs;
-                            self.set_reg_gpr_float(reg2, value);
-
-                            self.set_fp_flags(value);
 
-                            num_cycles = 44;
+                        num_cycles = 14;
+                    }
+                    SubOp::AddfS => {
+                        if let Some(exception_code) = self.fp_arithmetic(reg1, reg2, |lhs, rhs| lhs + rhs) {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        OPCODE_BITS_SUB_OP_XB => {
-                            let original = self.reg_gpr(reg2);
-                            let value = (original & 0xffff0000) | ((original & 0x0000ff00) >> 8) | ((original & 0x000000ff) << 8);
-                            self.set_reg_gpr(reg2, value);
 
-                            num_cycles = 6;
+                        num_cycles = 28;
+                    }
+                    SubOp::SubfS => {
+                        if let Some(exception_code) = self.fp_arithmetic(reg1, reg2, |lhs, rhs| lhs - rhs) {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        OPCODE_BITS_SUB_OP_XH => {
-                            let original = self.reg_gpr(reg2);
-                            let value = (original >> 16) | ((original & 0xffff) << 16);
-                            self.set_reg_gpr(reg2, value);
+
+                        num_cycles = 28;
+                    }
+                    SubOp::MulfS => {
+                        if let Some(exception_code) = self.fp_arithmetic(reg1, reg2, |lhs, rhs| lhs * rhs) {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        OPCODE_BITS_SUB_OP_REV => {
-                            let original = self.reg_gpr(reg1);
-                            let mut value: u32 = 0;
-                            for x in 0..32 {
-                                value = (value << 1) | ((original >> x) & 0x01);
-                            }
-                            self.set_reg_gpr(reg2, value);
 
-                            num_cycles = 22;
+                        num_cycles = 30;
+                    }
+                    SubOp::DivfS => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        let exception_code = if rhs == 0.0 && !is_reserved_operand(lhs) {
+                            if lhs == 0.0 {
+                                self.psw_fp_invalid_operation = true;
+                                Some(0xff70)
+                            } else {
+                                self.psw_fp_zero_division = true;
+                                Some(0xff68)
+                            }
+                        } else {
+                            self.fp_arithmetic(reg1, reg2, |lhs, rhs| lhs / rhs)
+                        };
+                        if let Some(exception_code) = exception_code {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        OPCODE_BITS_SUB_OP_TRNC_SW => {
-                            let value = (self.reg_gpr_float(reg1).trunc() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+                        num_cycles = 44;
+                    }
+                    SubOp::Xb => {
+                        let original = self.reg_gpr(reg2);
+                        let value = (original & 0xffff0000) | ((original & 0x0000ff00) >> 8) | ((original & 0x000000ff) << 8);
+                        self.set_reg_gpr(reg2, value);
 
-                            num_cycles = 14;
+                        num_cycles = 6;
+                    }
+                    SubOp::Xh => {
+                        let original = self.reg_gpr(reg2);
+                        let value = (original >> 16) | ((original & 0xffff) << 16);
+                        self.set_reg_gpr(reg2, value);
+                    }
+                    SubOp::Rev => {
+                        let original = self.reg_gpr(reg1);
+                        let mut value: u32 = 0;
+                        for x in 0..32 {
+                            value = (value << 1) | ((original >> x) & 0x01);
                         }
-                        OPCODE_BITS_SUB_OP_MPYHW => {
-                            let lhs = self.reg_gpr(reg2) as i32;
-                            let rhs = ((self.reg_gpr(reg1) as i32) << 15) >> 15;
-                            let value = (lhs * rhs) as u32;
-                            self.set_reg_gpr(reg2, value);
+                        self.set_reg_gpr(reg2, value);
 
-                            num_cycles = 9;
+                        num_cycles = 22;
+                    }
+                    SubOp::TrncSw => {
+                        let original = self.reg_gpr_float(reg1);
+                        if let Some(exception_code) = self.fp_to_int(original, original.trunc(), reg2) {
+                            next_pc = self.raise_exception(bus, exception_code);
                         }
-                        _ => panic!("Unrecognized subop bits: {:06b}", subop_bits)
+
+                        num_cycles = 14;
+                    }
+                    SubOp::Mpyhw => {
+                        let lhs = self.reg_gpr(reg2) as i32;
+                        let rhs = ((self.reg_gpr(reg1) as i32) << 15) >> 15;
+                        let value = (lhs * rhs) as u32;
+                        self.set_reg_gpr(reg2, value);
+
+                        num_cycles = 9;
                     }
                 }
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, first_halfword),
             }
+            // Invalid opcode exception; the instruction itself is restarted on return
+            Instruction::Illegal { .. } => next_pc = self.raise_exception(bus, 0xff90),
         }
 
         self.reg_pc = next_pc;
 
-        (num_cycles, trigger_watchpoint)
+        (num_cycles + fetch_cycles, trigger_watchpoint)
+    }
+
+    // Fetches the instruction at pc, returning it along with the cycles spent fetching it
+    fn fetch<B: Bus>(&mut self, bus: &mut B, pc: u32) -> (Instruction, u32) {
+        let generation = bus.code_generation(pc);
+
+        if let Some(generation) = generation {
+            if let Some(instruction) = self.decode_cache.get(pc, generation) {
+                let result = self.cache.access(bus, pc);
+                let mut cycles = fetch_cycles(bus, pc, result);
+                if instruction.is_long() {
+                    let result = self.cache.access(bus, pc.wrapping_add(2));
+                    cycles += fetch_cycles(bus, pc.wrapping_add(2), result);
+                }
+                return (instruction, cycles);
+            }
+        }
+
+        let (first_halfword, result) = self.cache.read_halfword(bus, pc);
+        let mut cycles = fetch_cycles(bus, pc, result);
+        let second_halfword = if is_long_instruction(first_halfword) {
+            let (second_halfword, result) = self.cache.read_halfword(bus, pc.wrapping_add(2));
+            cycles += fetch_cycles(bus, pc.wrapping_add(2), result);
+            second_halfword
+        } else {
+            0
+        };
+
+        let instruction = Instruction::decode(first_halfword, second_halfword);
+
+        if let Some(generation) = generation {
+            self.decode_cache.insert(pc, generation, instruction);
+        }
+
+        (instruction, cycles)
     }
 
     fn check_watchpoints(&self, addr: u32) -> bool {
         self.watchpoints.len() != 0 && self.watchpoints.contains(&addr)
     }
 
+    fn condition(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::V => self.psw_overflow,
+            Condition::C => self.psw_carry,
+            Condition::Z => self.psw_zero,
+            Condition::Nh => self.psw_carry || self.psw_zero,
+            Condition::N => self.psw_sign,
+            Condition::T => true,
+            Condition::Lt => self.psw_sign != self.psw_overflow,
+            Condition::Le => (self.psw_sign != self.psw_overflow) || self.psw_zero,
+            Condition::Nv => !self.psw_overflow,
+            Condition::Nc => !self.psw_carry,
+            Condition::Nz => !self.psw_zero,
+            Condition::H => !(self.psw_carry || self.psw_zero),
+            Condition::P => !self.psw_sign,
+            Condition::F => false,
+            Condition::Ge => !(self.psw_sign != self.psw_overflow),
+            Condition::Gt => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
+        }
+    }
+
     fn add(&mut self, lhs: u32, rhs: u32, reg2: usize) {
         let (res, carry) = lhs.overflowing_add(rhs);
         self.set_reg_gpr(reg2, res);
@@ -1003,59 +1288,650 @@ impl V810 {
         self.psw_zero = value == 0.0;
     }
 
-    pub fn request_interrupt(&mut self, exception_code: u16) {
+    // Performs a single precision operation on reg2 and reg1 like the FPU does. The operation is
+    //  computed in double precision (exactly, for everything but division, which is still exact
+    //  enough to tell whether rounding lost precision). Reserved operands and overflow raise an
+    //  exception, in which case the exception code is returned and reg2 is left alone, while
+    //  underflow and precision degradation only set their (sticky) PSW flags.
+    fn fp_arithmetic<F: FnOnce(f64, f64) -> f64>(&mut self, reg1: usize, reg2: usize, f: F) -> Option<u16> {
+        let lhs = self.reg_gpr_float(reg2);
+        let rhs = self.reg_gpr_float(reg1);
+        if is_reserved_operand(lhs) || is_reserved_operand(rhs) {
+            self.psw_fp_reserved_operand = true;
+            return Some(0xff60);
+        }
+
+        let exact_value = f(lhs as f64, rhs as f64);
+        let mut value = exact_value as f32;
+        if value.is_infinite() {
+            self.psw_fp_overflow = true;
+            return Some(0xff64);
+        }
+        if (value != 0.0 && !value.is_normal()) || (value == 0.0 && exact_value != 0.0) {
+            // Results too small to be normalized are flushed to zero
+            self.psw_fp_underflow = true;
+            value = 0.0;
+        }
+        if value as f64 != exact_value {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        self.set_reg_gpr_float(reg2, value);
+        self.set_fp_flags(value);
+
+        None
+    }
+
+    // Stores an already-rounded float (from CVT.SW/TRNC.SW) to reg2 as an integer, returning the
+    //  exception code to raise instead if the original is a reserved operand or out of range
+    fn fp_to_int(&mut self, original: f32, rounded: f32, reg2: usize) -> Option<u16> {
+        if is_reserved_operand(original) {
+            self.psw_fp_reserved_operand = true;
+            return Some(0xff60);
+        }
+        if !(-2147483648.0..2147483648.0).contains(&rounded) {
+            self.psw_fp_invalid_operation = true;
+            return Some(0xff70);
+        }
+        if rounded != original {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        let value = (rounded as i32) as u32;
+        self.set_reg_gpr(reg2, value);
+
+        self.psw_overflow = false;
+        self.set_zero_sign_flags(value);
+
+        None
+    }
+
+    // Returns whether the interrupt was taken
+    pub fn request_interrupt(&mut self, exception_code: u16) -> bool {
         if self.psw_nmi_pending || self.psw_exception_pending || self.psw_interrupt_disable {
-            return;
+            return false;
         }
 
         let mut interrupt_level = ((exception_code as u32) >> 4) & 0x0f;
         if interrupt_level < self.psw_interrupt_mask_level {
-            return;
+            return false;
         }
 
         if interrupt_level < 15 {
             interrupt_level += 1;
         }
 
+        self.last_exception = Some(Exception {
+            kind: ExceptionKind::Interrupt,
+            code: exception_code,
+            pc: self.reg_pc,
+        });
         self.reg_pc = self.enter_exception(exception_code);
 
         self.psw_interrupt_mask_level = interrupt_level;
+
+        true
+    }
+
+    // Raises an exception caused by the instruction being executed, returning the address to continue from.
+    //  Interrupts are only accepted while no exception is being handled, but instructions can fault at any
+    //  time: a fault while handling an exception (EP set) is a duplexed exception, and a fault while
+    //  handling a duplexed exception (NP set) is fatal.
+    fn raise_exception<B: Bus>(&mut self, bus: &mut B, exception_code: u16) -> u32 {
+        let pc = self.reg_pc;
+        let (kind, handler_pc) = if self.psw_nmi_pending {
+            (ExceptionKind::FatalException, self.enter_fatal_exception(bus, exception_code))
+        } else if self.psw_exception_pending {
+            (ExceptionKind::DuplexedException, self.enter_duplexed_exception(exception_code))
+        } else {
+            (ExceptionKind::Exception, self.enter_exception(exception_code))
+        };
+        self.last_exception = Some(Exception {
+            kind: kind,
+            code: exception_code,
+            pc: pc,
+        });
+        handler_pc
     }
 
     fn enter_exception(&mut self, exception_code: u16) -> u32 {
         logln!(Log::Cpu, "Entering exception (code: 0x{:04x})", exception_code);
-        // TODO: Duplexed exception
         self.reg_eipc = self.reg_pc;
         if self.is_halted {
             self.reg_eipc = self.reg_eipc.wrapping_add(2);
             self.is_halted = false;
         }
         self.reg_eipsw = self.reg_psw();
-        self.reg_ecr = exception_code;
+        self.reg_ecr = (self.reg_ecr & 0xffff0000) | (exception_code as u32);
         self.psw_exception_pending = true;
         self.psw_interrupt_disable = true;
         self.psw_address_trap_enable = false;
-        0xffff0000 | (exception_code as u32)
+        let handler_offset = match exception_code {
+            // All FPU exceptions share a handler
+            0xff60 ... 0xff7f => 0xff60,
+            // Otherwise, exception codes that share a handler (such as TRAP's 0xffa0-0xffaf) differ only in their low bits
+            _ => exception_code & 0xfff0,
+        };
+        0xffff0000 | (handler_offset as u32)
+    }
+
+    fn enter_duplexed_exception(&mut self, exception_code: u16) -> u32 {
+        logln!(Log::Cpu, "Entering duplexed exception (code: 0x{:04x})", exception_code);
+        self.reg_fepc = self.reg_pc;
+        self.reg_fepsw = self.reg_psw();
+        self.reg_ecr = (self.reg_ecr & 0x0000ffff) | ((exception_code as u32) << 16);
+        self.psw_nmi_pending = true;
+        self.psw_interrupt_disable = true;
+        self.psw_address_trap_enable = false;
+        0xffffffd0
+    }
+
+    // Dumps the exception code, PSW and PC to the start of the address space and stops the CPU. Only a
+    //  reset recovers from this; NP stays set, so not even an interrupt can wake the CPU back up.
+    fn enter_fatal_exception<B: Bus>(&mut self, bus: &mut B, exception_code: u16) -> u32 {
+        logln!(Log::Cpu, "Fatal exception (code: 0x{:04x}, pc: 0x{:08x})", exception_code, self.reg_pc);
+        let psw = self.reg_psw();
+        bus.write_word(0x00000000, 0xffff0000 | (exception_code as u32));
+        bus.write_word(0x00000004, psw);
+        bus.write_word(0x00000008, self.reg_pc);
+        self.is_halted = true;
+        self.reg_pc
     }
 
     fn return_from_exception(&mut self) -> u32 {
-        logln!(Log::Cpu, "Returning from exception (code: 0x{:04x})", self.reg_ecr);
-        let psw = self.reg_eipsw;
-        self.set_reg_psw(psw);
-        self.reg_eipc
+        if self.psw_nmi_pending {
+            logln!(Log::Cpu, "Returning from duplexed exception (code: 0x{:04x})", self.reg_ecr >> 16);
+            let psw = self.reg_fepsw;
+            self.set_reg_psw(psw);
+            self.reg_fepc
+        } else {
+            logln!(Log::Cpu, "Returning from exception (code: 0x{:04x})", self.reg_ecr & 0xffff);
+            let psw = self.reg_eipsw;
+            self.set_reg_psw(psw);
+            self.reg_eipc
+        }
     }
 }
 
-fn sign_extend_imm5(imm5: u32) -> u32 {
-    (((imm5 as i32) << 27) >> 27) as _
+// NaNs, infinities and denormals can't be used as FPU operands
+fn is_reserved_operand(value: f32) -> bool {
+    let bits = value.to_bits();
+    let exponent = (bits >> 23) & 0xff;
+    let mantissa = bits & 0x007fffff;
+    exponent == 0xff || (exponent == 0 && mantissa != 0)
 }
 
-fn read_word(interconnect: &mut Interconnect, addr: u32) -> u32 {
-    (interconnect.read_halfword(addr) as u32) |
-    ((interconnect.read_halfword(addr + 2) as u32) << 16)
+// Cycles spent fetching a halfword, on top of the instruction's own cycles. The pipeline overlaps
+//  an uncached fetch's bus cycle with execution, so only its wait states are felt. A miss has to
+//  fill a whole 4-byte subblock first, and a hit doesn't touch the bus at all.
+fn fetch_cycles<B: Bus>(bus: &B, addr: u32, result: CacheResult) -> u32 {
+    match result {
+        CacheResult::Hit => 0,
+        CacheResult::Miss => bus.access_cycles(addr, AccessSize::Word),
+        CacheResult::Disabled => bus.access_cycles(addr, AccessSize::Halfword) - 2,
+    }
 }
 
-fn write_word(interconnect: &mut Interconnect, addr: u32, value: u32) {
-    interconnect.write_halfword(addr, value as _);
-    interconnect.write_halfword(addr + 2, (value >> 16) as _);
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::assemble;
+    #[cfg(feature = "dynarec")]
+    use rom::Rom;
+    #[cfg(feature = "dynarec")]
+    use sram::Sram;
+    #[cfg(feature = "dynarec")]
+    use virtual_boy::{ExecutionMode, VirtualBoy};
+
+    const CODE: u32 = 0x07000100;
+
+    // Assembles source at CODE and points a CPU at it, with PSW cleared (NP is set at reset)
+    fn setup(source: &str) -> (V810, FlatBus) {
+        let mut bus = FlatBus::new();
+        load(&mut bus, CODE, source);
+        let mut cpu = V810::new();
+        cpu.set_reg_psw(0);
+        cpu.reg_pc = CODE;
+        (cpu, bus)
+    }
+
+    fn load(bus: &mut FlatBus, origin: u32, source: &str) {
+        for segment in assemble(source, origin).unwrap() {
+            for (i, &byte) in segment.bytes.iter().enumerate() {
+                bus.write_byte(segment.addr.wrapping_add(i as u32), byte);
+            }
+        }
+    }
+
+    #[test]
+    fn div_by_zero() {
+        let (mut cpu, mut bus) = setup("div r11, r10");
+        cpu.set_reg_gpr(10, 7);
+        cpu.set_reg_gpr(11, 0);
+        cpu.set_reg_gpr(30, 0x1234);
+        cpu.step(&mut bus);
+
+        assert_eq!(cpu.reg_pc(), 0xffffff80);
+        assert_eq!(cpu.reg_eipc(), CODE);
+        assert_eq!(cpu.reg_eipsw(), 0);
+        assert_eq!(cpu.reg_ecr() & 0xffff, 0xff80);
+        assert_eq!(cpu.reg_psw(), (1 << 14) | (1 << 12));
+        assert_eq!(cpu.take_exception(), Some(Exception {
+            kind: ExceptionKind::Exception,
+            code: 0xff80,
+            pc: CODE,
+        }));
+        assert_eq!(cpu.reg_gpr(10), 7);
+        assert_eq!(cpu.reg_gpr(30), 0x1234);
+    }
+
+    #[test]
+    fn trap() {
+        let (mut cpu, mut bus) = setup("trap 21");
+        load(&mut bus, 0xffffffb0, "reti");
+        cpu.set_reg_psw(0x0001);
+        cpu.step(&mut bus);
+
+        assert_eq!(cpu.reg_pc(), 0xffffffb0);
+        assert_eq!(cpu.reg_eipc(), CODE + 2);
+        assert_eq!(cpu.reg_eipsw(), 0x0001);
+        assert_eq!(cpu.reg_ecr() & 0xffff, 0xffb5);
+        assert_eq!(cpu.take_exception(), Some(Exception {
+            kind: ExceptionKind::Exception,
+            code: 0xffb5,
+            pc: CODE + 2,
+        }));
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(cpu.reg_psw(), 0x0001);
+    }
+
+    #[test]
+    fn caxi() {
+        let (mut cpu, mut bus) = setup("caxi 4[r11], r10\ncaxi 4[r11], r12");
+        bus.write_word(0x1004, 0x1234);
+        cpu.set_reg_gpr(10, 0x1234);
+        cpu.set_reg_gpr(11, 0x1000);
+        cpu.set_reg_gpr(12, 0x5678);
+        cpu.set_reg_gpr(30, 0xabcd);
+
+        // Equal, so r30 is stored
+        cpu.step(&mut bus);
+        assert_eq!(bus.read_word(0x1004), 0xabcd);
+        assert_eq!(cpu.reg_gpr(10), 0x1234);
+        assert_eq!(cpu.reg_psw() & 0x01, 1);
+
+        // Not equal, so memory is left as it is
+        cpu.step(&mut bus);
+        assert_eq!(bus.read_word(0x1004), 0xabcd);
+        assert_eq!(cpu.reg_gpr(12), 0xabcd);
+        assert_eq!(cpu.reg_psw() & 0x01, 0);
+    }
+
+    #[test]
+    fn movbsu() {
+        let (mut cpu, mut bus) = setup("movbsu");
+        bus.write_word(0x1000, 0x89abcdef);
+        bus.write_word(0x1004, 0x01234567);
+        bus.write_word(0x2000, 0xffffffff);
+        bus.write_word(0x2004, 0xffffffff);
+        cpu.set_reg_gpr(30, 0x1000);
+        cpu.set_reg_gpr(27, 4);
+        cpu.set_reg_gpr(29, 0x2000);
+        cpu.set_reg_gpr(26, 8);
+        cpu.set_reg_gpr(28, 40);
+
+        // Each step stops at the end of a destination word, leaving the PC on the instruction
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE);
+        assert_eq!(bus.read_word(0x2000), 0x9abcdeff);
+        assert_eq!(bus.read_word(0x2004), 0xffffffff);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1000, 28));
+        assert_eq!((cpu.reg_gpr(29), cpu.reg_gpr(26)), (0x2004, 0));
+        assert_eq!(cpu.reg_gpr(28), 16);
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(bus.read_word(0x2000), 0x9abcdeff);
+        assert_eq!(bus.read_word(0x2004), 0xffff5678);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1004, 12));
+        assert_eq!((cpu.reg_gpr(29), cpu.reg_gpr(26)), (0x2004, 16));
+        assert_eq!(cpu.reg_gpr(28), 0);
+    }
+
+    #[test]
+    fn notbsu() {
+        let (mut cpu, mut bus) = setup("notbsu");
+        bus.write_word(0x1000, 0x0000ff00);
+        bus.write_word(0x2000, 0x12345678);
+        cpu.set_reg_gpr(30, 0x1000);
+        cpu.set_reg_gpr(27, 4);
+        cpu.set_reg_gpr(29, 0x2000);
+        cpu.set_reg_gpr(26, 0);
+        cpu.set_reg_gpr(28, 8);
+        cpu.step(&mut bus);
+
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(bus.read_word(0x2000), 0x1234560f);
+    }
+
+    #[test]
+    fn sch1bsu() {
+        let (mut cpu, mut bus) = setup("sch1bsu\nsch1bsu");
+        bus.write_word(0x1000, 0x00000100);
+        bus.write_word(0x1004, 0x00000000);
+        cpu.set_reg_gpr(30, 0x1000);
+        cpu.set_reg_gpr(27, 4);
+        cpu.set_reg_gpr(28, 64);
+        cpu.set_reg_gpr(29, 0);
+
+        // Stops just past the bit it found, counting it
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(cpu.reg_psw() & 0x01, 0);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1000, 9));
+        assert_eq!(cpu.reg_gpr(28), 59);
+        assert_eq!(cpu.reg_gpr(29), 5);
+
+        // Finds nothing more, one word per step
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1004, 0));
+        assert_eq!(cpu.reg_gpr(28), 36);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1008, 0));
+        assert_eq!(cpu.reg_gpr(28), 4);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 4);
+        assert_eq!(cpu.reg_psw() & 0x01, 1);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1008, 4));
+        assert_eq!(cpu.reg_gpr(28), 0);
+        assert_eq!(cpu.reg_gpr(29), 64);
+    }
+
+    #[test]
+    fn sch0bsd() {
+        let (mut cpu, mut bus) = setup("sch0bsd");
+        bus.write_word(0x1004, 0xffffffff);
+        bus.write_word(0x1000, 0x7fffffff);
+        cpu.set_reg_gpr(30, 0x1004);
+        cpu.set_reg_gpr(27, 3);
+        cpu.set_reg_gpr(28, 64);
+        cpu.set_reg_gpr(29, 0);
+
+        // Downward searches move on to the top of the previous word
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1000, 31));
+        assert_eq!(cpu.reg_gpr(29), 4);
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(cpu.reg_psw() & 0x01, 0);
+        assert_eq!((cpu.reg_gpr(30), cpu.reg_gpr(27)), (0x1000, 30));
+        assert_eq!(cpu.reg_gpr(28), 59);
+        assert_eq!(cpu.reg_gpr(29), 5);
+    }
+
+    #[test]
+    fn duplexed_exception() {
+        let (mut cpu, mut bus) = setup("div r0, r10");
+        cpu.set_reg_psw(1 << 14);
+        cpu.step(&mut bus);
+
+        assert_eq!(cpu.reg_pc(), 0xffffffd0);
+        assert_eq!(cpu.reg_fepc(), CODE);
+        assert_eq!(cpu.reg_fepsw(), 1 << 14);
+        assert_eq!(cpu.reg_ecr(), 0xff80fff0);
+        assert_eq!(cpu.reg_psw(), (1 << 15) | (1 << 14) | (1 << 12));
+        assert_eq!(cpu.take_exception(), Some(Exception {
+            kind: ExceptionKind::DuplexedException,
+            code: 0xff80,
+            pc: CODE,
+        }));
+    }
+
+    #[test]
+    fn fatal_exception() {
+        let (mut cpu, mut bus) = setup("div r0, r10");
+        cpu.set_reg_psw((1 << 15) | (1 << 14));
+        cpu.step(&mut bus);
+
+        assert!(cpu.is_halted());
+        assert_eq!(bus.read_word(0x00000000), 0xffffff80);
+        assert_eq!(bus.read_word(0x00000004), (1 << 15) | (1 << 14));
+        assert_eq!(bus.read_word(0x00000008), CODE);
+        assert_eq!(cpu.take_exception(), Some(Exception {
+            kind: ExceptionKind::FatalException,
+            code: 0xff80,
+            pc: CODE,
+        }));
+
+        // Only a reset gets the CPU going again
+        assert_eq!(cpu.step(&mut bus), (1, false));
+        assert!(!cpu.request_interrupt(0xfe00));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.reg_pc(), CODE);
+    }
+
+    // Runs a program in each execution mode, checking that they all end up in the same state and
+    //  returning the machine that ran it in lockstep. ROM code starts at 0x07000000 (which the reset
+    //  vector jumps to), and anything assembled outside ROM is loaded into memory before it starts.
+    #[cfg(feature = "dynarec")]
+    fn run_translated(source: &str) -> VirtualBoy {
+        let segments = assemble(source, 0x07000000).unwrap();
+        let mut rom = vec![0; 0x10000];
+        let (first_halfword, second_halfword) = Instruction::Jr { disp: -0xfff0 }.encode();
+        rom[0xfff0] = first_halfword as u8;
+        rom[0xfff1] = (first_halfword >> 8) as u8;
+        rom[0xfff2] = second_halfword as u8;
+        rom[0xfff3] = (second_halfword >> 8) as u8;
+        for segment in segments.iter().filter(|segment| segment.addr >> 24 == 0x07) {
+            let offset = (segment.addr & 0xffff) as usize;
+            rom[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+        }
+
+        let run = |execution_mode| {
+            let mut virtual_boy = VirtualBoy::new(Rom::from_bytes(&rom).unwrap(), Sram::new());
+            for segment in segments.iter().filter(|segment| segment.addr >> 24 != 0x07) {
+                for (i, &byte) in segment.bytes.iter().enumerate() {
+                    virtual_boy.interconnect.write_byte(segment.addr.wrapping_add(i as u32), byte);
+                }
+            }
+            virtual_boy.set_execution_mode(execution_mode);
+            for _ in 0..20 {
+                virtual_boy.run_cycles(10000).unwrap();
+            }
+            // Every program ends with a halt
+            assert!(virtual_boy.cpu.is_halted());
+            virtual_boy
+        };
+
+        let interpreted_state = run(ExecutionMode::Interpreter).save_state();
+        assert!(run(ExecutionMode::Dynarec).save_state() == interpreted_state);
+        let virtual_boy = run(ExecutionMode::DynarecLockstep);
+        assert!(virtual_boy.save_state() == interpreted_state);
+        virtual_boy
+    }
+
+    #[cfg(feature = "dynarec")]
+    #[test]
+    fn translated_alu() {
+        let virtual_boy = run_translated("
+                mov 10, r10
+                movhi 0x1234, r0, r11
+                movea 0x5678, r11, r11
+                mov 0, r12
+                mov -1, r13
+                mov 0, r20
+            loop:
+                add r11, r12
+                sub r10, r13
+                xor r12, r11
+                shl 3, r11
+                sar 1, r13
+                shr r10, r12
+                shl r10, r14
+                sar r10, r15
+                or r10, r14
+                and r11, r14
+                not r12, r15
+                cmp r15, r12
+                setf lt, r16
+                add r16, r17
+                setf c, r16
+                add r16, r17
+                xori 0xa5a5, r11, r11
+                andi 0xff0f, r12, r18
+                ori 0x8000, r18, r18
+                addi -300, r18, r19
+                mov r19, r9
+                shr 7, r9
+                cmp 5, r10
+                bge skip
+                add 1, r20
+            skip:
+                add -1, r10
+                bnz loop
+                halt");
+
+        assert_eq!(virtual_boy.cpu.reg_gpr(10), 0);
+        assert_eq!(virtual_boy.cpu.reg_gpr(20), 4);
+    }
+
+    #[cfg(feature = "dynarec")]
+    #[test]
+    fn translated_loads_and_stores() {
+        // Stores run across WRAM's code page boundaries (every 256 bytes)
+        let mut virtual_boy = run_translated("
+                movhi 0x500, r0, r6
+                movea 0xf0, r6, r6
+                mov 0, r7
+                movea 0x40, r0, r8
+            fill:
+                st.b r7, 0[r6]
+                add 1, r6
+                add 1, r7
+                cmp r8, r7
+                blt fill
+
+                movhi 0x500, r0, r6
+                movea 0x1fc, r6, r6
+                movhi 0x1122, r0, r11
+                movea 0x3344, r11, r11
+                movea 0x5566, r0, r12
+                st.w r11, 0[r6]
+                st.w r11, 4[r6]
+                st.h r12, 2[r6]
+                st.h r12, 4[r6]
+                ld.w 0[r6], r13
+                ld.w 4[r6], r14
+                ld.h 2[r6], r15
+                ld.b 3[r6], r16
+                ld.b 4[r6], r17
+
+                movhi 0x500, r0, r6
+                movea 0xfc, r6, r6
+                ld.w 0[r6], r18
+                ld.w 4[r6], r19
+                ld.h -2[r6], r20
+                ld.b 5[r6], r21
+                halt");
+
+        assert_eq!(virtual_boy.interconnect.read_byte(0x050000ff), 0x0f);
+        assert_eq!(virtual_boy.interconnect.read_byte(0x05000100), 0x10);
+        assert_eq!(virtual_boy.interconnect.read_word(0x050001fc), 0x55663344);
+        assert_eq!(virtual_boy.interconnect.read_word(0x05000200), 0x11225566);
+        assert_eq!(virtual_boy.cpu.reg_gpr(18), 0x0f0e0d0c);
+        assert_eq!(virtual_boy.cpu.reg_gpr(19), 0x13121110);
+        assert_eq!(virtual_boy.cpu.reg_gpr(20), 0x0b0a);
+        assert_eq!(virtual_boy.cpu.reg_gpr(21), 0x11);
+    }
+
+    #[cfg(feature = "dynarec")]
+    #[test]
+    fn translated_self_modifying_code() {
+        // The program runs from WRAM, patching a subroutine that straddles a code page boundary
+        //  between calls, and then the instruction right after a store
+        let virtual_boy = run_translated("
+                movhi 0x500, r0, r6
+                movea 0x10, r6, r6
+                jmp [r6]
+
+            .org 0x05000010
+                mov 0, r10
+                mov 0, r11
+                mov 4, r21
+                movhi 0x500, r0, r22
+                movea 0xf8, r22, r22
+                ; add 2, r10
+                movea 0x4542, r0, r23
+            loop:
+                jal routine
+                st.h r23, 0[r22]
+                add 1, r23
+                add -1, r21
+                bnz loop
+                jr patch_next
+
+            .org 0x05000080
+            patch_next:
+                movhi 0x500, r0, r24
+                movea 0x90, r24, r24
+                ; add 1, r11
+                movea 0x4561, r0, r25
+                st.h r25, 0[r24]
+                mov 0, r11
+                halt
+
+            .org 0x050000f8
+            routine:
+                add 1, r10
+                add r10, r11
+                movhi 0, r0, r12
+                add 1, r12
+                jmp [r31]");
+
+        assert_eq!(virtual_boy.cpu.reg_gpr(10), 1 + 2 + 3 + 4);
+        assert_eq!(virtual_boy.cpu.reg_gpr(11), 1 + 3 + 6 + 10 + 1);
+        assert_eq!(virtual_boy.cpu.reg_gpr(12), 1);
+    }
+
+    #[test]
+    fn address_trap() {
+        let (mut cpu, mut bus) = setup("mov 1, r10\nadd 1, r10\nhalt");
+        load(&mut bus, 0xffffffc0, "add 1, r20\nreti");
+        cpu.set_reg_gpr(10, 0);
+        cpu.set_reg_gpr(20, 0);
+        cpu.reg_adtre = CODE;
+        cpu.set_reg_psw(1 << 13);
+
+        // Taken before the instruction executes
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), 0xffffffc0);
+        assert_eq!(cpu.reg_eipc(), CODE);
+        assert_eq!(cpu.reg_gpr(10), 0);
+        assert_eq!(cpu.reg_psw() & (1 << 13), 0);
+
+        // Returning runs the trapped instruction once, with the trap still enabled
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE);
+        assert_eq!(cpu.reg_psw() & (1 << 13), 1 << 13);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), CODE + 2);
+        assert_eq!(cpu.reg_gpr(10), 1);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_gpr(10), 2);
+        assert_eq!(cpu.reg_gpr(20), 1);
+
+        // Coming back to ADTRE any other way traps again
+        cpu.reg_pc = CODE;
+        cpu.step(&mut bus);
+        assert_eq!(cpu.reg_pc(), 0xffffffc0);
+        assert_eq!(cpu.reg_gpr(10), 2);
+        assert_eq!(cpu.reg_gpr(20), 1);
+    }
+}
@@ -0,0 +1,292 @@
+// A tiny x86-64 assembler, covering just the instructions the dynarec needs
+
+// Listed in full, since the encoding depends on each register's number
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    Rsp = 4,
+    Rbp = 5,
+    Rsi = 6,
+    Rdi = 7,
+    R8 = 8,
+    R9 = 9,
+    R10 = 10,
+    R11 = 11,
+    R12 = 12,
+    R13 = 13,
+    R14 = 14,
+    R15 = 15,
+}
+
+impl Reg {
+    fn low_bits(self) -> u8 {
+        (self as u8) & 0x07
+    }
+
+    fn is_extended(self) -> bool {
+        (self as u8) >= 8
+    }
+}
+
+// A memory operand of the form [base + disp]
+#[derive(Clone, Copy)]
+pub struct Mem {
+    pub base: Reg,
+    pub disp: i32,
+}
+
+pub fn mem(base: Reg, disp: i32) -> Mem {
+    Mem {
+        base: base,
+        disp: disp,
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Cond {
+    Overflow = 0x0,
+    Carry = 0x2,
+    Zero = 0x4,
+    Above = 0x7,
+    Sign = 0x8,
+}
+
+#[derive(Clone, Copy)]
+pub enum AluOp {
+    Add = 0,
+    Or = 1,
+    And = 4,
+    Sub = 5,
+    Xor = 6,
+    Cmp = 7,
+}
+
+#[derive(Clone, Copy)]
+pub enum ShiftOp {
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+// Position of a rel32 field to be patched once the jump target is known
+pub struct Label(usize);
+
+pub struct Emitter {
+    pub code: Vec<u8>,
+}
+
+impl Emitter {
+    pub fn new() -> Emitter {
+        Emitter {
+            code: Vec::new(),
+        }
+    }
+
+    fn byte(&mut self, value: u8) {
+        self.code.push(value);
+    }
+
+    fn dword(&mut self, value: u32) {
+        self.code.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    fn qword(&mut self, value: u64) {
+        self.dword(value as u32);
+        self.dword((value >> 32) as u32);
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, base: Reg) {
+        let rex = 0x40 | ((w as u8) << 3) | ((reg >> 3) << 2) | (base.is_extended() as u8);
+        if rex != 0x40 {
+            self.byte(rex);
+        }
+    }
+
+    // Emits a ModRM (plus SIB/displacement, if needed) addressing memory
+    fn modrm_mem(&mut self, reg: u8, mem: Mem) {
+        let reg = (reg & 0x07) << 3;
+        let base = mem.base.low_bits();
+
+        // rbp/r13 can't be used without a displacement, so always use one for those
+        let (mode, disp_len) = if mem.disp == 0 && base != 5 {
+            (0x00, 0)
+        } else if mem.disp >= -128 && mem.disp < 128 {
+            (0x40, 1)
+        } else {
+            (0x80, 4)
+        };
+
+        self.byte(mode | reg | base);
+        // rsp/r12 require a SIB byte
+        if base == 4 {
+            self.byte(0x24);
+        }
+
+        match disp_len {
+            1 => self.byte(mem.disp as u8),
+            4 => self.dword(mem.disp as u32),
+            _ => {}
+        }
+    }
+
+    fn modrm_reg(&mut self, reg: u8, rm: Reg) {
+        self.byte(0xc0 | ((reg & 0x07) << 3) | rm.low_bits());
+    }
+
+    fn op_mem(&mut self, w: bool, opcode: &[u8], reg: u8, mem: Mem) {
+        self.rex(w, reg, mem.base);
+        for &byte in opcode {
+            self.byte(byte);
+        }
+        self.modrm_mem(reg, mem);
+    }
+
+    fn op_reg(&mut self, w: bool, opcode: &[u8], reg: u8, rm: Reg) {
+        self.rex(w, reg, rm);
+        for &byte in opcode {
+            self.byte(byte);
+        }
+        self.modrm_reg(reg, rm);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, reg);
+        self.byte(0x50 + reg.low_bits());
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, reg);
+        self.byte(0x58 + reg.low_bits());
+    }
+
+    pub fn ret(&mut self) {
+        self.byte(0xc3);
+    }
+
+    pub fn mov_r64_r64(&mut self, dst: Reg, src: Reg) {
+        self.op_reg(true, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov_r64_imm64(&mut self, dst: Reg, value: u64) {
+        self.rex(true, 0, dst);
+        self.byte(0xb8 + dst.low_bits());
+        self.qword(value);
+    }
+
+    pub fn mov_r64_mem(&mut self, dst: Reg, src: Mem) {
+        self.op_mem(true, &[0x8b], dst as u8, src);
+    }
+
+    pub fn mov_r32_imm32(&mut self, dst: Reg, value: u32) {
+        self.rex(false, 0, dst);
+        self.byte(0xb8 + dst.low_bits());
+        self.dword(value);
+    }
+
+    pub fn mov_r32_mem(&mut self, dst: Reg, src: Mem) {
+        self.op_mem(false, &[0x8b], dst as u8, src);
+    }
+
+    pub fn mov_mem_r32(&mut self, dst: Mem, src: Reg) {
+        self.op_mem(false, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov_mem_imm32(&mut self, dst: Mem, value: u32) {
+        self.op_mem(false, &[0xc7], 0, dst);
+        self.dword(value);
+    }
+
+    pub fn mov_mem_imm8(&mut self, dst: Mem, value: u8) {
+        self.op_mem(false, &[0xc6], 0, dst);
+        self.byte(value);
+    }
+
+    // The 8-bit ops only ever use al, so they never need a REX prefix for the register
+    pub fn mov_al_mem(&mut self, src: Mem) {
+        self.op_mem(false, &[0x8a], 0, src);
+    }
+
+    pub fn or_al_mem(&mut self, src: Mem) {
+        self.op_mem(false, &[0x0a], 0, src);
+    }
+
+    pub fn xor_al_mem(&mut self, src: Mem) {
+        self.op_mem(false, &[0x32], 0, src);
+    }
+
+    pub fn xor_al_imm8(&mut self, value: u8) {
+        self.byte(0x34);
+        self.byte(value);
+    }
+
+    pub fn test_al_al(&mut self) {
+        self.byte(0x84);
+        self.byte(0xc0);
+    }
+
+    pub fn movzx_eax_al(&mut self) {
+        self.byte(0x0f);
+        self.byte(0xb6);
+        self.byte(0xc0);
+    }
+
+    pub fn alu_r32_r32(&mut self, op: AluOp, dst: Reg, src: Reg) {
+        self.op_reg(false, &[((op as u8) << 3) | 0x01], src as u8, dst);
+    }
+
+    pub fn alu_r32_imm32(&mut self, op: AluOp, dst: Reg, value: u32) {
+        self.op_reg(false, &[0x81], op as u8, dst);
+        self.dword(value);
+    }
+
+    pub fn cmp_mem_r32(&mut self, lhs: Mem, rhs: Reg) {
+        self.op_mem(false, &[0x39], rhs as u8, lhs);
+    }
+
+    pub fn not_r32(&mut self, reg: Reg) {
+        self.op_reg(false, &[0xf7], 2, reg);
+    }
+
+    pub fn test_r32_r32(&mut self, lhs: Reg, rhs: Reg) {
+        self.op_reg(false, &[0x85], rhs as u8, lhs);
+    }
+
+    pub fn shift_r32_cl(&mut self, op: ShiftOp, reg: Reg) {
+        self.op_reg(false, &[0xd3], op as u8, reg);
+    }
+
+    pub fn shift_r32_imm8(&mut self, op: ShiftOp, reg: Reg, amount: u8) {
+        self.op_reg(false, &[0xc1], op as u8, reg);
+        self.byte(amount);
+    }
+
+    pub fn setcc_mem(&mut self, cond: Cond, dst: Mem) {
+        self.op_mem(false, &[0x0f, 0x90 | (cond as u8)], 0, dst);
+    }
+
+    pub fn call_r64(&mut self, reg: Reg) {
+        self.op_reg(false, &[0xff], 2, reg);
+    }
+
+    pub fn jcc_forward(&mut self, cond: Cond) -> Label {
+        self.byte(0x0f);
+        self.byte(0x80 | (cond as u8));
+        self.forward_rel32()
+    }
+
+    fn forward_rel32(&mut self) -> Label {
+        let ret = Label(self.code.len());
+        self.dword(0);
+        ret
+    }
+
+    // Points a previously emitted forward jump at the current position
+    pub fn bind(&mut self, label: Label) {
+        let rel = (self.code.len() - (label.0 + 4)) as u32;
+        self.code[label.0..label.0 + 4].copy_from_slice(&[rel as u8, (rel >> 8) as u8, (rel >> 16) as u8, (rel >> 24) as u8]);
+    }
+}
@@ -0,0 +1,61 @@
+use libc;
+
+use std::ptr;
+
+// A fixed-size region of executable memory that translated blocks are appended to
+pub struct ExecBuffer {
+    ptr: *mut u8,
+    size: usize,
+    len: usize,
+}
+
+impl ExecBuffer {
+    pub fn new(size: usize) -> ExecBuffer {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0)
+        };
+        if ptr == libc::MAP_FAILED {
+            panic!("Couldn't allocate executable memory for the dynarec");
+        }
+
+        ExecBuffer {
+            ptr: ptr as *mut u8,
+            size: size,
+            len: 0,
+        }
+    }
+
+    // Copies code into the buffer, returning a pointer to it, or None if the buffer is full
+    pub fn push(&mut self, code: &[u8]) -> Option<*const u8> {
+        // Keep blocks 16-byte aligned
+        let start = (self.len + 15) & !15;
+        if start + code.len() > self.size {
+            return None;
+        }
+
+        unsafe {
+            let dest = self.ptr.add(start);
+            ptr::copy_nonoverlapping(code.as_ptr(), dest, code.len());
+            self.len = start + code.len();
+            Some(dest)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Drop for ExecBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut _, self.size);
+        }
+    }
+}
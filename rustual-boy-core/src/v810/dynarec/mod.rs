@@ -0,0 +1,735 @@
+// A block-based dynamic recompiler that translates straight-line V810 code into x86-64.
+//
+// Only the simple, common instructions (moves, ALU ops, shifts, loads/stores, branches and jumps)
+//  are translated; a block ends before anything else, and the interpreter takes over for that
+//  instruction. Blocks also end after any branch, jump or store, at the end of a code page (so a
+//  WRAM write invalidates every block containing that code), and as soon as the device event
+//  budget they're given runs out. Together, these guarantee a block can never observe anything
+//  the interpreter wouldn't: no device event or interrupt can happen in the middle of one, and
+//  memory accesses go through the interconnect just like they do in the interpreter. As a result,
+//  translated code behaves exactly the same as the interpreter, cycle for cycle.
+//
+// Blocks are chained one after another without going back to the caller until the next device
+//  event, except after a store, which may have changed when that event is due.
+
+mod emitter;
+mod exec_buffer;
+
+use self::emitter::*;
+use self::exec_buffer::*;
+
+use super::*;
+use interconnect::*;
+
+const EXEC_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+// Must be a power of two
+const NUM_BLOCKS: usize = 4096;
+
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+// Shared between translated code and the host; translated code gets a pointer to it in rdi
+#[repr(C)]
+struct Context {
+    gpr: *mut u32,
+    psw_zero: u8,
+    psw_sign: u8,
+    psw_overflow: u8,
+    psw_carry: u8,
+    // Cycles the block may run for before it has to exit
+    budget: u32,
+    // Written by translated code on exit
+    next_pc: u32,
+    cycles: u32,
+    instructions: u32,
+    // Cycles the interconnect has already been advanced by (for memory accesses)
+    synced_cycles: u32,
+    // Cycles spent on the bus by memory accesses, which depend on the address, so they're
+    //  added up as the block runs instead of being counted during translation
+    bus_cycles: u32,
+    interconnect: *mut Interconnect,
+    // The first unmapped access made by a load or store, and the PC of the instruction that made it
+    unmapped_access: Option<(u32, u32)>,
+}
+
+// Field offsets in Context, as seen by translated code
+const CONTEXT_GPR: i32 = 0;
+const CONTEXT_PSW_ZERO: i32 = 8;
+const CONTEXT_PSW_SIGN: i32 = 9;
+const CONTEXT_PSW_OVERFLOW: i32 = 10;
+const CONTEXT_PSW_CARRY: i32 = 11;
+const CONTEXT_BUDGET: i32 = 12;
+const CONTEXT_NEXT_PC: i32 = 16;
+const CONTEXT_CYCLES: i32 = 20;
+const CONTEXT_INSTRUCTIONS: i32 = 24;
+const CONTEXT_BUS_CYCLES: i32 = 32;
+
+impl Context {
+    // Brings the interconnect up to the given number of cycles into the block. This never crosses a
+    //  device event (see the budget), so there's no need to run any devices here.
+    unsafe fn sync<'a>(&mut self, cycles: u32) -> &'a mut Interconnect {
+        let interconnect = &mut *self.interconnect;
+        let cycles = cycles + self.bus_cycles;
+        interconnect.advance_cycles(cycles - self.synced_cycles);
+        self.synced_cycles = cycles;
+        interconnect
+    }
+
+    fn check_unmapped_access(&mut self, interconnect: &mut Interconnect, pc: u32) {
+        if let Some(addr) = interconnect.take_unmapped_access() {
+            if self.unmapped_access.is_none() {
+                self.unmapped_access = Some((addr, pc));
+            }
+        }
+    }
+}
+
+type BlockFn = extern "sysv64" fn(*mut Context);
+
+struct Block {
+    pc: u32,
+    generation: u64,
+    // None if the first instruction can't be translated
+    code: Option<BlockFn>,
+    can_chain: bool,
+    // Address of each translated instruction and whether it's 32 bits long, so that fetches can be
+    //  replayed through the CPU's instruction cache after the block runs
+    fetches: Vec<(u32, bool)>,
+    // Cycles fetching each halfword was assumed to take when the block was translated (see halfword_fetch_cycles)
+    halfword_fetch_cycles: u32,
+}
+
+pub struct Dynarec {
+    exec_buffer: ExecBuffer,
+    // Direct-mapped by start address; a block that gets evicted is simply translated again later
+    blocks: Box<[Option<Block>]>,
+    unmapped_access: Option<(u32, u32)>,
+}
+
+impl Dynarec {
+    pub fn new() -> Dynarec {
+        Dynarec {
+            exec_buffer: ExecBuffer::new(EXEC_BUFFER_SIZE),
+            blocks: Self::empty_blocks(),
+            unmapped_access: None,
+        }
+    }
+
+    fn empty_blocks() -> Box<[Option<Block>]> {
+        (0..NUM_BLOCKS).map(|_| None).collect::<Vec<_>>().into_boxed_slice()
+    }
+
+    // Runs translated blocks starting at the current PC, one after another, until the next device
+    //  event is due, max_cycles have elapsed, or the next instruction has to be run by the
+    //  interpreter. Returns the number of cycles and instructions executed, or None if nothing could
+    //  be run. The interconnect is advanced by the executed cycles, but devices aren't run; that's up
+    //  to the caller.
+    pub fn run_blocks(&mut self, cpu: &mut V810, interconnect: &mut Interconnect, max_cycles: u32) -> Option<(u32, u32)> {
+        let mut cycles = 0;
+        let mut instructions = 0;
+
+        loop {
+            let budget = interconnect.cycles_until_next_event().min(max_cycles - cycles);
+
+            let (block_cycles, block_instructions, can_chain) = match self.run_block(cpu, interconnect, budget) {
+                Some(ret) => ret,
+                _ => break,
+            };
+            cycles += block_cycles;
+            instructions += block_instructions;
+
+            // The last instruction may have run past the budget, in which case the event is due now
+            if !can_chain || block_cycles >= budget || self.unmapped_access.is_some() {
+                break;
+            }
+        }
+
+        if instructions > 0 {
+            Some((cycles, instructions))
+        } else {
+            None
+        }
+    }
+
+    // Runs the block at the current PC for at least one instruction, stopping as soon as budget
+    //  cycles have elapsed
+    fn run_block(&mut self, cpu: &mut V810, interconnect: &mut Interconnect, budget: u32) -> Option<(u32, u32, bool)> {
+        // Translated code doesn't check for address traps
+        if cpu.psw_address_trap_enable {
+            return None;
+        }
+
+        let pc = cpu.reg_pc;
+        let generation = interconnect.code_generation(pc)?;
+        let halfword_fetch_cycles = halfword_fetch_cycles(cpu, interconnect, pc);
+
+        let index = ((pc >> 1) as usize) & (NUM_BLOCKS - 1);
+        let is_stale = match self.blocks[index] {
+            Some(ref block) => block.pc != pc || block.generation != generation || block.halfword_fetch_cycles != halfword_fetch_cycles,
+            _ => true,
+        };
+        if is_stale {
+            let block = self.compile(interconnect, pc, generation, halfword_fetch_cycles);
+            self.blocks[index] = Some(block);
+        }
+
+        let block = self.blocks[index].as_ref().unwrap();
+        let code = block.code?;
+
+        // Translated code assumes every fetch hits when the cache is enabled; until then (or when
+        //  blocks evict each other), the interpreter has to run them, as misses cost extra cycles
+        if cpu.cache.is_enabled() {
+            let all_hit = block.fetches.iter().all(|&(addr, is_long)| {
+                cpu.cache.is_hit(addr) && (!is_long || cpu.cache.is_hit(addr.wrapping_add(2)))
+            });
+            if !all_hit {
+                return None;
+            }
+        }
+
+        let mut context = Context {
+            gpr: cpu.reg_gpr_ptr,
+            psw_zero: cpu.psw_zero as u8,
+            psw_sign: cpu.psw_sign as u8,
+            psw_overflow: cpu.psw_overflow as u8,
+            psw_carry: cpu.psw_carry as u8,
+            budget: budget,
+            next_pc: 0,
+            cycles: 0,
+            instructions: 0,
+            synced_cycles: 0,
+            bus_cycles: 0,
+            interconnect: interconnect as *mut _,
+            unmapped_access: None,
+        };
+        code(&mut context);
+
+        if self.unmapped_access.is_none() {
+            self.unmapped_access = context.unmapped_access;
+        }
+
+        cpu.reg_pc = context.next_pc;
+        cpu.psw_zero = context.psw_zero != 0;
+        cpu.psw_sign = context.psw_sign != 0;
+        cpu.psw_overflow = context.psw_overflow != 0;
+        cpu.psw_carry = context.psw_carry != 0;
+
+        for &(addr, is_long) in &block.fetches[..context.instructions as usize] {
+            cpu.cache.access(interconnect, addr);
+            if is_long {
+                cpu.cache.access(interconnect, addr.wrapping_add(2));
+            }
+        }
+
+        let cycles = context.cycles + context.bus_cycles;
+        interconnect.advance_cycles(cycles - context.synced_cycles);
+
+        Some((cycles, context.instructions, block.can_chain))
+    }
+
+    // The first unmapped address accessed by translated code since the last call, along with the PC
+    //  of the instruction that accessed it. The block it happened in still runs to completion.
+    pub fn take_unmapped_access(&mut self) -> Option<(u32, u32)> {
+        self.unmapped_access.take()
+    }
+
+    fn compile(&mut self, interconnect: &mut Interconnect, start_pc: u32, generation: u64, halfword_fetch_cycles: u32) -> Block {
+        let mut compiler = Compiler {
+            emitter: Emitter::new(),
+            can_chain: true,
+            cycles: 0,
+            instructions: 0,
+            fetch_cycles: 0,
+        };
+        compiler.prologue();
+
+        let mut fetches = Vec::new();
+        let mut pc = start_pc;
+        loop {
+            if fetches.len() == MAX_BLOCK_INSTRUCTIONS || !is_same_code_page(start_pc, pc) {
+                compiler.exit(pc);
+                break;
+            }
+
+            let first_halfword = interconnect.fetch_halfword(pc);
+            let is_long = is_long_instruction(first_halfword);
+            if is_long && !is_same_code_page(start_pc, pc.wrapping_add(2)) {
+                compiler.exit(pc);
+                break;
+            }
+            let second_halfword = if is_long { interconnect.fetch_halfword(pc.wrapping_add(2)) } else { 0 };
+            compiler.fetch_cycles = if is_long { 2 * halfword_fetch_cycles } else { halfword_fetch_cycles };
+
+            match compiler.instruction(pc, Instruction::decode(first_halfword, second_halfword)) {
+                Translation::Unsupported => {
+                    compiler.exit(pc);
+                    break;
+                }
+                Translation::Continue(next_pc) => {
+                    fetches.push((pc, is_long));
+                    compiler.check_budget(next_pc);
+                    pc = next_pc;
+                }
+                Translation::End => {
+                    fetches.push((pc, is_long));
+                    break;
+                }
+            }
+        }
+
+        let code = if fetches.is_empty() {
+            None
+        } else {
+            let code = &compiler.emitter.code;
+            let ptr = match self.exec_buffer.push(code) {
+                Some(ptr) => ptr,
+                None => {
+                    // Out of space; start over
+                    self.blocks = Self::empty_blocks();
+                    self.exec_buffer.clear();
+                    self.exec_buffer.push(code).expect("Translated block is larger than the dynarec's code buffer")
+                }
+            };
+            Some(unsafe { ::std::mem::transmute::<*const u8, BlockFn>(ptr) })
+        };
+
+        Block {
+            pc: start_pc,
+            generation: generation,
+            code: code,
+            can_chain: compiler.can_chain,
+            fetches: fetches,
+            halfword_fetch_cycles: halfword_fetch_cycles,
+        }
+    }
+}
+
+enum Translation {
+    // The instruction can't be translated, so the block ends before it
+    Unsupported,
+    // The instruction was translated and the block can continue with the instruction at the given address
+    Continue(u32),
+    // The instruction was translated and ends the block
+    End,
+}
+
+// Register usage in translated code:
+//  rbx: context
+//  r12: V810 general purpose registers
+//  rax, rcx, rdx, rsi, rdi, r8, r9: scratch. Helpers take the context in rdi and their arguments in
+//  rsi, rdx, rcx, r8 and r9 (stores pass the PC in r9), and calls to them clobber all of these.
+struct Compiler {
+    emitter: Emitter,
+    // Whether another block may run straight after this one without returning to the caller
+    can_chain: bool,
+    // Cycles/instructions up to (but not including) the instruction being translated
+    cycles: u32,
+    instructions: u32,
+    // Cycles spent fetching the instruction being translated, which count as part of its own
+    fetch_cycles: u32,
+}
+
+fn context(offset: i32) -> Mem {
+    mem(Reg::Rbx, offset)
+}
+
+fn gpr(index: usize) -> Mem {
+    mem(Reg::R12, (index * 4) as i32)
+}
+
+impl Compiler {
+    fn prologue(&mut self) {
+        self.emitter.push(Reg::Rbx);
+        self.emitter.push(Reg::R12);
+        // Keeps the stack 16-byte aligned for helper calls
+        self.emitter.push(Reg::Rcx);
+        self.emitter.mov_r64_r64(Reg::Rbx, Reg::Rdi);
+        self.emitter.mov_r64_mem(Reg::R12, context(CONTEXT_GPR));
+    }
+
+    fn epilogue(&mut self) {
+        self.emitter.pop(Reg::Rcx);
+        self.emitter.pop(Reg::R12);
+        self.emitter.pop(Reg::Rbx);
+        self.emitter.ret();
+    }
+
+    // Exits the block before the instruction being translated
+    fn exit(&mut self, next_pc: u32) {
+        let (cycles, instructions) = (self.cycles, self.instructions);
+        self.exit_with(Some(next_pc), cycles, instructions);
+    }
+
+    // Exits the block after the instruction being translated, which took the given number of cycles.
+    //  If next_pc is None, it's expected to have been written to the context already.
+    fn exit_after(&mut self, next_pc: Option<u32>, num_cycles: u32) {
+        let (cycles, instructions) = (self.cycles + self.fetch_cycles + num_cycles, self.instructions + 1);
+        self.exit_with(next_pc, cycles, instructions);
+    }
+
+    fn exit_with(&mut self, next_pc: Option<u32>, cycles: u32, instructions: u32) {
+        if let Some(next_pc) = next_pc {
+            self.emitter.mov_mem_imm32(context(CONTEXT_NEXT_PC), next_pc);
+        }
+        self.emitter.mov_mem_imm32(context(CONTEXT_CYCLES), cycles);
+        self.emitter.mov_mem_imm32(context(CONTEXT_INSTRUCTIONS), instructions);
+        self.epilogue();
+    }
+
+    // Called after an instruction that doesn't end the block; exits if the budget has run out
+    fn check_budget(&mut self, next_pc: u32) {
+        self.emitter.mov_r32_mem(Reg::Rax, context(CONTEXT_BUS_CYCLES));
+        self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rax, self.cycles);
+        self.emitter.cmp_mem_r32(context(CONTEXT_BUDGET), Reg::Rax);
+        let has_budget = self.emitter.jcc_forward(Cond::Above);
+        self.exit(next_pc);
+        self.emitter.bind(has_budget);
+    }
+
+    fn load_gpr(&mut self, dst: Reg, index: usize) {
+        self.emitter.mov_r32_mem(dst, gpr(index));
+    }
+
+    fn store_gpr(&mut self, index: usize, src: Reg) {
+        if index != 0 {
+            self.emitter.mov_mem_r32(gpr(index), src);
+        }
+    }
+
+    fn set_zero_sign_flags(&mut self) {
+        self.emitter.setcc_mem(Cond::Zero, context(CONTEXT_PSW_ZERO));
+        self.emitter.setcc_mem(Cond::Sign, context(CONTEXT_PSW_SIGN));
+    }
+
+    // Sets all four flags from an x86 add/sub, which compute them exactly like the V810 does
+    fn set_arithmetic_flags(&mut self) {
+        self.set_zero_sign_flags();
+        self.emitter.setcc_mem(Cond::Overflow, context(CONTEXT_PSW_OVERFLOW));
+        self.emitter.setcc_mem(Cond::Carry, context(CONTEXT_PSW_CARRY));
+    }
+
+    // Sets zero/sign from eax, and clears overflow (carry is unaffected)
+    fn set_logic_flags(&mut self) {
+        self.emitter.test_r32_r32(Reg::Rax, Reg::Rax);
+        self.set_zero_sign_flags();
+        self.emitter.mov_mem_imm8(context(CONTEXT_PSW_OVERFLOW), 0);
+    }
+
+    // Evaluates a 4-bit condition (as used by bcond and SETF) into al (0 or 1)
+    fn condition(&mut self, condition: Condition) {
+        let cond_bits = condition as u32;
+        match cond_bits & 0x07 {
+            0 => self.emitter.mov_al_mem(context(CONTEXT_PSW_OVERFLOW)),
+            1 => self.emitter.mov_al_mem(context(CONTEXT_PSW_CARRY)),
+            2 => self.emitter.mov_al_mem(context(CONTEXT_PSW_ZERO)),
+            3 => {
+                self.emitter.mov_al_mem(context(CONTEXT_PSW_CARRY));
+                self.emitter.or_al_mem(context(CONTEXT_PSW_ZERO));
+            }
+            4 => self.emitter.mov_al_mem(context(CONTEXT_PSW_SIGN)),
+            5 => self.emitter.mov_r32_imm32(Reg::Rax, 1),
+            6 => {
+                self.emitter.mov_al_mem(context(CONTEXT_PSW_SIGN));
+                self.emitter.xor_al_mem(context(CONTEXT_PSW_OVERFLOW));
+            }
+            _ => {
+                self.emitter.mov_al_mem(context(CONTEXT_PSW_SIGN));
+                self.emitter.xor_al_mem(context(CONTEXT_PSW_OVERFLOW));
+                self.emitter.or_al_mem(context(CONTEXT_PSW_ZERO));
+            }
+        }
+
+        // The upper 8 conditions are the negations of the lower 8
+        if cond_bits & 0x08 != 0 {
+            self.emitter.xor_al_imm8(1);
+        }
+    }
+
+    // eax = eax <op> ecx (or imm), setting flags
+    fn shift(&mut self, op: ShiftOp, amount: Option<u32>) {
+        self.emitter.mov_mem_imm8(context(CONTEXT_PSW_CARRY), 0);
+        match amount {
+            Some(amount) => {
+                // Shifting by 0 leaves carry clear
+                if amount != 0 {
+                    self.emitter.shift_r32_imm8(op, Reg::Rax, amount as u8);
+                    self.emitter.setcc_mem(Cond::Carry, context(CONTEXT_PSW_CARRY));
+                }
+            }
+            _ => {
+                // x86 shifts by 0 don't touch the flags, so carry has to be left clear in that case
+                self.emitter.alu_r32_imm32(AluOp::And, Reg::Rcx, 0x1f);
+                let no_shift = self.emitter.jcc_forward(Cond::Zero);
+                self.emitter.shift_r32_cl(op, Reg::Rax);
+                self.emitter.setcc_mem(Cond::Carry, context(CONTEXT_PSW_CARRY));
+                self.emitter.bind(no_shift);
+            }
+        }
+        self.set_logic_flags();
+    }
+
+    fn call_helper(&mut self, helper: u64) {
+        self.emitter.mov_r64_r64(Reg::Rdi, Reg::Rbx);
+        self.emitter.mov_r64_imm64(Reg::Rax, helper);
+        self.emitter.call_r64(Reg::Rax);
+    }
+
+    fn instruction(&mut self, pc: u32, instruction: Instruction) -> Translation {
+        let next_pc = pc.wrapping_add(if instruction.is_long() { 4 } else { 2 });
+
+        let mut num_cycles = 1;
+
+        match instruction {
+            // nop
+            Instruction::Bcond { condition: Condition::F, .. } => (),
+            Instruction::Bcond { condition: Condition::T, disp } => {
+                self.exit_after(Some(pc.wrapping_add((disp as u32) & 0xfffffffe)), 3);
+                return Translation::End;
+            }
+            Instruction::Bcond { condition, disp } => {
+                self.condition(condition);
+                self.emitter.test_al_al();
+                let not_taken = self.emitter.jcc_forward(Cond::Zero);
+                self.exit_after(Some(pc.wrapping_add((disp as u32) & 0xfffffffe)), 3);
+                self.emitter.bind(not_taken);
+                self.exit_after(Some(next_pc), 1);
+                return Translation::End;
+            }
+            Instruction::MovReg { reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::AddReg { reg1, reg2 } |
+            Instruction::Sub { reg1, reg2 } |
+            Instruction::CmpReg { reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg2);
+                self.load_gpr(Reg::Rcx, reg1);
+                let op = match instruction {
+                    Instruction::AddReg { .. } => AluOp::Add,
+                    Instruction::Sub { .. } => AluOp::Sub,
+                    _ => AluOp::Cmp,
+                };
+                self.emitter.alu_r32_r32(op, Reg::Rax, Reg::Rcx);
+                self.set_arithmetic_flags();
+                match instruction {
+                    Instruction::CmpReg { .. } => (),
+                    _ => self.store_gpr(reg2, Reg::Rax),
+                }
+            }
+            Instruction::ShlReg { reg1, reg2 } |
+            Instruction::ShrReg { reg1, reg2 } |
+            Instruction::SarReg { reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg2);
+                self.load_gpr(Reg::Rcx, reg1);
+                let op = match instruction {
+                    Instruction::ShlReg { .. } => ShiftOp::Shl,
+                    Instruction::ShrReg { .. } => ShiftOp::Shr,
+                    _ => ShiftOp::Sar,
+                };
+                self.shift(op, None);
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Jmp { reg1 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.emitter.alu_r32_imm32(AluOp::And, Reg::Rax, 0xfffffffe);
+                self.emitter.mov_mem_r32(context(CONTEXT_NEXT_PC), Reg::Rax);
+                self.exit_after(None, 3);
+                return Translation::End;
+            }
+            Instruction::Or { reg1, reg2 } |
+            Instruction::And { reg1, reg2 } |
+            Instruction::Xor { reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg2);
+                self.load_gpr(Reg::Rcx, reg1);
+                let op = match instruction {
+                    Instruction::Or { .. } => AluOp::Or,
+                    Instruction::And { .. } => AluOp::And,
+                    _ => AluOp::Xor,
+                };
+                self.emitter.alu_r32_r32(op, Reg::Rax, Reg::Rcx);
+                self.set_logic_flags();
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Not { reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.emitter.not_r32(Reg::Rax);
+                self.set_logic_flags();
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::MovImm { imm5, reg2 } => {
+                self.emitter.mov_r32_imm32(Reg::Rax, imm5 as u32);
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::AddImm5 { imm5, reg2 } |
+            Instruction::CmpImm { imm5, reg2 } => {
+                self.load_gpr(Reg::Rax, reg2);
+                let op = match instruction {
+                    Instruction::AddImm5 { .. } => AluOp::Add,
+                    _ => AluOp::Cmp,
+                };
+                self.emitter.alu_r32_imm32(op, Reg::Rax, imm5 as u32);
+                self.set_arithmetic_flags();
+                if let Instruction::AddImm5 { .. } = instruction {
+                    self.store_gpr(reg2, Reg::Rax);
+                }
+            }
+            Instruction::Setf { condition, reg2 } => {
+                self.condition(condition);
+                self.emitter.movzx_eax_al();
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::ShlImm { imm5, reg2 } |
+            Instruction::ShrImm { imm5, reg2 } |
+            Instruction::SarImm { imm5, reg2 } => {
+                self.load_gpr(Reg::Rax, reg2);
+                let op = match instruction {
+                    Instruction::ShlImm { .. } => ShiftOp::Shl,
+                    Instruction::ShrImm { .. } => ShiftOp::Shr,
+                    _ => ShiftOp::Sar,
+                };
+                self.shift(op, Some(imm5));
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Movea { imm16, reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rax, (imm16 as i16) as u32);
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::AddImm16 { imm16, reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rax, (imm16 as i16) as u32);
+                self.set_arithmetic_flags();
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Jr { disp } |
+            Instruction::Jal { disp } => {
+                if let Instruction::Jal { .. } = instruction {
+                    self.emitter.mov_mem_imm32(gpr(31), next_pc);
+                }
+                self.exit_after(Some(pc.wrapping_add((disp as u32) & 0xfffffffe)), 3);
+                return Translation::End;
+            }
+            Instruction::OrI { imm16, reg1, reg2 } |
+            Instruction::AndI { imm16, reg1, reg2 } |
+            Instruction::XorI { imm16, reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                let op = match instruction {
+                    Instruction::OrI { .. } => AluOp::Or,
+                    Instruction::AndI { .. } => AluOp::And,
+                    _ => AluOp::Xor,
+                };
+                self.emitter.alu_r32_imm32(op, Reg::Rax, imm16 as u32);
+                self.set_logic_flags();
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Movhi { imm16, reg1, reg2 } => {
+                self.load_gpr(Reg::Rax, reg1);
+                self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rax, (imm16 as u32) << 16);
+                self.store_gpr(reg2, Reg::Rax);
+            }
+            Instruction::Ldb { disp16, reg1, reg2 } |
+            Instruction::Ldh { disp16, reg1, reg2 } |
+            Instruction::Ldw { disp16, reg1, reg2 } |
+            Instruction::Inb { disp16, reg1, reg2 } |
+            Instruction::Inh { disp16, reg1, reg2 } |
+            Instruction::Inw { disp16, reg1, reg2 } => {
+                let opcode_bits = match instruction {
+                    Instruction::Ldb { .. } => OPCODE_BITS_LDB,
+                    Instruction::Ldh { .. } => OPCODE_BITS_LDH,
+                    Instruction::Ldw { .. } => OPCODE_BITS_LDW,
+                    Instruction::Inb { .. } => OPCODE_BITS_INB,
+                    Instruction::Inh { .. } => OPCODE_BITS_INH,
+                    _ => OPCODE_BITS_INW,
+                };
+                self.load_gpr(Reg::Rdx, reg1);
+                self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rdx, disp16 as u32);
+                self.emitter.mov_r32_imm32(Reg::Rsi, opcode_bits as u32);
+                self.emitter.mov_r32_imm32(Reg::Rcx, self.cycles);
+                self.emitter.mov_r32_imm32(Reg::R8, pc);
+                self.call_helper(load as *const () as u64);
+                self.store_gpr(reg2, Reg::Rax);
+                num_cycles = 2;
+            }
+            Instruction::Stb { disp16, reg1, reg2 } |
+            Instruction::Sth { disp16, reg1, reg2 } |
+            Instruction::Stw { disp16, reg1, reg2 } |
+            Instruction::Outb { disp16, reg1, reg2 } |
+            Instruction::Outh { disp16, reg1, reg2 } |
+            Instruction::Outw { disp16, reg1, reg2 } => {
+                let opcode_bits = match instruction {
+                    Instruction::Stb { .. } => OPCODE_BITS_STB,
+                    Instruction::Sth { .. } => OPCODE_BITS_STH,
+                    Instruction::Stw { .. } => OPCODE_BITS_STW,
+                    Instruction::Outb { .. } => OPCODE_BITS_OUTB,
+                    Instruction::Outh { .. } => OPCODE_BITS_OUTH,
+                    _ => OPCODE_BITS_OUTW,
+                };
+                self.load_gpr(Reg::Rdx, reg1);
+                self.emitter.alu_r32_imm32(AluOp::Add, Reg::Rdx, disp16 as u32);
+                self.load_gpr(Reg::Rcx, reg2);
+                self.emitter.mov_r32_imm32(Reg::Rsi, opcode_bits as u32);
+                self.emitter.mov_r32_imm32(Reg::R8, self.cycles);
+                self.emitter.mov_r32_imm32(Reg::R9, pc);
+                self.call_helper(store as *const () as u64);
+                // Stores can reschedule devices, raise interrupts or overwrite code, so the block has to end
+                //  here, and control has to go back to the caller before any other block runs
+                self.exit_after(Some(next_pc), 2);
+                self.can_chain = false;
+                return Translation::End;
+            }
+            _ => return Translation::Unsupported,
+        }
+
+        self.cycles += self.fetch_cycles + num_cycles;
+        self.instructions += 1;
+        Translation::Continue(next_pc)
+    }
+}
+
+// Cycles fetching each halfword of a block at pc takes. A block never leaves its code page, so this
+//  is the same for all of its instructions. With the cache enabled, blocks only run when every fetch
+//  hits, so fetching is free.
+fn halfword_fetch_cycles(cpu: &V810, interconnect: &Interconnect, pc: u32) -> u32 {
+    let result = if cpu.cache.is_enabled() { CacheResult::Hit } else { CacheResult::Disabled };
+    fetch_cycles(interconnect, pc, result)
+}
+
+// These mirror the corresponding cases in V810::step
+
+extern "sysv64" fn load(context: *mut Context, opcode_bits: u32, addr: u32, cycles: u32, pc: u32) -> u32 {
+    let context = unsafe { &mut *context };
+    let interconnect = unsafe { context.sync(cycles) };
+    let (value, size) = match opcode_bits as u16 {
+        OPCODE_BITS_LDB => ((interconnect.read_byte(addr) as i8) as u32, AccessSize::Byte),
+        OPCODE_BITS_LDH => ((interconnect.read_halfword(addr & 0xfffffffe) as i16) as u32, AccessSize::Halfword),
+        OPCODE_BITS_LDW | OPCODE_BITS_INW => (interconnect.read_word(addr & 0xfffffffc), AccessSize::Word),
+        OPCODE_BITS_INB => (interconnect.read_byte(addr) as u32, AccessSize::Byte),
+        OPCODE_BITS_INH => (interconnect.read_halfword(addr & 0xfffffffe) as u32, AccessSize::Halfword),
+        _ => unreachable!()
+    };
+    context.bus_cycles += interconnect.access_cycles(addr, size);
+    context.check_unmapped_access(interconnect, pc);
+    value
+}
+
+extern "sysv64" fn store(context: *mut Context, opcode_bits: u32, addr: u32, value: u32, cycles: u32, pc: u32) {
+    let context = unsafe { &mut *context };
+    let interconnect = unsafe { context.sync(cycles) };
+    let size = match opcode_bits as u16 {
+        OPCODE_BITS_STB | OPCODE_BITS_OUTB => {
+            interconnect.write_byte(addr, value as u8);
+            AccessSize::Byte
+        }
+        OPCODE_BITS_STH | OPCODE_BITS_OUTH => {
+            interconnect.write_halfword(addr & 0xfffffffe, value as u16);
+            AccessSize::Halfword
+        }
+        OPCODE_BITS_STW | OPCODE_BITS_OUTW => {
+            interconnect.write_word(addr & 0xfffffffc, value);
+            AccessSize::Word
+        }
+        _ => unreachable!()
+    };
+    context.bus_cycles += interconnect.access_cycles(addr, size);
+    context.check_unmapped_access(interconnect, pc);
+}
@@ -1,9 +1,13 @@
 mod mem_map;
 
 use sinks::*;
+use save_state::*;
+use scheduler::*;
 
 use self::mem_map::*;
 
+use std::mem;
+
 const FRAMEBUFFER_RESOLUTION_X: u32 = 384;
 const FRAMEBUFFER_RESOLUTION_Y: u32 = 256;
 
@@ -49,6 +53,22 @@ enum WindowMode {
     Obj,
 }
 
+// Points in the VIP's display and drawing processes, recorded for observers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VipEvent {
+    // FCLK rising edge
+    FrameStart,
+    // GCLK rising edge, where drawing starts (if enabled)
+    GameStart,
+    // Drawing finished the given 8-row block
+    DrawingBlockEnd(u32),
+    DrawingEnd,
+    // A pair of framebuffers was sent out as a video frame
+    Display,
+    LeftFramebufferDisplayEnd,
+    RightFramebufferDisplayEnd,
+}
+
 #[derive(Clone, Copy, Debug)]
 enum ObjGroup {
     Group0,
@@ -58,7 +78,7 @@ enum ObjGroup {
 }
 
 pub struct Vip {
-    _vram: Box<[u8]>,
+    vram: Box<[u8]>,
     vram_ptr: *mut u8,
 
     display_state: DisplayState,
@@ -120,6 +140,9 @@ pub struct Vip {
 
     display_first_framebuffers: bool,
     last_bkcol: u8,
+
+    // Only recorded while set_recording_events is on; not part of the save state
+    events: Option<Vec<VipEvent>>,
 }
 
 impl Vip {
@@ -128,7 +151,7 @@ impl Vip {
         let vram_ptr = vram.as_mut_ptr();
 
         Vip {
-            _vram: vram,
+            vram: vram,
             vram_ptr: vram_ptr,
 
             display_state: DisplayState::Idle,
@@ -190,6 +213,32 @@ impl Vip {
 
             display_first_framebuffers: false,
             last_bkcol: 0,
+
+            events: None,
+        }
+    }
+
+    pub fn is_recording_events(&self) -> bool {
+        self.events.is_some()
+    }
+
+    pub fn set_recording_events(&mut self, is_recording: bool) {
+        if is_recording != self.is_recording_events() {
+            self.events = if is_recording { Some(Vec::new()) } else { None };
+        }
+    }
+
+    // Events recorded since the last call, in order
+    pub fn take_events(&mut self) -> Vec<VipEvent> {
+        match self.events {
+            Some(ref mut events) => mem::replace(events, Vec::new()),
+            _ => Vec::new(),
+        }
+    }
+
+    fn event(&mut self, event: VipEvent) {
+        if let Some(ref mut events) = self.events {
+            events.push(event);
         }
     }
 
@@ -211,6 +260,22 @@ impl Vip {
         (if self.reg_intenb_xpend { 1 } else { 0 } << 14)
     }
 
+    // Whether anything responds at addr (within the VIP's 512KB window); everything else is open bus
+    pub fn is_mapped(addr: u32) -> bool {
+        match addr & 0xfffffffe {
+            VRAM_START ... VRAM_END |
+            CHR_RAM_PATTERN_TABLE_0_MIRROR_START ... CHR_RAM_PATTERN_TABLE_3_MIRROR_END |
+            INTPND | INTENB | INTCLR |
+            DPSTTS | DPCTRL | BRTA | BRTB | BRTC | REST | FRMCYC |
+            XPSTTS | XPCTRL |
+            SPT0 | SPT1 | SPT2 | SPT3 |
+            GPLT0 | GPLT1 | GPLT2 | GPLT3 |
+            JPLT0 | JPLT1 | JPLT2 | JPLT3 |
+            BKCOL => true,
+            _ => false,
+        }
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         let addr = addr & 0x0007ffff;
         match addr {
@@ -506,90 +571,290 @@ impl Vip {
         }
     }
 
-    pub fn cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>) -> bool {
-        for _ in 0..cycles {
-            self.display_frame_eighth_clock_counter += 1;
-            if self.display_frame_eighth_clock_counter >= DISPLAY_FRAME_EIGHTH_PERIOD {
-                self.display_frame_eighth_clock_counter = 0;
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_bytes(&self.vram);
 
-                self.display_frame_eighth_counter = match self.display_frame_eighth_counter {
-                    7 => 0,
-                    _ => self.display_frame_eighth_counter + 1
-                };
+        writer.write_u8(match self.display_state {
+            DisplayState::Idle => 0,
+            DisplayState::LeftFramebuffer => 1,
+            DisplayState::RightFramebuffer => 2,
+            DisplayState::Finished => 3,
+        });
 
-                match self.display_frame_eighth_counter {
-                    0 => {
-                        self.frame_clock();
-                    }
-                    1 => {
-                        self.display(video_frame_sink);
+        writer.write_u8(match self.drawing_state {
+            DrawingState::Idle => 0,
+            DrawingState::Drawing => 1,
+        });
 
-                        if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
-                            self.begin_left_framebuffer_display_process();
-                        }
+        writer.write_bool(self.reg_intpnd_lfbend);
+        writer.write_bool(self.reg_intpnd_rfbend);
+        writer.write_bool(self.reg_intpnd_gamestart);
+        writer.write_bool(self.reg_intpnd_framestart);
+        writer.write_bool(self.reg_intpnd_sbhit);
+        writer.write_bool(self.reg_intpnd_xpend);
+
+        writer.write_bool(self.reg_intenb_lfbend);
+        writer.write_bool(self.reg_intenb_rfbend);
+        writer.write_bool(self.reg_intenb_gamestart);
+        writer.write_bool(self.reg_intenb_framestart);
+        writer.write_bool(self.reg_intenb_sbhit);
+        writer.write_bool(self.reg_intenb_xpend);
+
+        writer.write_bool(self.reg_dpctrl_disp);
+        writer.write_bool(self.reg_dpctrl_synce);
+
+        writer.write_bool(self.reg_xpctrl_xpen);
+        writer.write_u32(self.reg_xpctrl_sbcount);
+        writer.write_u32(self.reg_xpctrl_sbcmp);
+        writer.write_bool(self.reg_xpctrl_sbout);
+
+        writer.write_u32(self.reg_frmcyc);
+
+        writer.write_u8(self.reg_brta);
+        writer.write_u8(self.reg_brtb);
+        writer.write_u8(self.reg_brtc);
+
+        writer.write_u16(self.reg_spt0);
+        writer.write_u16(self.reg_spt1);
+        writer.write_u16(self.reg_spt2);
+        writer.write_u16(self.reg_spt3);
+
+        writer.write_u8(self.reg_gplt0);
+        writer.write_u8(self.reg_gplt1);
+        writer.write_u8(self.reg_gplt2);
+        writer.write_u8(self.reg_gplt3);
+
+        writer.write_u8(self.reg_jplt0);
+        writer.write_u8(self.reg_jplt1);
+        writer.write_u8(self.reg_jplt2);
+        writer.write_u8(self.reg_jplt3);
+
+        writer.write_u8(self.reg_bkcol);
+
+        writer.write_u32(self.display_frame_eighth_clock_counter);
+        writer.write_u32(self.display_frame_eighth_counter);
+
+        writer.write_u32(self.drawing_block_counter);
+        writer.write_u32(self.drawing_sbout_counter);
+
+        writer.write_u32(self.fclk);
+
+        writer.write_bool(self.display_first_framebuffers);
+        writer.write_u8(self.last_bkcol);
+    }
+
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.vram)?;
+
+        self.display_state = match reader.read_u8()? {
+            0 => DisplayState::Idle,
+            1 => DisplayState::LeftFramebuffer,
+            2 => DisplayState::RightFramebuffer,
+            3 => DisplayState::Finished,
+            _ => return Err(SaveStateError::InvalidValue("VIP display state"))
+        };
+
+        self.drawing_state = match reader.read_u8()? {
+            0 => DrawingState::Idle,
+            1 => DrawingState::Drawing,
+            _ => return Err(SaveStateError::InvalidValue("VIP drawing state"))
+        };
+
+        self.reg_intpnd_lfbend = reader.read_bool()?;
+        self.reg_intpnd_rfbend = reader.read_bool()?;
+        self.reg_intpnd_gamestart = reader.read_bool()?;
+        self.reg_intpnd_framestart = reader.read_bool()?;
+        self.reg_intpnd_sbhit = reader.read_bool()?;
+        self.reg_intpnd_xpend = reader.read_bool()?;
+
+        self.reg_intenb_lfbend = reader.read_bool()?;
+        self.reg_intenb_rfbend = reader.read_bool()?;
+        self.reg_intenb_gamestart = reader.read_bool()?;
+        self.reg_intenb_framestart = reader.read_bool()?;
+        self.reg_intenb_sbhit = reader.read_bool()?;
+        self.reg_intenb_xpend = reader.read_bool()?;
+
+        self.reg_dpctrl_disp = reader.read_bool()?;
+        self.reg_dpctrl_synce = reader.read_bool()?;
+
+        self.reg_xpctrl_xpen = reader.read_bool()?;
+        self.reg_xpctrl_sbcount = reader.read_u32()?;
+        self.reg_xpctrl_sbcmp = reader.read_u32()?;
+        self.reg_xpctrl_sbout = reader.read_bool()?;
+        if self.reg_xpctrl_sbcount >= DRAWING_BLOCK_COUNT {
+            return Err(SaveStateError::InvalidValue("VIP SBCOUNT"));
+        }
+
+        self.reg_frmcyc = reader.read_u32()?;
+
+        self.reg_brta = reader.read_u8()?;
+        self.reg_brtb = reader.read_u8()?;
+        self.reg_brtc = reader.read_u8()?;
+
+        self.reg_spt0 = reader.read_u16()?;
+        self.reg_spt1 = reader.read_u16()?;
+        self.reg_spt2 = reader.read_u16()?;
+        self.reg_spt3 = reader.read_u16()?;
+
+        self.reg_gplt0 = reader.read_u8()?;
+        self.reg_gplt1 = reader.read_u8()?;
+        self.reg_gplt2 = reader.read_u8()?;
+        self.reg_gplt3 = reader.read_u8()?;
+
+        self.reg_jplt0 = reader.read_u8()?;
+        self.reg_jplt1 = reader.read_u8()?;
+        self.reg_jplt2 = reader.read_u8()?;
+        self.reg_jplt3 = reader.read_u8()?;
+
+        self.reg_bkcol = reader.read_u8()?;
+
+        self.display_frame_eighth_clock_counter = reader.read_u32()?;
+        self.display_frame_eighth_counter = reader.read_u32()?;
+        if self.display_frame_eighth_counter > 7 {
+            return Err(SaveStateError::InvalidValue("VIP display frame eighth counter"));
+        }
+
+        self.drawing_block_counter = reader.read_u32()?;
+        self.drawing_sbout_counter = reader.read_u32()?;
+
+        self.fclk = reader.read_u32()?;
+
+        self.display_first_framebuffers = reader.read_bool()?;
+        self.last_bkcol = reader.read_u8()?;
+
+        Ok(())
+    }
+
+    pub fn cycles(&mut self, mut cycles: u32, video_frame_sink: &mut Sink<VideoFrame>) -> bool {
+        while cycles > 0 {
+            let cycles_until_next_event = self.cycles_until_next_event();
+            if cycles < cycles_until_next_event {
+                self.catch_up(cycles);
+                break;
+            }
+
+            self.catch_up(cycles_until_next_event - 1);
+            self.cycle(video_frame_sink);
+            cycles -= cycles_until_next_event;
+        }
+
+        self.interrupt_pending()
+    }
+
+    // Always raise any pending interrupts if the corresponding interrupts are enabled
+    pub fn interrupt_pending(&self) -> bool {
+        (self.reg_intpnd() & self.reg_intenb()) != 0
+    }
+
+    pub fn cycles_until_next_event(&self) -> u32 {
+        let mut ret = cycles_until(self.display_frame_eighth_clock_counter, DISPLAY_FRAME_EIGHTH_PERIOD);
+
+        if let DrawingState::Drawing = self.drawing_state {
+            ret = ret.min(cycles_until(self.drawing_block_counter, DRAWING_BLOCK_PERIOD));
+
+            if self.reg_xpctrl_sbout {
+                ret = ret.min(cycles_until(self.drawing_sbout_counter, DRAWING_SBOUT_PERIOD));
+            }
+        }
+
+        ret
+    }
+
+    // Advances the VIP without crossing an event (cycles must be less than cycles_until_next_event)
+    pub fn catch_up(&mut self, cycles: u32) {
+        self.display_frame_eighth_clock_counter += cycles;
+
+        if let DrawingState::Drawing = self.drawing_state {
+            self.drawing_block_counter += cycles;
+
+            if self.reg_xpctrl_sbout {
+                self.drawing_sbout_counter += cycles;
+            }
+        }
+    }
+
+    fn cycle(&mut self, video_frame_sink: &mut Sink<VideoFrame>) {
+        self.display_frame_eighth_clock_counter += 1;
+        if self.display_frame_eighth_clock_counter >= DISPLAY_FRAME_EIGHTH_PERIOD {
+            self.display_frame_eighth_clock_counter = 0;
+
+            self.display_frame_eighth_counter = match self.display_frame_eighth_counter {
+                7 => 0,
+                _ => self.display_frame_eighth_counter + 1
+            };
+
+            match self.display_frame_eighth_counter {
+                0 => {
+                    self.frame_clock();
+                }
+                1 => {
+                    self.display(video_frame_sink);
+                    self.event(VipEvent::Display);
+
+                    if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
+                        self.begin_left_framebuffer_display_process();
                     }
-                    3 => {
-                        if self.reg_dpctrl_disp {
-                            if let DisplayState::LeftFramebuffer = self.display_state {
-                                self.end_left_framebuffer_display_process();
-                            }
+                }
+                3 => {
+                    if self.reg_dpctrl_disp {
+                        if let DisplayState::LeftFramebuffer = self.display_state {
+                            self.end_left_framebuffer_display_process();
                         }
                     }
-                    5 => {
-                        if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
-                            self.begin_right_framebuffer_display_process();
-                        }
+                }
+                5 => {
+                    if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
+                        self.begin_right_framebuffer_display_process();
                     }
-                    7 => {
-                        if self.reg_dpctrl_disp {
-                            if let DisplayState::RightFramebuffer = self.display_state {
-                                self.reg_intpnd_rfbend = true;
-                            }
-
-                            self.end_display_process();
+                }
+                7 => {
+                    if self.reg_dpctrl_disp {
+                        if let DisplayState::RightFramebuffer = self.display_state {
+                            self.reg_intpnd_rfbend = true;
+                            self.event(VipEvent::RightFramebufferDisplayEnd);
                         }
+
+                        self.end_display_process();
                     }
-                    _ => {}
                 }
+                _ => {}
             }
+        }
 
-            if let DrawingState::Drawing = self.drawing_state {
-                self.drawing_block_counter += 1;
-                if self.drawing_block_counter >= DRAWING_BLOCK_PERIOD {
-                    self.drawing_block_counter = 0;
+        if let DrawingState::Drawing = self.drawing_state {
+            self.drawing_block_counter += 1;
+            if self.drawing_block_counter >= DRAWING_BLOCK_PERIOD {
+                self.drawing_block_counter = 0;
 
-                    if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT {
-                        self.end_drawing_block();
+                if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT {
+                    self.end_drawing_block();
 
-                        if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT - 1 {
-                            self.reg_xpctrl_sbcount += 1;
-                            if self.reg_xpctrl_xpen {
-                                self.begin_drawing_block();
-                            }
-                        } else {
-                            self.end_drawing_process();
-                            self.reg_intpnd_xpend = true;
+                    if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT - 1 {
+                        self.reg_xpctrl_sbcount += 1;
+                        if self.reg_xpctrl_xpen {
+                            self.begin_drawing_block();
                         }
+                    } else {
+                        self.end_drawing_process();
+                        self.reg_intpnd_xpend = true;
                     }
                 }
+            }
 
-                if self.reg_xpctrl_sbout {
-                    self.drawing_sbout_counter += 1;
-                    if self.drawing_sbout_counter >= DRAWING_SBOUT_PERIOD {
-                        self.reg_xpctrl_sbout = false;
-                    }
+            if self.reg_xpctrl_sbout {
+                self.drawing_sbout_counter += 1;
+                if self.drawing_sbout_counter >= DRAWING_SBOUT_PERIOD {
+                    self.reg_xpctrl_sbout = false;
                 }
             }
         }
-
-        // Always raise any pending interrupts if the corresponding interrupts are enabled
-        (self.reg_intpnd() & self.reg_intenb()) != 0
     }
 
     fn frame_clock(&mut self) {
         logln!(Log::Vip, "Frame clock rising edge");
 
         self.reg_intpnd_framestart = true;
+        self.event(VipEvent::FrameStart);
 
         if self.reg_dpctrl_disp {
             self.begin_display_process();
@@ -606,6 +871,7 @@ impl Vip {
         logln!(Log::Vip, "Game clock rising edge");
 
         self.reg_intpnd_gamestart = true;
+        self.event(VipEvent::GameStart);
 
         if self.reg_xpctrl_xpen {
             self.display_first_framebuffers = !self.display_first_framebuffers;
@@ -635,6 +901,7 @@ impl Vip {
         logln!(Log::Vip, "End drawing block {}", self.reg_xpctrl_sbcount);
 
         self.draw_current_block();
+        self.event(VipEvent::DrawingBlockEnd(self.reg_xpctrl_sbcount));
 
         if self.reg_xpctrl_sbcount == self.reg_xpctrl_sbcmp {
             self.reg_xpctrl_sbout = true;
@@ -647,6 +914,7 @@ impl Vip {
     fn end_drawing_process(&mut self) {
         logln!(Log::Vip, "End drawing process");
         self.drawing_state = DrawingState::Idle;
+        self.event(VipEvent::DrawingEnd);
     }
 
     fn begin_display_process(&mut self) {
@@ -665,6 +933,7 @@ impl Vip {
         self.display_state = DisplayState::Idle;
 
         self.reg_intpnd_lfbend = true;
+        self.event(VipEvent::LeftFramebufferDisplayEnd);
     }
 
     fn begin_right_framebuffer_display_process(&mut self) {
@@ -1,12 +1,89 @@
 use sinks::*;
+use hooks::*;
 use rom::*;
 use sram::*;
 use interconnect::*;
 use v810::*;
+use vip::*;
+use save_state::*;
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+/// Why a call to `VirtualBoy::run_frame` or `VirtualBoy::run_cycles` returned
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    /// The VIP emitted a video frame (`run_frame` only)
+    FrameComplete,
+    /// The requested number of cycles has been emulated (`run_cycles` only)
+    CyclesComplete,
+    /// The CPU reached an address in `V810::breakpoints`
+    Breakpoint,
+    /// The CPU accessed an address in `V810::watchpoints`
+    Watchpoint,
+}
+
+/// Something the emulated program did that means it can't be emulated correctly any further. The
+///  machine is left in a consistent state (the faulting instruction completes, reading open bus
+///  from unmapped addresses), so callers can choose to carry on anyway.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmulationError {
+    /// The instruction at `pc` accessed `addr`, where nothing is mapped. In strict mode, this also
+    ///  covers addresses that only mirror something; see `Interconnect::set_strict_mode`.
+    UnmappedAddress { addr: u32, pc: u32 },
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::UnmappedAddress { addr, pc } => write!(f, "Access to unmapped address 0x{:08x} (pc: 0x{:08x})", addr, pc),
+        }
+    }
+}
+
+impl Error for EmulationError {}
+
+/// The output of a successful call to `VirtualBoy::step`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StepOutcome {
+    /// Number of cycles emulated, including any skipped while the CPU was halted
+    pub cycles: u32,
+    /// Whether the instruction accessed an address in `V810::watchpoints`
+    pub watchpoint_hit: bool,
+}
+
+/// How `VirtualBoy::run_frame` and `VirtualBoy::run_cycles` execute CPU code
+#[cfg(feature = "dynarec")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExecutionMode {
+    Interpreter,
+    /// Translates blocks of V810 code to x86-64 where possible, falling back to the interpreter otherwise
+    Dynarec,
+    /// Like `Dynarec`, but also re-runs all translated code in the interpreter and panics if the results differ
+    DynarecLockstep,
+}
+
+/// The output of a call to `VirtualBoy::run_frame` or `VirtualBoy::run_cycles`
+pub struct RunResult {
+    pub stop_reason: StopReason,
+    /// Number of cycles actually emulated; may overshoot the requested amount by the length of the last instruction
+    pub cycles: u64,
+    /// The most recent video frame emitted by the VIP, if any
+    pub video_frame: Option<VideoFrame>,
+    pub audio_frames: Vec<AudioFrame>,
+}
 
 pub struct VirtualBoy {
     pub interconnect: Interconnect,
     pub cpu: V810,
+
+    hooks: Hooks,
+
+    #[cfg(feature = "dynarec")]
+    execution_mode: ExecutionMode,
+    #[cfg(feature = "dynarec")]
+    dynarec: Dynarec,
 }
 
 impl VirtualBoy {
@@ -14,16 +91,309 @@ impl VirtualBoy {
         VirtualBoy {
             interconnect: Interconnect::new(rom, sram),
             cpu: V810::new(),
+
+            hooks: Hooks::new(),
+
+            #[cfg(feature = "dynarec")]
+            execution_mode: ExecutionMode::Dynarec,
+            #[cfg(feature = "dynarec")]
+            dynarec: Dynarec::new(),
         }
     }
 
-    pub fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
-        let ret = self.cpu.step(&mut self.interconnect);
+    #[cfg(feature = "dynarec")]
+    pub fn execution_mode(&self) -> ExecutionMode {
+        self.execution_mode
+    }
+
+    #[cfg(feature = "dynarec")]
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        self.execution_mode = execution_mode;
+    }
 
-        if let Some(exception_code) = self.interconnect.cycles(ret.0, video_frame_sink, audio_frame_sink) {
-            self.cpu.request_interrupt(exception_code);
+    /// Registers a hook that's called with the PC of each instruction just before it executes.
+    ///  Like breakpoints, this means only the interpreter can be used.
+    pub fn add_instruction_hook<F: FnMut(u32) + 'static>(&mut self, hook: F) -> HookId {
+        let id = self.hooks.next_id();
+        self.hooks.instruction.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that's called after each data access the CPU makes. Like watchpoints, this
+    ///  means only the interpreter can be used.
+    pub fn add_memory_hook<F: FnMut(&MemoryAccess) + 'static>(&mut self, hook: F) -> HookId {
+        let id = self.hooks.next_id();
+        self.hooks.memory.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that's called whenever the CPU takes an interrupt or exception, once it
+    ///  has entered the handler. Only the interpreter can be used while this is registered.
+    pub fn add_exception_hook<F: FnMut(&Exception) + 'static>(&mut self, hook: F) -> HookId {
+        // Don't report one that was taken before the hook existed
+        self.cpu.take_exception();
+        let id = self.hooks.next_id();
+        self.hooks.exception.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that's called with the VIP's display and drawing events. These are
+    ///  reported after the instruction during which they happened.
+    pub fn add_vip_hook<F: FnMut(VipEvent) + 'static>(&mut self, hook: F) -> HookId {
+        let id = self.hooks.next_id();
+        self.hooks.vip.push((id, Box::new(hook)));
+        self.interconnect.set_recording_vip_events(true);
+        id
+    }
+
+    /// Unregisters a hook, returning whether it was registered
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let ret = self.hooks.remove(id);
+        if self.hooks.vip.is_empty() {
+            self.interconnect.set_recording_vip_events(false);
         }
+        ret
+    }
+
+    /// Executes one instruction. While the CPU is halted, skips straight to the next device event
+    ///  instead, since nothing can wake the CPU up before then; the returned cycle count includes the skipped cycles.
+    pub fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<StepOutcome, EmulationError> {
+        let pc = self.cpu.reg_pc();
+        let (cycles, watchpoint_hit) = self.step_at_most(u32::MAX, video_frame_sink, audio_frame_sink);
+        self.check_unmapped_access(pc)?;
+
+        Ok(StepOutcome {
+            cycles: cycles,
+            watchpoint_hit: watchpoint_hit,
+        })
+    }
+
+    // Like step, but skips at most max_halted_cycles while halted
+    fn step_at_most(&mut self, max_halted_cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
+        let ret = if self.cpu.is_halted() {
+            (self.interconnect.cycles_until_next_event().min(max_halted_cycles).max(1), false)
+        } else {
+            if !self.hooks.instruction.is_empty() {
+                let pc = self.cpu.reg_pc();
+                for &mut (_, ref mut hook) in self.hooks.instruction.iter_mut() {
+                    hook(pc);
+                }
+            }
+
+            if self.hooks.memory.is_empty() {
+                self.cpu.step(&mut self.interconnect)
+            } else {
+                self.cpu.step(&mut ObservedBus {
+                    interconnect: &mut self.interconnect,
+                    hooks: &mut self.hooks.memory,
+                })
+            }
+        };
+        self.report_exception();
+
+        self.interconnect.cycles(ret.0, video_frame_sink, audio_frame_sink);
+        self.request_interrupt();
+        self.report_exception();
+        self.report_vip_events();
 
         ret
     }
+
+    fn report_exception(&mut self) {
+        if !self.hooks.exception.is_empty() {
+            if let Some(exception) = self.cpu.take_exception() {
+                for &mut (_, ref mut hook) in self.hooks.exception.iter_mut() {
+                    hook(&exception);
+                }
+            }
+        }
+    }
+
+    fn report_vip_events(&mut self) {
+        if !self.hooks.vip.is_empty() {
+            for event in self.interconnect.take_vip_events() {
+                for &mut (_, ref mut hook) in self.hooks.vip.iter_mut() {
+                    hook(event);
+                }
+            }
+        }
+    }
+
+    // Reports any unmapped access made by the instruction at pc
+    fn check_unmapped_access(&mut self, pc: u32) -> Result<(), EmulationError> {
+        match self.interconnect.take_unmapped_access() {
+            Some(addr) => Err(EmulationError::UnmappedAddress { addr: addr, pc: pc }),
+            _ => Ok(()),
+        }
+    }
+
+    fn request_interrupt(&mut self) {
+        if let Some(line) = self.interconnect.pending_interrupt(self.cpu.interrupt_mask_level()) {
+            if self.cpu.request_interrupt(line.exception_code()) {
+                self.interconnect.acknowledge_interrupt(line);
+            }
+        }
+    }
+
+    // Like step, but runs translated blocks (for at most max_cycles) instead of a single instruction
+    //  when possible
+    #[cfg(feature = "dynarec")]
+    fn run_step(&mut self, max_cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        // Translated blocks don't check breakpoints/watchpoints, so only the interpreter can be used while any are set
+        let can_run_block =
+            self.execution_mode != ExecutionMode::Interpreter &&
+            !self.cpu.is_halted() &&
+            self.cpu.breakpoints.is_empty() &&
+            self.cpu.watchpoints.is_empty() &&
+            !self.hooks.require_interpreter();
+        if !can_run_block {
+            return self.interpret_step(max_cycles, video_frame_sink, audio_frame_sink);
+        }
+
+        let lockstep_state = match self.execution_mode {
+            ExecutionMode::DynarecLockstep => Some(self.save_state()),
+            _ => None,
+        };
+
+        let block_pc = self.cpu.reg_pc();
+
+        // Blocks never run past the next device event, so devices only have to be run once they're done
+        let (cycles, instructions) = match self.dynarec.run_blocks(&mut self.cpu, &mut self.interconnect, max_cycles) {
+            Some(ret) => ret,
+            _ => return self.interpret_step(max_cycles, video_frame_sink, audio_frame_sink),
+        };
+        let unmapped_access = self.dynarec.take_unmapped_access();
+
+        if let Some(lockstep_state) = lockstep_state {
+            // Any frames (and VIP events) emitted after the block are emitted again by the interpreter below
+            self.interconnect.cycles(0, &mut Vec::new(), &mut Vec::new());
+            self.interconnect.take_vip_events();
+            self.request_interrupt();
+            let translated_state = self.save_state();
+
+            self.load_state(&lockstep_state).unwrap();
+            let mut interpreted_cycles = 0;
+            let mut interpreted_unmapped_access = None;
+            for _ in 0..instructions {
+                let pc = self.cpu.reg_pc();
+                interpreted_cycles += self.step_at_most(u32::MAX, video_frame_sink, audio_frame_sink).0;
+                if let Some(addr) = self.interconnect.take_unmapped_access() {
+                    interpreted_unmapped_access = interpreted_unmapped_access.or(Some((addr, pc)));
+                }
+            }
+
+            if interpreted_cycles != cycles || self.save_state() != translated_state || interpreted_unmapped_access != unmapped_access {
+                panic!("Dynarec lockstep mismatch in blocks starting at 0x{:08x} ({} instructions, {} cycles translated, {} cycles interpreted)", block_pc, instructions, cycles, interpreted_cycles);
+            }
+        } else {
+            self.interconnect.cycles(0, video_frame_sink, audio_frame_sink);
+            self.request_interrupt();
+            self.report_vip_events();
+        }
+
+        match unmapped_access {
+            Some((addr, pc)) => Err(EmulationError::UnmappedAddress { addr: addr, pc: pc }),
+            _ => Ok((cycles, false)),
+        }
+    }
+
+    #[cfg(not(feature = "dynarec"))]
+    fn run_step(&mut self, max_cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        self.interpret_step(max_cycles, video_frame_sink, audio_frame_sink)
+    }
+
+    fn interpret_step(&mut self, max_cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        let pc = self.cpu.reg_pc();
+        let ret = self.step_at_most(max_cycles, video_frame_sink, audio_frame_sink);
+        self.check_unmapped_access(pc)?;
+        Ok(ret)
+    }
+
+    /// Runs until the VIP emits the next video frame, or a breakpoint/watchpoint is hit. Stops early
+    ///  with an error if the emulated program faults; frames emitted up to that point are dropped.
+    pub fn run_frame(&mut self) -> Result<RunResult, EmulationError> {
+        self.run(None)
+    }
+
+    /// Runs for (at least) the given number of cycles, or until a breakpoint/watchpoint is hit. Stops
+    ///  early with an error if the emulated program faults, like `run_frame`.
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<RunResult, EmulationError> {
+        self.run(Some(cycles))
+    }
+
+    fn run(&mut self, target_cycles: Option<u64>) -> Result<RunResult, EmulationError> {
+        let mut video_frames = Vec::new();
+        let mut audio_frames = Vec::new();
+        let mut cycles = 0;
+
+        let stop_reason = loop {
+            let mut max_cycles = u32::MAX;
+            if let Some(target_cycles) = target_cycles {
+                if cycles >= target_cycles {
+                    break StopReason::CyclesComplete;
+                }
+
+                max_cycles = (target_cycles - cycles).min(u32::MAX as u64) as u32;
+            }
+
+            let (step_cycles, trigger_watchpoint) = self.run_step(max_cycles, &mut video_frames, &mut audio_frames)?;
+            cycles += step_cycles as u64;
+
+            if trigger_watchpoint {
+                break StopReason::Watchpoint;
+            }
+
+            if !self.cpu.breakpoints.is_empty() && self.cpu.breakpoints.contains(&self.cpu.reg_pc()) {
+                break StopReason::Breakpoint;
+            }
+
+            if target_cycles.is_none() && !video_frames.is_empty() {
+                break StopReason::FrameComplete;
+            }
+        };
+
+        Ok(RunResult {
+            stop_reason: stop_reason,
+            cycles: cycles,
+            video_frame: video_frames.pop(),
+            audio_frames: audio_frames,
+        })
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = SaveStateWriter::new();
+
+        writer.write_bytes(SAVE_STATE_MAGIC);
+        writer.write_u32(SAVE_STATE_VERSION);
+
+        self.cpu.save_state(&mut writer);
+        self.interconnect.save_state(&mut writer);
+
+        writer.into_bytes()
+    }
+
+    // Either restores the entire machine or leaves it exactly as it was
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = SaveStateReader::new(bytes);
+
+        if reader.read_bytes(SAVE_STATE_MAGIC.len()).map_err(|_| SaveStateError::InvalidMagic)? != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+        let version = reader.read_u32()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let mut cpu = V810::new();
+        cpu.load_state(&mut reader)?;
+        let interconnect_state = self.interconnect.read_state(&mut reader)?;
+        reader.finish()?;
+
+        mem::swap(&mut cpu.breakpoints, &mut self.cpu.breakpoints);
+        mem::swap(&mut cpu.watchpoints, &mut self.cpu.watchpoints);
+        self.cpu = cpu;
+        self.interconnect.restore_state(interconnect_state);
+
+        Ok(())
+    }
 }
@@ -1,6 +1,8 @@
 mod mem_map;
 
 use sinks::*;
+use save_state::*;
+use scheduler::*;
 
 use self::mem_map::*;
 
@@ -64,6 +66,22 @@ impl IntReg {
             }
         }
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_bool(self.output_enable);
+        writer.write_bool(self.interval_data);
+        writer.write_u32(self.interval_counter_setting_values);
+        writer.write_u32(self.interval_counter);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.output_enable = reader.read_bool()?;
+        self.interval_data = reader.read_bool()?;
+        self.interval_counter_setting_values = reader.read_u32()?;
+        self.interval_counter = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 #[derive(Default)]
@@ -77,6 +95,21 @@ impl LrvReg {
         self.left = (value >> 4) as _;
         self.right = (value & 0x0f) as _;
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u32(self.left);
+        writer.write_u32(self.right);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.left = reader.read_u32()?;
+        self.right = reader.read_u32()?;
+        if self.left > 0x0f || self.right > 0x0f {
+            return Err(SaveStateError::InvalidValue("VSU LRV"));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Default)]
@@ -127,6 +160,31 @@ impl Envelope {
             }
         }
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_u32(self.reg_data_reload);
+        writer.write_bool(self.reg_data_direction);
+        writer.write_u32(self.reg_data_step_interval);
+        writer.write_bool(self.reg_control_repeat);
+        writer.write_bool(self.reg_control_enable);
+        writer.write_u32(self.level);
+        writer.write_u32(self.envelope_counter);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.reg_data_reload = reader.read_u32()?;
+        self.reg_data_direction = reader.read_bool()?;
+        self.reg_data_step_interval = reader.read_u32()?;
+        self.reg_control_repeat = reader.read_bool()?;
+        self.reg_control_enable = reader.read_bool()?;
+        self.level = reader.read_u32()?;
+        if self.reg_data_reload > 0x0f || self.level > 0x0f {
+            return Err(SaveStateError::InvalidValue("VSU envelope level"));
+        }
+        self.envelope_counter = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 trait Sound {
@@ -197,6 +255,12 @@ impl StandardSound {
         }
     }
 
+    fn frequency_clocks(&mut self, clocks: u32) {
+        let period = 2048 - ((self.fqh << 8) | self.fql);
+        let steps = advance_counter(&mut self.frequency_counter, period, clocks);
+        self.phase = (self.phase + steps) & (NUM_WAVEFORM_DATA_WORDS - 1);
+    }
+
     fn output(&self, waveform_data: &[u8]) -> u32 {
         if self.ram > 4 {
             return 0;
@@ -204,6 +268,36 @@ impl StandardSound {
 
         waveform_data[(self.ram * NUM_WAVEFORM_DATA_WORDS + self.phase) as usize] as _
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        self.reg_int.save_state(writer);
+        self.reg_lrv.save_state(writer);
+        writer.write_u32(self.fql);
+        writer.write_u32(self.fqh);
+        self.envelope.save_state(writer);
+        writer.write_u32(self.ram);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.phase);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.reg_int.load_state(reader)?;
+        self.reg_lrv.load_state(reader)?;
+        self.fql = reader.read_u32()?;
+        self.fqh = reader.read_u32()?;
+        if self.fql > 0xff || self.fqh > 0x07 {
+            return Err(SaveStateError::InvalidValue("VSU frequency"));
+        }
+        self.envelope.load_state(reader)?;
+        self.ram = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.phase = reader.read_u32()?;
+        if self.phase >= NUM_WAVEFORM_DATA_WORDS {
+            return Err(SaveStateError::InvalidValue("VSU waveform phase"));
+        }
+
+        Ok(())
+    }
 }
 
 impl Sound for StandardSound {
@@ -312,6 +406,12 @@ impl SweepModSound {
         }
     }
 
+    fn frequency_clocks(&mut self, clocks: u32) {
+        let period = 2048 - ((self.frequency_high << 8) | self.frequency_low);
+        let steps = advance_counter(&mut self.frequency_counter, period, clocks);
+        self.phase = (self.phase + steps) & (NUM_WAVEFORM_DATA_WORDS - 1);
+    }
+
     fn sweep_mod_clock(&mut self, mod_data: &[i8]) {
         self.sweep_mod_counter += 1;
         if self.sweep_mod_counter >= self.reg_sweep_mod_interval {
@@ -364,6 +464,71 @@ impl SweepModSound {
 
         waveform_data[(self.ram * NUM_WAVEFORM_DATA_WORDS + self.phase) as usize] as _
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        self.reg_int.save_state(writer);
+        self.reg_lrv.save_state(writer);
+        writer.write_u32(self.fql);
+        writer.write_u32(self.fqh);
+        writer.write_u32(self.frequency_low);
+        writer.write_u32(self.frequency_high);
+        writer.write_u32(self.next_frequency_low);
+        writer.write_u32(self.next_frequency_high);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.reg_sweep_mod_enable);
+        writer.write_bool(self.reg_mod_repeat);
+        writer.write_bool(self.reg_function);
+        writer.write_bool(self.reg_sweep_mod_base_interval);
+        writer.write_u32(self.reg_sweep_mod_interval);
+        writer.write_bool(self.reg_sweep_direction);
+        writer.write_u32(self.reg_sweep_shift_amount);
+        writer.write_u32(self.ram);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.phase);
+        writer.write_u32(self.sweep_mod_counter);
+        writer.write_u32(self.mod_phase);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.reg_int.load_state(reader)?;
+        self.reg_lrv.load_state(reader)?;
+        self.fql = reader.read_u32()?;
+        self.fqh = reader.read_u32()?;
+        if self.fql > 0xff || self.fqh > 0x07 {
+            return Err(SaveStateError::InvalidValue("VSU frequency"));
+        }
+        self.frequency_low = reader.read_u32()?;
+        self.frequency_high = reader.read_u32()?;
+        self.next_frequency_low = reader.read_u32()?;
+        self.next_frequency_high = reader.read_u32()?;
+        if self.frequency_low > 0xff || self.frequency_high > 0x07 || self.next_frequency_low > 0xff || self.next_frequency_high > 0x07 {
+            return Err(SaveStateError::InvalidValue("VSU sweep/mod frequency"));
+        }
+        self.envelope.load_state(reader)?;
+        self.reg_sweep_mod_enable = reader.read_bool()?;
+        self.reg_mod_repeat = reader.read_bool()?;
+        self.reg_function = reader.read_bool()?;
+        self.reg_sweep_mod_base_interval = reader.read_bool()?;
+        self.reg_sweep_mod_interval = reader.read_u32()?;
+        self.reg_sweep_direction = reader.read_bool()?;
+        self.reg_sweep_shift_amount = reader.read_u32()?;
+        if self.reg_sweep_mod_interval > 0x07 || self.reg_sweep_shift_amount > 0x07 {
+            return Err(SaveStateError::InvalidValue("VSU sweep/mod settings"));
+        }
+        self.ram = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.phase = reader.read_u32()?;
+        if self.phase >= NUM_WAVEFORM_DATA_WORDS {
+            return Err(SaveStateError::InvalidValue("VSU waveform phase"));
+        }
+        self.sweep_mod_counter = reader.read_u32()?;
+        self.mod_phase = reader.read_u32()?;
+        if self.mod_phase >= NUM_MOD_DATA_WORDS {
+            return Err(SaveStateError::InvalidValue("VSU mod phase"));
+        }
+
+        Ok(())
+    }
 }
 
 impl Sound for SweepModSound {
@@ -465,6 +630,35 @@ impl NoiseSound {
     fn output(&self) -> u32 {
         self.output
     }
+
+    fn save_state(&self, writer: &mut SaveStateWriter) {
+        self.reg_int.save_state(writer);
+        self.reg_lrv.save_state(writer);
+        writer.write_u32(self.fql);
+        writer.write_u32(self.fqh);
+        self.envelope.save_state(writer);
+        writer.write_u32(self.reg_noise_control);
+        writer.write_u32(self.frequency_counter);
+        writer.write_u32(self.shift);
+        writer.write_u32(self.output);
+    }
+
+    fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        self.reg_int.load_state(reader)?;
+        self.reg_lrv.load_state(reader)?;
+        self.fql = reader.read_u32()?;
+        self.fqh = reader.read_u32()?;
+        if self.fql > 0xff || self.fqh > 0x07 {
+            return Err(SaveStateError::InvalidValue("VSU frequency"));
+        }
+        self.envelope.load_state(reader)?;
+        self.reg_noise_control = reader.read_u32()?;
+        self.frequency_counter = reader.read_u32()?;
+        self.shift = reader.read_u32()?;
+        self.output = reader.read_u32()?;
+
+        Ok(())
+    }
 }
 
 impl Sound for NoiseSound {
@@ -522,10 +716,64 @@ impl Vsu {
         }
     }
 
-    pub fn read_byte(&self, addr: u32) -> u8 {
-        logln!(Log::Vsu, "WARNING: Attempted read byte from VSU (addr: 0x{:08x})", addr);
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_bytes(&self.waveform_data);
+        for &value in self.mod_data.iter() {
+            writer.write_u8(value as _);
+        }
+
+        self.sound1.save_state(writer);
+        self.sound2.save_state(writer);
+        self.sound3.save_state(writer);
+        self.sound4.save_state(writer);
+        self.sound5.save_state(writer);
+        self.sound6.save_state(writer);
+
+        writer.write_u32(self.duration_clock_counter);
+        writer.write_u32(self.envelope_clock_counter);
+        writer.write_u32(self.frequency_clock_counter);
+        writer.write_u32(self.sweep_mod_clock_counter);
+        writer.write_u32(self.noise_clock_counter);
+        writer.write_u32(self.sample_clock_counter);
+    }
 
-        0
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.waveform_data)?;
+        for value in self.mod_data.iter_mut() {
+            *value = reader.read_u8()? as _;
+        }
+
+        self.sound1.load_state(reader)?;
+        self.sound2.load_state(reader)?;
+        self.sound3.load_state(reader)?;
+        self.sound4.load_state(reader)?;
+        self.sound5.load_state(reader)?;
+        self.sound6.load_state(reader)?;
+
+        self.duration_clock_counter = reader.read_u32()?;
+        self.envelope_clock_counter = reader.read_u32()?;
+        self.frequency_clock_counter = reader.read_u32()?;
+        self.sweep_mod_clock_counter = reader.read_u32()?;
+        self.noise_clock_counter = reader.read_u32()?;
+        self.sample_clock_counter = reader.read_u32()?;
+
+        Ok(())
+    }
+
+    // Whether anything responds at addr (within the VSU's 2KB window). All of it is write-only, so
+    //  even mapped addresses read back as open bus.
+    pub fn is_mapped(addr: u32) -> bool {
+        match addr {
+            WAVEFORM_DATA_0_START ... MOD_DATA_END |
+            S1INT | S1LRV | S1FQL | S1FQH | S1EV0 | S1EV1 | S1RAM |
+            S2INT | S2LRV | S2FQL | S2FQH | S2EV0 | S2EV1 | S2RAM |
+            S3INT | S3LRV | S3FQL | S3FQH | S3EV0 | S3EV1 | S3RAM |
+            S4INT | S4LRV | S4FQL | S4FQH | S4EV0 | S4EV1 | S4RAM |
+            S5INT | S5LRV | S5FQL | S5FQH | S5EV0 | S5EV1 | S5RAM | S5SWP |
+            S6INT | S6LRV | S6FQL | S6FQH | S6EV0 | S6EV1 |
+            SSTOP => true,
+            _ => false,
+        }
     }
 
     pub fn write_byte(&mut self, addr: u32, value: u8) {
@@ -616,78 +864,119 @@ impl Vsu {
         }
     }
 
-    pub fn read_halfword(&self, addr: u32) -> u16 {
-        logln!(Log::Vsu, "WARNING: Attempted read halfword from VSU (addr: 0x{:08x})", addr);
-
-        0
-    }
-
     pub fn write_halfword(&mut self, addr: u32, value: u16) {
         let addr = addr & 0xfffffffe;
         self.write_byte(addr, value as _);
     }
 
-    pub fn cycles(&mut self, num_cycles: u32, audio_frame_sink: &mut Sink<AudioFrame>) {
-        for _ in 0..num_cycles {
-            self.duration_clock_counter += 1;
-            if self.duration_clock_counter >= DURATION_CLOCK_PERIOD {
-                self.duration_clock_counter = 0;
-
-                self.sound1.reg_int.duration_clock();
-                self.sound2.reg_int.duration_clock();
-                self.sound3.reg_int.duration_clock();
-                self.sound4.reg_int.duration_clock();
-                self.sound5.reg_int.duration_clock();
-                self.sound6.reg_int.duration_clock();
+    pub fn cycles(&mut self, mut num_cycles: u32, audio_frame_sink: &mut Sink<AudioFrame>) {
+        while num_cycles > 0 {
+            let cycles_until_next_event = self.cycles_until_next_event();
+            if num_cycles < cycles_until_next_event {
+                self.catch_up(num_cycles);
+                break;
             }
 
-            self.envelope_clock_counter += 1;
-            if self.envelope_clock_counter >= ENVELOPE_CLOCK_PERIOD {
-                self.envelope_clock_counter = 0;
+            self.catch_up(cycles_until_next_event - 1);
+            self.cycle(audio_frame_sink);
+            num_cycles -= cycles_until_next_event;
+        }
+    }
 
-                self.sound1.envelope.envelope_clock();
-                self.sound2.envelope.envelope_clock();
-                self.sound3.envelope.envelope_clock();
-                self.sound4.envelope.envelope_clock();
-                self.sound5.envelope.envelope_clock();
-                self.sound6.envelope.envelope_clock();
-            }
+    // Frequency and noise clocks only affect their own channel, so they're handled in bulk
+    //  rather than treated as events
+    pub fn cycles_until_next_event(&self) -> u32 {
+        cycles_until(self.duration_clock_counter, DURATION_CLOCK_PERIOD)
+            .min(cycles_until(self.envelope_clock_counter, ENVELOPE_CLOCK_PERIOD))
+            .min(cycles_until(self.sweep_mod_clock_counter, self.sweep_mod_clock_period()))
+            .min(cycles_until(self.sample_clock_counter, SAMPLE_CLOCK_PERIOD))
+    }
 
-            self.frequency_clock_counter += 1;
-            if self.frequency_clock_counter >= FREQUENCY_CLOCK_PERIOD {
-                self.frequency_clock_counter = 0;
+    // Advances the VSU without crossing an event (num_cycles must be less than cycles_until_next_event)
+    pub fn catch_up(&mut self, num_cycles: u32) {
+        self.duration_clock_counter += num_cycles;
+        self.envelope_clock_counter += num_cycles;
 
-                self.sound1.frequency_clock();
-                self.sound2.frequency_clock();
-                self.sound3.frequency_clock();
-                self.sound4.frequency_clock();
-                self.sound5.frequency_clock();
-            }
+        let frequency_clocks = advance_counter(&mut self.frequency_clock_counter, FREQUENCY_CLOCK_PERIOD, num_cycles);
+        self.sound1.frequency_clocks(frequency_clocks);
+        self.sound2.frequency_clocks(frequency_clocks);
+        self.sound3.frequency_clocks(frequency_clocks);
+        self.sound4.frequency_clocks(frequency_clocks);
+        self.sound5.frequency_clocks(frequency_clocks);
 
-            self.sweep_mod_clock_counter += 1;
-            let sweep_mod_clock_period = match self.sound5.reg_sweep_mod_base_interval {
-                false => SWEEP_MOD_SMALL_PERIOD,
-                true => SWEEP_MOD_LARGE_PERIOD
-            };
-            if self.sweep_mod_clock_counter >= sweep_mod_clock_period {
-                self.sweep_mod_clock_counter = 0;
+        self.sweep_mod_clock_counter += num_cycles;
 
-                self.sound5.sweep_mod_clock(&self.mod_data);
-            }
+        let noise_clocks = advance_counter(&mut self.noise_clock_counter, NOISE_CLOCK_PERIOD, num_cycles);
+        for _ in 0..noise_clocks {
+            self.sound6.noise_clock();
+        }
 
-            self.noise_clock_counter += 1;
-            if self.noise_clock_counter >= NOISE_CLOCK_PERIOD {
-                self.noise_clock_counter = 0;
+        self.sample_clock_counter += num_cycles;
+    }
 
-                self.sound6.noise_clock();
-            }
+    fn sweep_mod_clock_period(&self) -> u32 {
+        match self.sound5.reg_sweep_mod_base_interval {
+            false => SWEEP_MOD_SMALL_PERIOD,
+            true => SWEEP_MOD_LARGE_PERIOD
+        }
+    }
 
-            self.sample_clock_counter += 1;
-            if self.sample_clock_counter >= SAMPLE_CLOCK_PERIOD {
-                self.sample_clock_counter = 0;
+    fn cycle(&mut self, audio_frame_sink: &mut Sink<AudioFrame>) {
+        self.duration_clock_counter += 1;
+        if self.duration_clock_counter >= DURATION_CLOCK_PERIOD {
+            self.duration_clock_counter = 0;
+
+            self.sound1.reg_int.duration_clock();
+            self.sound2.reg_int.duration_clock();
+            self.sound3.reg_int.duration_clock();
+            self.sound4.reg_int.duration_clock();
+            self.sound5.reg_int.duration_clock();
+            self.sound6.reg_int.duration_clock();
+        }
 
-                self.sample_clock(audio_frame_sink);
-            }
+        self.envelope_clock_counter += 1;
+        if self.envelope_clock_counter >= ENVELOPE_CLOCK_PERIOD {
+            self.envelope_clock_counter = 0;
+
+            self.sound1.envelope.envelope_clock();
+            self.sound2.envelope.envelope_clock();
+            self.sound3.envelope.envelope_clock();
+            self.sound4.envelope.envelope_clock();
+            self.sound5.envelope.envelope_clock();
+            self.sound6.envelope.envelope_clock();
+        }
+
+        self.frequency_clock_counter += 1;
+        if self.frequency_clock_counter >= FREQUENCY_CLOCK_PERIOD {
+            self.frequency_clock_counter = 0;
+
+            self.sound1.frequency_clock();
+            self.sound2.frequency_clock();
+            self.sound3.frequency_clock();
+            self.sound4.frequency_clock();
+            self.sound5.frequency_clock();
+        }
+
+        self.sweep_mod_clock_counter += 1;
+        let sweep_mod_clock_period = self.sweep_mod_clock_period();
+        if self.sweep_mod_clock_counter >= sweep_mod_clock_period {
+            self.sweep_mod_clock_counter = 0;
+
+            self.sound5.sweep_mod_clock(&self.mod_data);
+        }
+
+        self.noise_clock_counter += 1;
+        if self.noise_clock_counter >= NOISE_CLOCK_PERIOD {
+            self.noise_clock_counter = 0;
+
+            self.sound6.noise_clock();
+        }
+
+        self.sample_clock_counter += 1;
+        if self.sample_clock_counter >= SAMPLE_CLOCK_PERIOD {
+            self.sample_clock_counter = 0;
+
+            self.sample_clock(audio_frame_sink);
         }
     }
 
@@ -1,7 +1,9 @@
+use save_state::*;
+
 pub const WRAM_SIZE: usize = 65536;
 
 pub struct Wram {
-    _bytes: Box<[u8]>,
+    bytes: Box<[u8]>,
     bytes_ptr: *mut u8,
 }
 
@@ -11,11 +13,15 @@ impl Wram {
         let bytes_ptr = bytes.as_mut_ptr();
 
         Wram {
-            _bytes: bytes,
+            bytes: bytes,
             bytes_ptr: bytes_ptr,
         }
     }
 
+    pub(crate) fn bytes_ptr(&self) -> *mut u8 {
+        self.bytes_ptr
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         let addr = self.mask_addr(addr);
         unsafe {
@@ -48,6 +54,14 @@ impl Wram {
         }
     }
 
+    pub fn save_state(&self, writer: &mut SaveStateWriter) {
+        writer.write_bytes(&self.bytes);
+    }
+
+    pub fn load_state(&mut self, reader: &mut SaveStateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.bytes)
+    }
+
     fn mask_addr(&self, addr: u32) -> u32 {
         let mask = (WRAM_SIZE - 1) as u32;
         addr & mask
@@ -5,6 +5,7 @@ mod color_frame;
 mod anaglyphizer;
 mod gamma_adjust_sink;
 mod most_recent_sink;
+mod rewind_buffer;
 
 // reexports
 pub use color::Color;
@@ -12,3 +13,4 @@ pub use color_frame::ColorFrame;
 pub use anaglyphizer::Anaglyphizer;
 pub use gamma_adjust_sink::GammaAdjustSink;
 pub use most_recent_sink::MostRecentSink;
+pub use rewind_buffer::RewindBuffer;
@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+
+/// A bounded history of machine snapshots (as produced by `VirtualBoy::save_state`)
+///
+/// Only the most recent snapshot is kept in full. Each older snapshot is stored as a
+/// delta against the snapshot that followed it: the two are XORed together and the
+/// result is run-length encoded, which is very compact since most of the machine
+/// state (VRAM, WRAM) doesn't change from one snapshot to the next. When the memory
+/// budget is exceeded, the oldest snapshots are discarded first.
+pub struct RewindBuffer {
+    budget: usize,
+
+    latest: Option<Vec<u8>>,
+    // Ordered oldest to newest; deltas[i] turns snapshot i + 1 back into snapshot i
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl RewindBuffer {
+    /// Creates an empty buffer that uses at most `budget` bytes
+    pub fn new(budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            budget: budget,
+
+            latest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    /// Records a new snapshot
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if snapshot.len() > self.budget {
+            self.clear();
+            return;
+        }
+
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&previous, &snapshot);
+            self.deltas_size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(snapshot);
+
+        while self.memory_usage() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Discards the most recent snapshot and returns the one before it. When only one
+    /// snapshot is left, it's returned without being discarded, so holding rewind stays
+    /// on the oldest snapshot rather than running out.
+    pub fn rewind(&mut self) -> Option<&[u8]> {
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.len();
+
+            let latest = self.latest.take().unwrap();
+            self.latest = Some(decode_delta(&latest, &delta));
+        }
+
+        self.latest.as_ref().map(|snapshot| &snapshot[..])
+    }
+
+    /// Returns the number of snapshots currently held
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Returns the number of bytes used to store the snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, |snapshot| snapshot.len()) + self.deltas_size
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+}
+
+// Delta layout: target length, followed by (zero run length, literal length, literal bytes)
+//  runs, where the encoded bytes are the target XORed with the base (padded with zeroes).
+fn encode_delta(target: &[u8], base: &[u8]) -> Vec<u8> {
+    let xor = |index: usize| target[index] ^ base.get(index).cloned().unwrap_or(0);
+
+    let mut ret = Vec::new();
+    write_varint(&mut ret, target.len());
+
+    let mut index = 0;
+    while index < target.len() {
+        let zeroes_start = index;
+        while index < target.len() && xor(index) == 0 {
+            index += 1;
+        }
+        let zeroes = index - zeroes_start;
+
+        // Short zero runs are cheaper to store as part of the literal
+        let literal_start = index;
+        while index < target.len() && (xor(index) != 0 || (index + 1 < target.len() && xor(index + 1) != 0)) {
+            index += 1;
+        }
+
+        write_varint(&mut ret, zeroes);
+        write_varint(&mut ret, index - literal_start);
+        for literal_index in literal_start..index {
+            ret.push(xor(literal_index));
+        }
+    }
+
+    ret
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+
+    let mut ret: Vec<u8> = (0..len).map(|index| base.get(index).cloned().unwrap_or(0)).collect();
+
+    let mut index = 0;
+    while index < len {
+        index += read_varint(delta, &mut pos);
+        let literal_len = read_varint(delta, &mut pos);
+        for &byte in &delta[pos..pos + literal_len] {
+            ret[index] ^= byte;
+            index += 1;
+        }
+        pos += literal_len;
+    }
+
+    ret
+}
+
+fn write_varint(vec: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        vec.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    vec.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        ret |= ((byte & 0x7f) as usize) << shift;
+        if (byte & 0x80) == 0 {
+            return ret;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic filler, so snapshots differ without pulling in a random number generator
+    fn snapshot(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(2654435761).wrapping_add(1);
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn round_trip(target: &[u8], base: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(target, base);
+        let decoded = decode_delta(base, &delta);
+        assert_eq!(decoded, target);
+        delta
+    }
+
+    #[test]
+    fn identical_states() {
+        let state = snapshot(4096, 0);
+        let delta = round_trip(&state, &state);
+        // Length, then a single zero run with no literal
+        assert_eq!(delta.len(), 5);
+        round_trip(&[], &[]);
+    }
+
+    #[test]
+    fn different_lengths() {
+        let long = snapshot(1000, 1);
+        let short = snapshot(300, 2);
+        round_trip(&long, &short);
+        round_trip(&short, &long);
+        round_trip(&long[..700], &long);
+        round_trip(&long, &long[..700]);
+        round_trip(&long, &[]);
+        round_trip(&[], &long);
+    }
+
+    #[test]
+    fn long_zero_runs() {
+        let base = snapshot(1 << 20, 3);
+        let mut target = base.clone();
+        target[0] ^= 0x01;
+        target[1] ^= 0x80;
+        target[3] ^= 0xff;
+        target[200000] ^= 0x10;
+        target[(1 << 20) - 1] ^= 0x42;
+        let delta = round_trip(&target, &base);
+        assert!(delta.len() < 32);
+
+        // Zeroes in the state itself shouldn't matter, only differences
+        let zeroes = vec![0; 100000];
+        let mut target = zeroes.clone();
+        target[50000] = 1;
+        round_trip(&target, &zeroes);
+        round_trip(&zeroes, &target);
+    }
+
+    #[test]
+    fn rewind_in_order() {
+        let snapshots: Vec<Vec<u8>> = (0..8).map(|seed| {
+            let mut state = snapshot(2048, 4);
+            state[seed * 100] = seed as u8;
+            state.truncate(2000 + seed);
+            state
+        }).collect();
+
+        let mut buffer = RewindBuffer::new(1 << 20);
+        assert_eq!(buffer.rewind(), None);
+        for state in snapshots.iter() {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), snapshots.len());
+
+        for state in snapshots.iter().rev().skip(1) {
+            assert_eq!(buffer.rewind(), Some(&state[..]));
+        }
+        assert_eq!(buffer.len(), 1);
+
+        // The oldest snapshot stays put
+        assert_eq!(buffer.rewind(), Some(&snapshots[0][..]));
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn budget_evicts_oldest() {
+        // Unrelated snapshots, so each delta is about as big as a snapshot
+        let snapshots: Vec<Vec<u8>> = (0..10).map(|seed| snapshot(1000, seed + 10)).collect();
+
+        let mut buffer = RewindBuffer::new(4500);
+        for state in snapshots.iter() {
+            buffer.push(state.clone());
+            assert!(buffer.memory_usage() <= 4500);
+        }
+        let len = buffer.len();
+        assert!(len > 1 && len < snapshots.len());
+
+        // What's left is the most recent snapshots, still in order
+        for state in snapshots.iter().rev().skip(1).take(len - 1) {
+            assert_eq!(buffer.rewind(), Some(&state[..]));
+        }
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.rewind(), Some(&snapshots[snapshots.len() - len][..]));
+
+        // A snapshot that can't fit at all empties the buffer
+        buffer.push(snapshot(5000, 0));
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.memory_usage(), 0);
+    }
+}